@@ -40,7 +40,7 @@ fn main() -> Result<(), Error> {
     for (url, exposure) in image_urls {
         let file_name = url
             .split('/')
-            .last()
+            .next_back()
             .expect("Expected filename as last component in url");
 
         if std::path::Path::exists(file_name.as_ref()) {
@@ -78,7 +78,7 @@ fn main() -> Result<(), Error> {
 
     println!("Mergin images...");
     let hdr_merged = image_hdr::hdr_merge_images(&mut images.into())?;
-    let stretched = apply_histogram_stretch(&hdr_merged)?;
+    let stretched = apply_histogram_stretch(&hdr_merged.into())?;
 
     println!("Saving merged image...");
     stretched
@@ -0,0 +1,372 @@
+//! Alignment of hand held brackets using Median Threshold Bitmaps, as introduced in
+//! [Fast, Robust Image Registration for Compositing High Dynamic Range Photographs from Hand-Held Exposures](https://www.anyhere.com/gward/papers/jgtpap2.pdf)
+//!
+//! Each frame is reduced to a bitmap of pixels brighter than its median, which is largely
+//! independent of exposure. The bitmaps are compared against the reference frame over an image
+//! pyramid to find the integer translation that minimises their difference.
+
+use crate::input::{validate_override_length, HDRInputList};
+use crate::parallel::prelude::*;
+use crate::tonemap::luminance;
+use crate::Error;
+use ndarray::{Array2, Array3, Axis, Zip};
+
+/// Default number of pyramid levels to search. Shifts of up to `2^bits - 1` pixels in each
+/// direction can be detected.
+pub const DEFAULT_MAX_SHIFT_BITS: u32 = 6;
+
+/// Pixels this close to the median are ignored when comparing bitmaps since they are mostly noise.
+const EXCLUSION_TOLERANCE: f32 = 4. / 255.;
+
+/// Smallest dimension of the coarsest pyramid level.
+const MIN_PYRAMID_SIZE: usize = 16;
+
+/// Integer translation, in pixels, that is applied to a frame to align it with the reference.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Shift {
+    /// Horizontal shift. Positive values move the frame to the right.
+    pub x: i32,
+    /// Vertical shift. Positive values move the frame down.
+    pub y: i32,
+}
+
+/// Threshold and exclusion bitmaps of a single pyramid level.
+struct Bitmaps {
+    threshold: Array2<bool>,
+    exclusion: Array2<bool>,
+}
+
+impl Bitmaps {
+    fn new(gray: &Array2<f32>) -> Self {
+        let mut values = gray.iter().copied().collect::<Vec<f32>>();
+        let middle = values.len() / 2;
+        let median = *values.select_nth_unstable_by(middle, f32::total_cmp).1;
+
+        Self {
+            threshold: gray.mapv(|value| value > median),
+            exclusion: gray.mapv(|value| (value - median).abs() > EXCLUSION_TOLERANCE),
+        }
+    }
+
+    /// Count the differing, non-excluded pixels when `other` is translated by `shift`.
+    #[allow(clippy::cast_possible_wrap)]
+    #[allow(clippy::cast_sign_loss)]
+    fn difference(&self, other: &Self, shift: Shift) -> usize {
+        let (height, width) = self.threshold.dim();
+        let (shift_x, shift_y) = (shift.x as isize, shift.y as isize);
+        let mut count = 0;
+
+        for y in 0..height as isize {
+            let source_y = y - shift_y;
+            if source_y < 0 || source_y >= height as isize {
+                continue;
+            }
+
+            for x in 0..width as isize {
+                let source_x = x - shift_x;
+                if source_x < 0 || source_x >= width as isize {
+                    continue;
+                }
+
+                let target = [y as usize, x as usize];
+                let source = [source_y as usize, source_x as usize];
+
+                if self.exclusion[target]
+                    && other.exclusion[source]
+                    && self.threshold[target] != other.threshold[source]
+                {
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+}
+
+/// Grayscale version of an image buffer.
+fn to_gray(buffer: &Array3<f32>) -> Array2<f32> {
+    let mut gray = Array2::<f32>::zeros((buffer.dim().0, buffer.dim().1));
+
+    Zip::from(&mut gray)
+        .and(buffer.lanes(Axis(2)))
+        .par_for_each(|gray, pixel| *gray = luminance(pixel));
+
+    gray
+}
+
+/// Halve the resolution of an image by averaging 2x2 blocks.
+fn downsample(gray: &Array2<f32>) -> Array2<f32> {
+    let (height, width) = gray.dim();
+
+    Array2::from_shape_fn((height / 2, width / 2), |(y, x)| {
+        (gray[[2 * y, 2 * x]]
+            + gray[[2 * y + 1, 2 * x]]
+            + gray[[2 * y, 2 * x + 1]]
+            + gray[[2 * y + 1, 2 * x + 1]])
+            / 4.
+    })
+}
+
+/// Bitmaps for each level of the image pyramid, finest level first.
+fn pyramid(buffer: &Array3<f32>, levels: u32) -> Vec<Bitmaps> {
+    let mut gray = to_gray(buffer);
+    let mut bitmaps = vec![Bitmaps::new(&gray)];
+
+    for _ in 1..levels {
+        gray = downsample(&gray);
+        bitmaps.push(Bitmaps::new(&gray));
+    }
+
+    bitmaps
+}
+
+/// Find the shift that aligns `image` with `reference`, starting at the coarsest level and
+/// refining the shift by one pixel in each direction at every finer level.
+fn find_shift(reference: &[Bitmaps], image: &[Bitmaps]) -> Shift {
+    let mut shift = Shift::default();
+
+    for (reference, image) in reference.iter().zip(image).rev() {
+        let center = Shift {
+            x: shift.x * 2,
+            y: shift.y * 2,
+        };
+
+        shift = (-1..=1)
+            .flat_map(|y| (-1..=1).map(move |x| (x, y)))
+            .map(|(x, y)| Shift {
+                x: center.x + x,
+                y: center.y + y,
+            })
+            .min_by_key(|candidate| reference.difference(image, *candidate))
+            .unwrap_or(center);
+    }
+
+    shift
+}
+
+/// Compute the shift of each input relative to the reference frame (see
+/// [`HDRInputList::reference_index`]) using Median Threshold Bitmaps. All inputs must be of the
+/// same dimensions. The returned shifts are in the same order as the inputs, and the reference
+/// frame always has a shift of zero.
+///
+/// # Arguments
+///
+/// * `inputs`: The images to align
+/// * `max_shift_bits`: Number of pyramid levels to search. Shifts of up to `2^max_shift_bits - 1`
+///   pixels in each direction can be detected. See [`DEFAULT_MAX_SHIFT_BITS`].
+///
+/// returns: `Vec<Shift>`
+#[must_use]
+pub fn compute_shifts(inputs: &HDRInputList, max_shift_bits: u32) -> Vec<Shift> {
+    let Some(first) = inputs.as_slice().first() else {
+        return Vec::new();
+    };
+
+    let (height, width, _) = first.get_buffer().dim();
+    let mut levels = 1;
+    while levels < max_shift_bits.max(1) && height.min(width) >> levels >= MIN_PYRAMID_SIZE {
+        levels += 1;
+    }
+
+    let reference_index = inputs.reference_index();
+    let pyramids = inputs
+        .as_slice()
+        .par_iter()
+        .map(|input| pyramid(input.get_buffer(), levels))
+        .collect::<Vec<_>>();
+
+    pyramids
+        .par_iter()
+        .enumerate()
+        .map(|(index, pyramid)| {
+            if index == reference_index {
+                Shift::default()
+            } else {
+                find_shift(&pyramids[reference_index], pyramid)
+            }
+        })
+        .collect()
+}
+
+/// Translate each input, and its alpha channel and weight map if any, by its corresponding shift.
+/// Pixels moved in from outside the frame replicate the nearest edge pixel.
+///
+/// # Errors
+/// If `shifts` doesn't have one entry per input
+pub fn apply_shifts(inputs: &mut HDRInputList, shifts: &[Shift]) -> Result<(), Error> {
+    validate_override_length("shifts", shifts.len(), inputs.len())?;
+
+    inputs
+        .as_slice_mut()
+        .par_iter_mut()
+        .zip(shifts)
+        .filter(|(_, shift)| **shift != Shift::default())
+        .for_each(|(input, shift)| {
+            let buffer = input.get_buffer_mut();
+            *buffer = shift_buffer(buffer, *shift);
+
+            if let Some(alpha) = input.get_alpha_mut() {
+                *alpha = shift_buffer(alpha, *shift);
+            }
+            if let Some(weight_map) = input.get_weight_map_mut() {
+                *weight_map = shift_buffer(weight_map, *shift);
+            }
+        });
+
+    Ok(())
+}
+
+/// Translate a `(height, width, channels)` buffer by a whole number of pixels.
+#[allow(clippy::cast_possible_wrap)]
+#[allow(clippy::cast_sign_loss)]
+fn shift_buffer(buffer: &Array3<f32>, shift: Shift) -> Array3<f32> {
+    let (height, width, channels) = buffer.dim();
+    let clamp = |value: isize, size: usize| value.clamp(0, size as isize - 1) as usize;
+
+    Array3::from_shape_fn((height, width, channels), |(y, x, channel)| {
+        let source_y = clamp(y as isize - shift.y as isize, height);
+        let source_x = clamp(x as isize - shift.x as isize, width);
+
+        buffer[[source_y, source_x, channel]]
+    })
+}
+
+/// Compute the shift of each input relative to the reference frame like [`compute_shifts`], and
+/// refine it to a fraction of a pixel. Around the integer shift, the difference between the
+/// bitmaps of the full resolution frames is measured one pixel to each side, and the minimum of a
+/// parabola through the three values is taken, separately for each axis. The refinement is at
+/// most half a pixel in each direction.
+///
+/// returns: `Vec<(f32, f32)>` with the `(x, y)` shift of each input in pixels, in the same order
+/// as the inputs, which can be applied with [`apply_subpixel_shifts`]. The reference frame always
+/// has a shift of zero.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn compute_subpixel_shifts(inputs: &HDRInputList, max_shift_bits: u32) -> Vec<(f32, f32)> {
+    let shifts = compute_shifts(inputs, max_shift_bits);
+    let reference_index = inputs.reference_index();
+    let bitmaps = inputs
+        .as_slice()
+        .par_iter()
+        .map(|input| Bitmaps::new(&to_gray(input.get_buffer())))
+        .collect::<Vec<_>>();
+
+    shifts
+        .par_iter()
+        .enumerate()
+        .map(|(index, shift)| {
+            if index == reference_index {
+                return (0., 0.);
+            }
+
+            let difference = |x: i32, y: i32| {
+                bitmaps[reference_index].difference(
+                    &bitmaps[index],
+                    Shift {
+                        x: shift.x + x,
+                        y: shift.y + y,
+                    },
+                ) as f32
+            };
+            let center = difference(0, 0);
+
+            (
+                shift.x as f32 + parabola_minimum(difference(-1, 0), center, difference(1, 0)),
+                shift.y as f32 + parabola_minimum(difference(0, -1), center, difference(0, 1)),
+            )
+        })
+        .collect()
+}
+
+/// Offset of the minimum of the parabola through `(-1, before)`, `(0, center)` and
+/// `(1, after)`, limited to half a sample. Returns `0.0` if the parabola has no minimum.
+fn parabola_minimum(before: f32, center: f32, after: f32) -> f32 {
+    let curvature = before - 2. * center + after;
+    if curvature <= 0. {
+        return 0.;
+    }
+
+    ((before - after) / (2. * curvature)).clamp(-0.5, 0.5)
+}
+
+/// Translate each input by its corresponding `(x, y)` shift in pixels, which may be fractional,
+/// e.g. computed with [`compute_subpixel_shifts`]. Alpha channels and weight maps are shifted
+/// along. As with [`Shift`], positive values move the frame to the right and down. Each output
+/// pixel is bilinearly interpolated from the four pixels around its source position. Source
+/// positions outside of the frame are clamped to its edges, so that pixels moved in from outside
+/// replicate the nearest edge pixel, like [`apply_shifts`].
+///
+/// Bilinear interpolation slightly softens frames with a fractional shift, while integer shifts
+/// move pixels without any interpolation.
+///
+/// # Errors
+/// - If `shifts` doesn't have one entry per input
+/// - If any of the shifts is not finite
+pub fn apply_subpixel_shifts(
+    inputs: &mut HDRInputList,
+    shifts: &[(f32, f32)],
+) -> Result<(), Error> {
+    validate_override_length("shifts", shifts.len(), inputs.len())?;
+
+    if let Some((x, y)) = shifts
+        .iter()
+        .find(|(x, y)| !x.is_finite() || !y.is_finite())
+    {
+        return Err(Error::InputError {
+            parameter_name: "shifts".to_string(),
+            message: format!("Shifts must be finite numbers, got ({x}, {y})"),
+        });
+    }
+
+    inputs
+        .as_slice_mut()
+        .par_iter_mut()
+        .zip(shifts)
+        .filter(|(_, (x, y))| *x != 0. || *y != 0.)
+        .for_each(|(input, shift)| {
+            let buffer = input.get_buffer_mut();
+            *buffer = subpixel_shift_buffer(buffer, *shift);
+
+            if let Some(alpha) = input.get_alpha_mut() {
+                *alpha = subpixel_shift_buffer(alpha, *shift);
+            }
+            if let Some(weight_map) = input.get_weight_map_mut() {
+                *weight_map = subpixel_shift_buffer(weight_map, *shift);
+            }
+        });
+
+    Ok(())
+}
+
+/// Translate a `(height, width, channels)` buffer by a fractional `(x, y)` shift with bilinear
+/// interpolation.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_sign_loss)]
+fn subpixel_shift_buffer(buffer: &Array3<f32>, (shift_x, shift_y): (f32, f32)) -> Array3<f32> {
+    let (height, width, channels) = buffer.dim();
+    let source = |position: f32, size: usize| {
+        let position = position.clamp(0., (size - 1) as f32);
+        let low = position.floor() as usize;
+
+        (low, (low + 1).min(size - 1), position - low as f32)
+    };
+
+    let mut shifted = Array3::<f32>::zeros((height, width, channels));
+    Zip::indexed(shifted.lanes_mut(Axis(2))).par_for_each(|(y, x), mut pixel| {
+        let (y0, y1, fraction_y) = source(y as f32 - shift_y, height);
+        let (x0, x1, fraction_x) = source(x as f32 - shift_x, width);
+
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            let top = buffer[[y0, x0, channel]] * (1. - fraction_x)
+                + buffer[[y0, x1, channel]] * fraction_x;
+            let bottom = buffer[[y1, x0, channel]] * (1. - fraction_x)
+                + buffer[[y1, x1, channel]] * fraction_x;
+
+            *value = top * (1. - fraction_y) + bottom * fraction_y;
+        }
+    });
+
+    shifted
+}
@@ -0,0 +1,138 @@
+//! Asynchronous merging for use within a [tokio](https://docs.rs/tokio) runtime.
+//!
+//! Merging a stack is CPU bound and blocks the calling thread until it completes, which stalls
+//! every other task scheduled on a tokio worker thread. The functions of this module read the
+//! files with [`tokio::fs`] instead, and run decoding and merging on tokio's blocking thread pool
+//! with [`tokio::task::spawn_blocking`], so the worker threads stay free while stacks are merged.
+//!
+//! The blocking thread only drives the merge: the pixel work itself still runs on rayon's global
+//! thread pool, which is shared by every merge running at the same time, so merging many stacks
+//! concurrently doesn't oversubscribe the CPU. To run the work on a dedicated rayon pool instead,
+//! call [`crate::hdr_merge_images_in_pool`] from within your own
+//! [`tokio::task::spawn_blocking`] task.
+//!
+//! Enable the `async` feature to use this module. Its functions must be called from within a
+//! tokio runtime.
+
+use crate::config::MergeConfig;
+use crate::error::ReadFailure;
+use crate::input::{read_each, validate_channel_layouts, HDRInput, HDRInputList};
+use crate::io::read_image;
+use crate::output::HDRImage;
+use crate::{hdr_merge_images_with_config, validate_image_count, Error};
+use std::path::{Path, PathBuf};
+use tokio::task::{JoinError, JoinSet};
+
+/// Given a set of file paths, read them asynchronously and HDR merge them with the default
+/// [`MergeConfig`] without blocking the async runtime. This is the asynchronous counterpart of
+/// [`crate::hdr_merge_paths`], see [`merge_hdr_async_with_config`].
+///
+/// # Errors
+/// - If any of the files cannot be read, or any of the images cannot be decoded or doesn't
+///   contain EXIF metadata for exposure and/or gain, listing every failure
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub async fn merge_hdr_async<P: AsRef<Path>>(paths: &[P]) -> Result<HDRImage, Error> {
+    merge_hdr_async_with_config(paths, MergeConfig::default()).await
+}
+
+/// Same as [`merge_hdr_async`], but allows customising the merge with a [`MergeConfig`].
+///
+/// Every file is read concurrently with [`tokio::fs::read`]. Once all of them are in memory, the
+/// images are decoded and merged in a single [`tokio::task::spawn_blocking`] task, which is
+/// awaited. Panics raised while merging are propagated to the caller.
+///
+/// # Errors
+/// - If any of the files cannot be read, listing every failure
+/// - If any of the images cannot be decoded or doesn't contain EXIF metadata for exposure and/or
+///   gain, listing every failure
+/// - If the images don't all have the same channel layout, listing every image that differs from
+///   the first one
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+/// - If the runtime shuts down before the merge completes
+pub async fn merge_hdr_async_with_config<P: AsRef<Path>>(
+    paths: &[P],
+    config: MergeConfig,
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let files = read_files(paths).await?;
+
+    tokio::task::spawn_blocking(move || {
+        let inputs = read_each(
+            &files,
+            |(path, _)| Some(path.as_path()),
+            |(path, data), _| {
+                let format = image::ImageFormat::from_path(path).ok();
+                let image = read_image(data, format)?;
+
+                HDRInput::with_decoded_file(data, &image, None, None)
+            },
+        )?;
+
+        validate_channel_layouts(&inputs, |index| (index, Some(files[index].0.clone())))?;
+
+        let mut inputs = HDRInputList::from(inputs);
+
+        hdr_merge_images_with_config(&mut inputs, &config)
+    })
+    .await
+    .unwrap_or_else(|error| Err(join_error(error)))
+}
+
+/// Read every file concurrently, keeping the order of `paths`.
+///
+/// # Errors
+/// If any of the files cannot be read, listing every failure
+async fn read_files<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+    let mut tasks = JoinSet::new();
+    for (index, path) in paths.iter().enumerate() {
+        let path = path.as_ref().to_path_buf();
+
+        tasks.spawn(async move {
+            let result = tokio::fs::read(&path).await;
+            (index, path, result)
+        });
+    }
+
+    let mut files = Vec::with_capacity(paths.len());
+    let mut failures = Vec::new();
+
+    while let Some(result) = tasks.join_next().await {
+        let (index, path, result) = result.map_err(join_error)?;
+
+        match result {
+            Ok(data) => files.push((index, path, data)),
+            Err(error) => failures.push(ReadFailure {
+                index,
+                path: Some(path),
+                error: error.into(),
+            }),
+        }
+    }
+
+    if !failures.is_empty() {
+        failures.sort_by_key(|failure| failure.index);
+        return Err(Error::ReadErrors(failures));
+    }
+
+    files.sort_by_key(|(index, _, _)| *index);
+
+    Ok(files
+        .into_iter()
+        .map(|(_, path, data)| (path, data))
+        .collect())
+}
+
+/// Resume panics of a task on the awaiting thread, and report cancelled tasks as I/O errors.
+fn join_error(error: JoinError) -> Error {
+    if error.is_panic() {
+        std::panic::resume_unwind(error.into_panic());
+    }
+
+    Error::IoError(error.into())
+}
@@ -0,0 +1,360 @@
+//! Command line tool to merge a stack of exposures into an HDR image.
+//!
+//! Build with `cargo build --release --features cli`, then run for example
+//! `image-hdr "shots/*.jpg" -o merged.png --tonemap durand`.
+#![allow(clippy::multiple_crate_versions)]
+
+use clap::{Parser, ValueEnum};
+use image::{DynamicImage, ImageFormat};
+use image_hdr::builder::MergeBuilder;
+use image_hdr::filename::ExposurePattern;
+use image_hdr::input::{HDRInput, HDRInputList};
+use image_hdr::output::HDRImage;
+use image_hdr::sidecar::Sidecar;
+use image_hdr::tonemap::{BitDepth, ToneMapOperator};
+use image_hdr::{export, stretch, tonemap};
+use rayon::prelude::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Merge a stack of exposures into an HDR image, using the exposure and gain from each file's
+/// EXIF metadata.
+#[derive(Parser)]
+#[command(version, about)]
+struct Arguments {
+    /// Input files, or glob patterns matching them
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Path of the output file
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Output format. Inferred from the output extension when omitted
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t = ToneMap::Reinhard,
+        help = "Tone mapping operator used for PNG, JPEG and TIFF. Radiance, OpenEXR and PFM output is never tone mapped"
+    )]
+    tonemap: ToneMap,
+
+    /// Write 16 bits per channel instead of 8 to PNG and TIFF output
+    #[arg(long)]
+    sixteen_bit: bool,
+
+    /// Align the images with each other before merging
+    #[arg(long)]
+    align: bool,
+
+    /// Suppress ghosts caused by subjects moving between exposures
+    #[arg(long)]
+    deghost: bool,
+
+    /// Read the exposure time of files without EXIF metadata from their file name with this
+    /// pattern, e.g. "*_{exposure}s" for shot_1-250s.jpg
+    #[arg(long)]
+    exposure_pattern: Option<String>,
+
+    /// Read the exposure and gain of every input from this file, holding one
+    /// "filename exposure gain" line per input, instead of EXIF metadata
+    #[arg(long, conflicts_with = "exposure_pattern")]
+    sidecar: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Radiance RGBE, holding the linear radiance
+    Hdr,
+    #[value(help = "OpenEXR, holding the linear radiance. Needs the exr feature")]
+    Exr,
+    /// Portable Float Map, holding the linear radiance
+    Pfm,
+    /// Tone mapped 8 or 16-bit PNG
+    Png,
+    /// Tone mapped 8-bit JPEG
+    Jpeg,
+    /// Tone mapped 8 or 16-bit TIFF
+    Tiff,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "hdr" => Some(Self::Hdr),
+            "exr" => Some(Self::Exr),
+            "pfm" => Some(Self::Pfm),
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "tif" | "tiff" => Some(Self::Tiff),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ToneMap {
+    /// Global Reinhard operator applied to each channel
+    Reinhard,
+    /// Global Reinhard operator applied to luminance, preserving hue
+    ReinhardLuminance,
+    /// Durand and Dorsey local operator, preserving local contrast
+    Durand,
+    /// ACES filmic curve, rolling highlights off like film
+    AcesFilmic,
+    /// Drago adaptive logarithmic operator, suited to very high dynamic range scenes
+    Drago,
+    /// Black and white points at the 1st and 99th luminance percentiles
+    Auto,
+    /// Histogram stretch of the linear radiance
+    Stretch,
+}
+
+fn main() -> ExitCode {
+    let arguments = Arguments::parse();
+
+    match run(&arguments) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("error: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(arguments: &Arguments) -> Result<(), String> {
+    let format = arguments
+        .format
+        .or_else(|| Format::from_path(&arguments.output))
+        .ok_or_else(|| {
+            format!(
+                "Cannot infer the output format of {}, use --format",
+                arguments.output.display()
+            )
+        })?;
+
+    let paths = expand_inputs(&arguments.inputs)?;
+    let source = match (&arguments.exposure_pattern, &arguments.sidecar) {
+        (Some(pattern), _) => ExposureSource::Pattern(
+            ExposurePattern::new(pattern).map_err(|error| error.to_string())?,
+        ),
+        (_, Some(sidecar)) => ExposureSource::Sidecar(
+            Sidecar::load(sidecar).map_err(|error| format!("{}: {error}", sidecar.display()))?,
+        ),
+        (None, None) => ExposureSource::Exif,
+    };
+    let mut inputs = read_inputs(&paths, &source)?;
+    let count = paths.len();
+
+    let merged = MergeBuilder::new()
+        .align(arguments.align)
+        .deghost(arguments.deghost)
+        .progress(move |done| {
+            eprint!("\rMerging {count} images: {:>3.0}%", done * 100.);
+            let _ = std::io::stderr().flush();
+        })
+        .merge_inputs(&mut inputs)
+        .map_err(|error| error.to_string())?;
+    eprintln!();
+
+    let bit_depth = if arguments.sixteen_bit {
+        BitDepth::Sixteen
+    } else {
+        BitDepth::Eight
+    };
+    save(
+        &merged,
+        format,
+        arguments.tonemap,
+        bit_depth,
+        &arguments.output,
+    )?;
+    eprintln!("Saved {}", arguments.output.display());
+
+    Ok(())
+}
+
+/// Expand glob patterns into the paths they match. Arguments without glob characters are used
+/// as is, so that missing files are reported when reading them.
+fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut paths = Vec::new();
+
+    for input in inputs {
+        if !input.contains(['*', '?', '[']) {
+            paths.push(PathBuf::from(input));
+            continue;
+        }
+
+        let matches = glob::glob(input)
+            .map_err(|error| format!("Invalid pattern {input}: {error}"))?
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .map_err(|error| error.to_string())?;
+
+        if matches.is_empty() {
+            return Err(format!("Pattern {input} doesn't match any file"));
+        }
+
+        paths.extend(matches);
+    }
+
+    Ok(paths)
+}
+
+/// Where the exposure and gain of each input come from
+enum ExposureSource {
+    /// EXIF metadata
+    Exif,
+    /// EXIF metadata, falling back to the file name for the exposure
+    Pattern(ExposurePattern),
+    /// A sidecar file
+    Sidecar(Sidecar),
+}
+
+/// Read every input, reporting each file that cannot be read or lacks an exposure and gain.
+fn read_inputs(paths: &[PathBuf], source: &ExposureSource) -> Result<HDRInputList, String> {
+    eprintln!("Reading {} images", paths.len());
+
+    let results = paths
+        .par_iter()
+        .map(|path| match source {
+            ExposureSource::Exif => HDRInput::new(path),
+            ExposureSource::Pattern(pattern) => HDRInput::with_exposure_pattern(path, pattern),
+            ExposureSource::Sidecar(sidecar) => match sidecar.entry(path) {
+                Some(entry) => HDRInput::with_exposure_and_gain(path, entry.exposure, entry.gain),
+                None => Err(image_hdr::Error::InputError {
+                    parameter_name: "sidecar".to_string(),
+                    message: "The sidecar has no entry for this file".to_string(),
+                }),
+            },
+        })
+        .collect::<Vec<Result<HDRInput, image_hdr::Error>>>();
+
+    let mut inputs = Vec::with_capacity(results.len());
+    let mut failures = 0;
+
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(input) => inputs.push(input),
+            Err(error) => {
+                eprintln!("{}: {error}", path.display());
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(format!(
+            "{failures} of {} images could not be read",
+            paths.len()
+        ));
+    }
+
+    let inputs = HDRInputList::from(inputs);
+    if let Err(image_hdr::Error::ReadErrors(failures)) = inputs.validate_channel_layouts() {
+        for failure in &failures {
+            eprintln!("{}: {}", paths[failure.index].display(), failure.error);
+        }
+
+        return Err(format!(
+            "{} of {} images have a different channel layout",
+            failures.len(),
+            paths.len()
+        ));
+    }
+
+    Ok(inputs)
+}
+
+fn save(
+    merged: &HDRImage,
+    format: Format,
+    tone_map: ToneMap,
+    bit_depth: BitDepth,
+    path: &Path,
+) -> Result<(), String> {
+    let image_format = match format {
+        Format::Hdr => {
+            return export::save_radiance(merged, path).map_err(|error| error.to_string())
+        }
+        Format::Exr => return save_exr(merged, path),
+        Format::Pfm => return export::save_pfm(merged, path).map_err(|error| error.to_string()),
+        Format::Png => ImageFormat::Png,
+        Format::Jpeg if bit_depth == BitDepth::Sixteen => {
+            return Err("JPEG output only supports 8 bits per channel".to_string())
+        }
+        Format::Jpeg => ImageFormat::Jpeg,
+        Format::Tiff => ImageFormat::Tiff,
+    };
+
+    let operator = match tone_map {
+        ToneMap::Reinhard => ToneMapOperator::Reinhard {
+            key: tonemap::DEFAULT_REINHARD_KEY,
+        },
+        ToneMap::ReinhardLuminance => ToneMapOperator::ReinhardLuminance {
+            key: tonemap::DEFAULT_REINHARD_KEY,
+        },
+        ToneMap::Durand => ToneMapOperator::Durand {
+            contrast: tonemap::DEFAULT_DURAND_CONTRAST,
+            sigma_spatial: None,
+            sigma_range: tonemap::DEFAULT_DURAND_SIGMA_RANGE,
+        },
+        ToneMap::AcesFilmic => ToneMapOperator::AcesFilmic {
+            exposure: tonemap::DEFAULT_ACES_EXPOSURE,
+        },
+        ToneMap::Drago => ToneMapOperator::Drago {
+            bias: tonemap::DEFAULT_DRAGO_BIAS,
+        },
+        ToneMap::Auto => ToneMapOperator::Auto {
+            black_percentile: tonemap::DEFAULT_AUTO_BLACK_PERCENTILE,
+            white_percentile: tonemap::DEFAULT_AUTO_WHITE_PERCENTILE,
+        },
+        ToneMap::Stretch => {
+            let stretched = stretch::apply_histogram_stretch(&DynamicImage::from(merged.clone()))
+                .map_err(|error| error.to_string())?;
+
+            return save_stretched(&stretched, bit_depth, image_format, path);
+        }
+    };
+
+    let tone_mapped =
+        tonemap::tone_map(merged, operator, bit_depth).map_err(|error| error.to_string())?;
+
+    tone_mapped
+        .save_with_format(path, image_format)
+        .map_err(|error| error.to_string())
+}
+
+fn save_stretched(
+    stretched: &DynamicImage,
+    bit_depth: BitDepth,
+    image_format: ImageFormat,
+    path: &Path,
+) -> Result<(), String> {
+    let stretched = match (bit_depth, stretched.color().channel_count()) {
+        (BitDepth::Eight, 1) => DynamicImage::from(stretched.to_luma8()),
+        (BitDepth::Eight, _) => DynamicImage::from(stretched.to_rgb8()),
+        (BitDepth::Sixteen, 1) => DynamicImage::from(stretched.to_luma16()),
+        (BitDepth::Sixteen, _) => DynamicImage::from(stretched.to_rgb16()),
+    };
+
+    stretched
+        .save_with_format(path, image_format)
+        .map_err(|error| error.to_string())
+}
+
+#[cfg(feature = "exr")]
+fn save_exr(merged: &HDRImage, path: &Path) -> Result<(), String> {
+    export::save_exr(merged, path).map_err(|error| error.to_string())
+}
+
+#[cfg(not(feature = "exr"))]
+fn save_exr(_: &HDRImage, _: &Path) -> Result<(), String> {
+    Err("OpenEXR output needs the `exr` feature".to_string())
+}
@@ -0,0 +1,447 @@
+//! Builder collecting every option of the merge pipeline
+
+use crate::align::apply_subpixel_shifts;
+use crate::calibration::Calibration;
+use crate::config::{
+    AlphaMode, ColorCoefficients, ExposureModel, ExtremeFrameHandling, InputEncoding, MergeConfig,
+    NoiseModel, NonFiniteHandling, VignettingCorrection, WeightFunction,
+};
+use crate::input::{validate_override_length, HDRInputList};
+use crate::output::HDRImage;
+use crate::{
+    hdr_merge_images_with_progress, merge_aligned, merge_paths_low_memory, validate_image_count,
+    Error,
+};
+use image::imageops::FilterType;
+use std::path::Path;
+use std::time::Duration;
+
+/// Builder for configuring and running an HDR merge.
+///
+/// Every option defaults to the behavior of [`crate::hdr_merge_paths`], so
+/// `MergeBuilder::new().merge(&paths)` is equivalent to `hdr_merge_paths(&paths)`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use image_hdr::builder::MergeBuilder;
+///
+/// let paths = ["image1.tif", "image2.tif", "image3.tif"];
+/// let merged = MergeBuilder::new()
+///     .align(true)
+///     .deghost(true)
+///     .progress(|done| println!("{:.0}%", done * 100.))
+///     .merge(&paths)?;
+/// # Ok::<(), image_hdr::Error>(())
+/// ```
+#[derive(Default)]
+pub struct MergeBuilder {
+    config: MergeConfig,
+    exposures: Option<Vec<Duration>>,
+    gains: Option<Vec<f32>>,
+    ev_offsets: Option<Vec<f32>>,
+    shifts: Option<Vec<(f32, f32)>>,
+    align: bool,
+    low_memory: bool,
+    skip_unreadable: bool,
+    progress: Option<Box<dyn Fn(f32)>>,
+}
+
+impl MergeBuilder {
+    /// Create new [`MergeBuilder`] with the default options
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace every option covered by [`MergeConfig`] at once
+    #[must_use]
+    pub fn config(mut self, config: MergeConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the camera settings that make up the effective exposure of each frame. See
+    /// [`MergeConfig::exposure_model`].
+    #[must_use]
+    pub fn exposure_model(mut self, model: ExposureModel) -> Self {
+        self.config.exposure_model = model;
+        self
+    }
+
+    /// Set the per-channel coefficients. See [`MergeConfig::coefficients`].
+    #[must_use]
+    pub fn coefficients(mut self, coefficients: ColorCoefficients) -> Self {
+        self.config.coefficients = coefficients;
+        self
+    }
+
+    /// Enable or disable deghosting. See [`MergeConfig::deghost`].
+    #[must_use]
+    pub fn deghost(mut self, deghost: bool) -> Self {
+        self.config.deghost = deghost;
+        self
+    }
+
+    /// Set the deghosting threshold. See [`MergeConfig::deghost_threshold`].
+    #[must_use]
+    pub fn deghost_threshold(mut self, threshold: f32) -> Self {
+        self.config.deghost_threshold = threshold;
+        self
+    }
+
+    /// Set the saturation threshold. See [`MergeConfig::saturation_threshold`].
+    #[must_use]
+    pub fn saturation_threshold(mut self, threshold: f32) -> Self {
+        self.config.saturation_threshold = threshold;
+        self
+    }
+
+    /// Set per-channel saturation thresholds, or use the single saturation threshold for every
+    /// channel with `None`. See [`MergeConfig::channel_saturation_thresholds`].
+    #[must_use]
+    pub fn channel_saturation_thresholds(mut self, thresholds: Option<Vec<f32>>) -> Self {
+        self.config.channel_saturation_thresholds = thresholds;
+        self
+    }
+
+    /// Set the noise floor. See [`MergeConfig::noise_floor`].
+    #[must_use]
+    pub fn noise_floor(mut self, noise_floor: f32) -> Self {
+        self.config.noise_floor = noise_floor;
+        self
+    }
+
+    /// Set the sensor noise model. See [`MergeConfig::noise_model`].
+    #[must_use]
+    pub fn noise_model(mut self, noise_model: NoiseModel) -> Self {
+        self.config.noise_model = noise_model;
+        self
+    }
+
+    /// Set the largest share of clipped pixels of a frame. See
+    /// [`MergeConfig::max_clipped_fraction`].
+    #[must_use]
+    pub fn max_clipped_fraction(mut self, fraction: f32) -> Self {
+        self.config.max_clipped_fraction = fraction;
+        self
+    }
+
+    /// Set how frames with extreme clipping are handled. See [`MergeConfig::extreme_frames`].
+    #[must_use]
+    pub fn extreme_frames(mut self, handling: ExtremeFrameHandling) -> Self {
+        self.config.extreme_frames = handling;
+        self
+    }
+
+    /// Enable or disable sorting by exposure. See [`MergeConfig::sort_by_exposure`].
+    #[must_use]
+    pub fn sort_by_exposure(mut self, sort: bool) -> Self {
+        self.config.sort_by_exposure = sort;
+        self
+    }
+
+    /// Resize frames of different sizes with the given filter instead of rejecting the stack.
+    /// See [`MergeConfig::resize_filter`].
+    #[must_use]
+    pub fn resize_filter(mut self, filter: Option<FilterType>) -> Self {
+        self.config.resize_filter = filter;
+        self
+    }
+
+    /// Correct hot and dead pixels in every frame with the given threshold, or disable the
+    /// correction with `None`. See [`MergeConfig::hot_pixel_threshold`].
+    #[must_use]
+    pub fn hot_pixel_threshold(mut self, threshold: Option<f32>) -> Self {
+        self.config.hot_pixel_threshold = threshold;
+        self
+    }
+
+    /// Denoise the merged radiance with the given strength, or disable denoising with `None`.
+    /// See [`MergeConfig::denoise_strength`].
+    #[must_use]
+    pub fn denoise_strength(mut self, strength: Option<f32>) -> Self {
+        self.config.denoise_strength = strength;
+        self
+    }
+
+    /// Set the transfer function the pixel values of the inputs are encoded with. See
+    /// [`MergeConfig::input_encoding`].
+    #[must_use]
+    pub fn input_encoding(mut self, encoding: InputEncoding) -> Self {
+        self.config.input_encoding = encoding;
+        self
+    }
+
+    /// Set the function of the pixel value that scales the weight of each sample. See
+    /// [`MergeConfig::weight_function`].
+    #[must_use]
+    pub fn weight_function(mut self, weight_function: WeightFunction) -> Self {
+        self.config.weight_function = weight_function;
+        self
+    }
+
+    /// Set how the alpha channels of the frames are combined. See [`MergeConfig::alpha`].
+    #[must_use]
+    pub fn alpha(mut self, alpha: AlphaMode) -> Self {
+        self.config.alpha = alpha;
+        self
+    }
+
+    /// Set whether the merged color channels are premultiplied by alpha. See
+    /// [`MergeConfig::premultiply_alpha`].
+    #[must_use]
+    pub fn premultiply_alpha(mut self, premultiply: bool) -> Self {
+        self.config.premultiply_alpha = premultiply;
+        self
+    }
+
+    /// Set the minimum value pixels are divided by to convert them to radiance. See
+    /// [`MergeConfig::min_scaling_factor`].
+    #[must_use]
+    pub fn min_scaling_factor(mut self, factor: Option<f32>) -> Self {
+        self.config.min_scaling_factor = factor;
+        self
+    }
+
+    /// Set the dark frame and flat field. See [`MergeConfig::calibration`].
+    #[must_use]
+    pub fn calibration(mut self, calibration: Calibration) -> Self {
+        self.config.calibration = calibration;
+        self
+    }
+
+    /// Set the vignetting correction. See [`MergeConfig::vignetting`].
+    #[must_use]
+    pub fn vignetting(mut self, vignetting: VignettingCorrection) -> Self {
+        self.config.vignetting = vignetting;
+        self
+    }
+
+    /// Only merge the `(x, y, width, height)` region of the frames, or merge the whole frames with
+    /// `None`. See [`MergeConfig::crop`].
+    #[must_use]
+    pub fn crop(mut self, crop: Option<(u32, u32, u32, u32)>) -> Self {
+        self.config.crop = crop;
+        self
+    }
+
+    /// Reject stacks whose exposures span less than the given ratio, or accept any stack with
+    /// `None`. See [`MergeConfig::min_exposure_ratio`].
+    #[must_use]
+    pub fn min_exposure_ratio(mut self, ratio: Option<f32>) -> Self {
+        self.config.min_exposure_ratio = ratio;
+        self
+    }
+
+    /// Set how infinite and NaN values in the result are handled. See
+    /// [`MergeConfig::non_finite`].
+    #[must_use]
+    pub fn non_finite(mut self, non_finite: NonFiniteHandling) -> Self {
+        self.config.non_finite = non_finite;
+        self
+    }
+
+    /// Use the given exposures instead of the ones in EXIF data, one per path, in the same order.
+    #[must_use]
+    pub fn exposures(mut self, exposures: Vec<Duration>) -> Self {
+        self.exposures = Some(exposures);
+        self
+    }
+
+    /// Use the given gains instead of the ones in EXIF data, one per path, in the same order.
+    #[must_use]
+    pub fn gains(mut self, gains: Vec<f32>) -> Self {
+        self.gains = Some(gains);
+        self
+    }
+
+    /// Apply the given exposure compensation, in EV, one per path, in the same order. Each
+    /// exposure is multiplied by `2^ev`, on top of the exposure time read from EXIF data or set
+    /// with [`MergeBuilder::exposures`]. To rely on EV offsets alone, e.g. when the EXIF exposure
+    /// times are rounded, set every exposure to the same base duration, like one second. The
+    /// absolute exposure then only scales the merged radiance.
+    #[must_use]
+    pub fn ev_offsets(mut self, ev_offsets: Vec<f32>) -> Self {
+        self.ev_offsets = Some(ev_offsets);
+        self
+    }
+
+    /// Translate each frame by the given `(x, y)` shift in pixels before merging, one per path, in
+    /// the same order. Shifts may be fractional, see [`crate::align::apply_subpixel_shifts`].
+    /// When combined with [`MergeBuilder::align`], the shifts are applied first.
+    #[must_use]
+    pub fn shifts(mut self, shifts: Vec<(f32, f32)>) -> Self {
+        self.shifts = Some(shifts);
+        self
+    }
+
+    /// Enable or disable aligning the images with each other before merging. See
+    /// [`crate::hdr_merge_images_aligned`].
+    #[must_use]
+    pub fn align(mut self, align: bool) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Enable or disable low memory mode. Instead of decoding every image up front, images are
+    /// read, converted to radiance and accumulated one at a time, and each decoded frame is
+    /// dropped before the next one is read. Peak memory is then roughly that of two frames
+    /// instead of the whole stack, at the cost of reading and decoding files one after another
+    /// rather than in parallel. Pixels within a frame are still processed in parallel.
+    ///
+    /// Alignment, deghosting, sorting by exposure, resizing and dropping frames with extreme
+    /// clipping need every frame at once and cannot be combined with this mode. Only [`MergeBuilder::merge`] uses it, since
+    /// [`MergeBuilder::merge_inputs`] receives frames that are already in memory.
+    #[must_use]
+    pub fn low_memory(mut self, low_memory: bool) -> Self {
+        self.low_memory = low_memory;
+        self
+    }
+
+    /// Skip files that cannot be read and merge the rest, as long as at least two remain. Only
+    /// [`MergeBuilder::merge`] reads files. Use [`HDRInputList::with_overrides_lenient`] directly
+    /// to find out which files were skipped.
+    #[must_use]
+    pub fn skip_unreadable(mut self, skip_unreadable: bool) -> Self {
+        self.skip_unreadable = skip_unreadable;
+        self
+    }
+
+    /// Report progress of the merge through the given callback. See
+    /// [`crate::hdr_merge_images_with_progress`].
+    #[must_use]
+    pub fn progress(mut self, progress: impl Fn(f32) + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Read the images at the given paths and merge them with the configured options.
+    ///
+    /// # Errors
+    /// - If overrides or EV offsets are provided but their length doesn't match the number of
+    ///   paths
+    /// - If an EV offset leads to an invalid exposure
+    /// - If any of the images cannot be read, or doesn't contain EXIF metadata for a value that
+    ///   isn't overridden, listing every failure. With [`MergeBuilder::skip_unreadable`], only if
+    ///   fewer than two images can be read.
+    /// - If fewer than two paths are provided
+    /// - If images are of different dimensions.
+    /// - If an exposure or gain is not a finite positive number.
+    /// - If the configuration is invalid
+    /// - If shifts are provided but their length doesn't match the number of images, or any of them
+    ///   is not finite
+    /// - If low memory mode is combined with alignment, shifts, deghosting, sorting by exposure,
+    ///   resizing or [`crate::config::ExtremeFrameHandling::Drop`]
+    pub fn merge<P: AsRef<Path> + Sync>(&self, paths: &[P]) -> Result<HDRImage, Error> {
+        if self.low_memory {
+            if self.align {
+                return Err(Error::InputError {
+                    parameter_name: "align".to_string(),
+                    message: "Alignment needs every frame at once and is not supported in low memory mode".to_string(),
+                });
+            }
+
+            if self.shifts.is_some() {
+                return Err(Error::InputError {
+                    parameter_name: "shifts".to_string(),
+                    message: "Shifting frames is not supported in low memory mode".to_string(),
+                });
+            }
+
+            return merge_paths_low_memory(
+                paths,
+                self.exposures.as_deref(),
+                self.gains.as_deref(),
+                self.ev_offsets.as_deref(),
+                &self.config,
+                self.skip_unreadable,
+                self.progress.as_deref().unwrap_or(&|_| {}),
+            );
+        }
+
+        validate_image_count(paths.len())?;
+
+        if let Some(ev_offsets) = &self.ev_offsets {
+            validate_override_length("ev_offsets", ev_offsets.len(), paths.len())?;
+        }
+
+        if let Some(shifts) = &self.shifts {
+            validate_override_length("shifts", shifts.len(), paths.len())?;
+        }
+
+        let (mut inputs, skipped) = if self.skip_unreadable {
+            HDRInputList::with_overrides_lenient(
+                paths,
+                self.exposures.as_deref(),
+                self.gains.as_deref(),
+            )?
+        } else {
+            let inputs = HDRInputList::with_overrides(
+                paths,
+                self.exposures.as_deref(),
+                self.gains.as_deref(),
+            )?;
+
+            (inputs, Vec::new())
+        };
+
+        let is_read = |index: usize| skipped.iter().all(|failure| failure.index != index);
+
+        if let Some(ev_offsets) = &self.ev_offsets {
+            let ev_offsets = ev_offsets
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| is_read(*index))
+                .map(|(_, ev)| *ev)
+                .collect::<Vec<f32>>();
+
+            inputs.compensate_exposures(&ev_offsets)?;
+        }
+
+        let shifts = self.shifts.as_ref().map(|shifts| {
+            shifts
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| is_read(*index))
+                .map(|(_, shift)| *shift)
+                .collect::<Vec<(f32, f32)>>()
+        });
+
+        self.merge_shifted(&mut inputs, shifts.as_deref())
+    }
+
+    /// Merge already loaded inputs with the configured options. Exposure and gain overrides are
+    /// not used, since the inputs already carry their own.
+    ///
+    /// # Errors
+    /// - If image list is empty
+    /// - If images are of different dimensions.
+    /// - If an exposure or gain is not a finite positive number.
+    /// - If the configuration is invalid
+    /// - If shifts are provided but their length doesn't match the number of inputs, or any of
+    ///   them is not finite
+    pub fn merge_inputs(&self, inputs: &mut HDRInputList) -> Result<HDRImage, Error> {
+        self.merge_shifted(inputs, self.shifts.as_deref())
+    }
+
+    /// Merge already loaded inputs, applying the given shifts rather than the configured ones,
+    /// which are for every path rather than every input that could be read.
+    fn merge_shifted(
+        &self,
+        inputs: &mut HDRInputList,
+        shifts: Option<&[(f32, f32)]>,
+    ) -> Result<HDRImage, Error> {
+        if let Some(shifts) = shifts {
+            apply_subpixel_shifts(inputs, shifts)?;
+        }
+
+        let progress = self.progress.as_deref().unwrap_or(&|_| {});
+
+        if self.align {
+            Ok(merge_aligned(inputs, &self.config, progress)?.0)
+        } else {
+            hdr_merge_images_with_progress(inputs, &self.config, progress)
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Dark frame and flat field calibration, removing the fixed pattern noise and the uneven
+//! sensitivity of a sensor before a stack is merged.
+//!
+//! A dark frame is captured with the lens capped, and holds the signal the sensor records without
+//! any light, e.g. amplifier glow and hot pixels. A flat field is captured of an evenly lit
+//! surface, and holds the relative sensitivity of every pixel, including vignetting and dust
+//! shadows. Every sample of every frame is corrected as `(frame - dark) / flat` before it is
+//! converted to radiance. See [`crate::config::MergeConfig::calibration`].
+//!
+//! Calibration is only meaningful for linear pixel values, so it is applied after the values are
+//! linearized according to [`crate::config::MergeConfig::input_encoding`], and the calibration
+//! frames are used as they are, like the output of astrophotography and microscopy cameras.
+
+use crate::extensions::NDArrayBuffer;
+use crate::io::read_image_file;
+#[cfg(not(feature = "parallel"))]
+use crate::parallel::prelude::*;
+use crate::Error;
+use image::DynamicImage;
+use ndarray::{Array3, Axis, Zip};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Dark frame and flat field applied to every frame of a stack. The frames are shared, so cloning
+/// a [`Calibration`], or the [`crate::config::MergeConfig`] holding it, doesn't copy them. The
+/// default calibration holds neither and leaves the frames untouched.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Calibration {
+    dark_frame: Option<Arc<Array3<f32>>>,
+    flat_field: Option<Arc<Array3<f32>>>,
+}
+
+impl Calibration {
+    /// Create new [`Calibration`] from `(height, width, channels)` buffers of normalised pixel
+    /// values, with the dimensions of the stack they apply to. The flat field is scaled so that
+    /// each of its channels has a mean of `1.0`, which keeps the overall brightness and white
+    /// balance of the frames.
+    ///
+    /// # Errors
+    ///
+    /// - If the dark frame holds non-finite values
+    /// - If the flat field holds values that are not finite, positive and non-zero
+    /// - If the dark frame and flat field have different dimensions
+    pub fn new(
+        dark_frame: Option<Array3<f32>>,
+        flat_field: Option<Array3<f32>>,
+    ) -> Result<Self, Error> {
+        if let Some(dark_frame) = &dark_frame {
+            if !dark_frame.iter().all(|value| value.is_finite()) {
+                return Err(Error::InputError {
+                    parameter_name: "dark_frame".to_string(),
+                    message: "Dark frame must only hold finite values".to_string(),
+                });
+            }
+        }
+
+        let flat_field = flat_field.map(normalise_flat_field).transpose()?;
+
+        if let (Some(dark_frame), Some(flat_field)) = (&dark_frame, &flat_field) {
+            if dark_frame.dim() != flat_field.dim() {
+                return Err(Error::InputError {
+                    parameter_name: "flat_field".to_string(),
+                    message: format!(
+                        "Flat field has dimensions {:?}, but the dark frame has dimensions {:?}",
+                        flat_field.dim(),
+                        dark_frame.dim()
+                    ),
+                });
+            }
+        }
+
+        Ok(Self {
+            dark_frame: dark_frame.map(Arc::new),
+            flat_field: flat_field.map(Arc::new),
+        })
+    }
+
+    /// Create new [`Calibration`] from decoded images, see [`Calibration::new`]. The images are
+    /// converted like the frames of the stack, see [`crate::input::HDRInput::with_image`].
+    ///
+    /// # Errors
+    ///
+    /// - If the images are invalid, see [`Calibration::new`]
+    pub fn with_images(
+        dark_frame: Option<&DynamicImage>,
+        flat_field: Option<&DynamicImage>,
+    ) -> Result<Self, Error> {
+        Self::new(
+            dark_frame.map(NDArrayBuffer::to_nd_array_buffer),
+            flat_field.map(NDArrayBuffer::to_nd_array_buffer),
+        )
+    }
+
+    /// Create new [`Calibration`] by reading images from files, see [`Calibration::with_images`].
+    ///
+    /// # Errors
+    ///
+    /// - If any of the files cannot be read or decoded
+    /// - If the images are invalid, see [`Calibration::new`]
+    pub fn with_paths(
+        dark_frame: Option<impl AsRef<Path>>,
+        flat_field: Option<impl AsRef<Path>>,
+    ) -> Result<Self, Error> {
+        let read = |path: &Path| read_image_file(path).map(|(_, image)| image);
+        let dark_frame = dark_frame.map(|path| read(path.as_ref())).transpose()?;
+        let flat_field = flat_field.map(|path| read(path.as_ref())).transpose()?;
+
+        Self::with_images(dark_frame.as_ref(), flat_field.as_ref())
+    }
+
+    /// Get the dark frame, if any
+    #[must_use]
+    pub fn dark_frame(&self) -> Option<&Array3<f32>> {
+        self.dark_frame.as_deref()
+    }
+
+    /// Get the flat field, scaled to a mean of `1.0` per channel, if any
+    #[must_use]
+    pub fn flat_field(&self) -> Option<&Array3<f32>> {
+        self.flat_field.as_deref()
+    }
+
+    /// Whether the calibration holds neither a dark frame nor a flat field
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.dark_frame.is_none() && self.flat_field.is_none()
+    }
+
+    /// Check that the calibration frames apply to frames of the given `(height, width, channels)`
+    /// shape.
+    ///
+    /// # Errors
+    /// If a calibration frame has different dimensions.
+    pub(crate) fn validate_shape(&self, shape: (usize, usize, usize)) -> Result<(), Error> {
+        for (parameter_name, frame) in [
+            ("dark_frame", self.dark_frame()),
+            ("flat_field", self.flat_field()),
+        ] {
+            if let Some(frame) = frame {
+                if frame.dim() != shape {
+                    return Err(Error::InputError {
+                        parameter_name: parameter_name.to_string(),
+                        message: format!(
+                            "Calibration frame has dimensions {:?}, but the frames have dimensions {shape:?}",
+                            frame.dim()
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Correct a linear sample at the given `(y, x, channel)` index of the uncropped frame. The
+    /// shape must have been validated.
+    pub(crate) fn apply(&self, value: f32, index: (usize, usize, usize)) -> f32 {
+        let value = match &self.dark_frame {
+            Some(dark_frame) => value - dark_frame[index],
+            None => value,
+        };
+
+        match &self.flat_field {
+            Some(flat_field) => value / flat_field[index],
+            None => value,
+        }
+    }
+}
+
+/// Scale each channel of a flat field to a mean of `1.0`.
+///
+/// # Errors
+/// If the flat field holds values that are not finite, positive and non-zero.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+fn normalise_flat_field(mut flat_field: Array3<f32>) -> Result<Array3<f32>, Error> {
+    if !flat_field
+        .iter()
+        .all(|value| value.is_finite() && *value > 0.)
+    {
+        return Err(Error::InputError {
+            parameter_name: "flat_field".to_string(),
+            message: "Flat field must only hold finite, positive and non-zero values".to_string(),
+        });
+    }
+
+    for mut channel in flat_field.axis_iter_mut(Axis(2)) {
+        let mean = (channel.iter().map(|value| f64::from(*value)).sum::<f64>()
+            / channel.len() as f64) as f32;
+
+        Zip::from(&mut channel).par_for_each(|value| *value /= mean);
+    }
+
+    Ok(flat_field)
+}
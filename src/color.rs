@@ -0,0 +1,255 @@
+//! Color transforms for images holding HDR radiance or tone mapped values.
+//!
+//! Besides the sRGB transfer function, this converts linear radiance between the primaries of
+//! common color spaces with 3x3 matrices, see [`convert_color_space`]. Merged radiance has the
+//! primaries of the camera's output, which is Rec. 709 for most JPEG and PNG images.
+
+use crate::output::HDRImage;
+#[cfg(not(feature = "parallel"))]
+use crate::parallel::prelude::*;
+use crate::Error;
+use ndarray::{Axis, Zip};
+
+/// Encode a linear value with the piecewise sRGB transfer function. The value is clamped to
+/// `[0, 1]` first.
+#[must_use]
+pub fn encode_srgb(value: f32) -> f32 {
+    let value = value.clamp(0., 1.);
+
+    if value <= 0.003_130_8 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Decode an sRGB encoded value back to linear with the inverse of the piecewise sRGB transfer
+/// function. The value is clamped to `[0, 1]` first.
+#[must_use]
+pub fn decode_srgb(value: f32) -> f32 {
+    let value = value.clamp(0., 1.);
+
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Apply the sRGB transfer function to every color channel of the image, leaving the alpha
+/// channel untouched.
+///
+/// Values are clamped to `[0, 1]` before encoding, so HDR radiance must be tone mapped (or
+/// otherwise brought into that range) first. Encoding before tone mapping clips all highlights.
+/// The tone mapping functions in [`crate::tonemap`] already encode their output.
+#[must_use]
+pub fn to_srgb(hdr: &HDRImage) -> HDRImage {
+    let mut result = hdr.clone();
+    result.color_channels_mut().par_mapv_inplace(encode_srgb);
+
+    result
+}
+
+/// Convert sRGB encoded values in every color channel of the image back to linear values. This is
+/// the inverse of [`to_srgb`] for values in `[0, 1]`.
+#[must_use]
+pub fn from_srgb(hdr: &HDRImage) -> HDRImage {
+    let mut result = hdr.clone();
+    result.color_channels_mut().par_mapv_inplace(decode_srgb);
+
+    result
+}
+
+/// Linear color spaces that [`convert_color_space`] converts between, defined by the
+/// chromaticities of their primaries and white point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// sRGB and Rec. 709 primaries with a D65 white point
+    Rec709,
+    /// Rec. 2020 primaries with a D65 white point
+    Rec2020,
+    /// ACES AP1 primaries with the ACES white point
+    AcesCg,
+    /// DCI-P3 primaries with the DCI white point
+    DciP3,
+}
+
+impl ColorSpace {
+    /// `(x, y)` chromaticities of the red, green and blue primaries and of the white point.
+    fn chromaticities(self) -> [[f64; 2]; 4] {
+        match self {
+            Self::Rec709 => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06], D65],
+            Self::Rec2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046], D65],
+            Self::AcesCg => [
+                [0.713, 0.293],
+                [0.165, 0.830],
+                [0.128, 0.044],
+                [0.321_68, 0.337_67],
+            ],
+            Self::DciP3 => [
+                [0.680, 0.320],
+                [0.265, 0.690],
+                [0.150, 0.060],
+                [0.314, 0.351],
+            ],
+        }
+    }
+
+    /// Matrix converting linear values of the color space to CIE XYZ.
+    fn to_xyz(self) -> Matrix {
+        let [red, green, blue, white] = self.chromaticities().map(chromaticity_to_xyz);
+        let primaries = transpose([red, green, blue]);
+        let scale = multiply_vector(&invert(&primaries), white);
+
+        // Scale each primary so that equal values of all three produce the white point.
+        primaries.map(|row| [0, 1, 2].map(|column| row[column] * scale[column]))
+    }
+
+    fn white_point(self) -> [f64; 3] {
+        chromaticity_to_xyz(self.chromaticities()[3])
+    }
+}
+
+/// `(x, y)` chromaticity of the D65 white point.
+const D65: [f64; 2] = [0.3127, 0.3290];
+
+/// Bradford cone response matrix, used to adapt between white points.
+const BRADFORD: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+type Matrix = [[f64; 3]; 3];
+
+/// Compute the matrix converting linear values from one color space to another. Color spaces with
+/// different white points are adapted with the Bradford transform, so the white of `from` maps to
+/// the white of `to`.
+///
+/// returns: Row major matrix, which converts a column vector of linear `(r, g, b)` values of
+/// `from` to `to` when multiplied with it. It can be passed to [`apply_color_matrix`].
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn conversion_matrix(from: ColorSpace, to: ColorSpace) -> [[f32; 3]; 3] {
+    if from == to {
+        return [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+    }
+
+    let source_cone = multiply_vector(&BRADFORD, from.white_point());
+    let target_cone = multiply_vector(&BRADFORD, to.white_point());
+    let scale = [0, 1, 2].map(|row| {
+        [0, 1, 2].map(|column| {
+            if row == column {
+                target_cone[row] / source_cone[row]
+            } else {
+                0.
+            }
+        })
+    });
+    let adaptation = multiply(&invert(&BRADFORD), &multiply(&scale, &BRADFORD));
+
+    let matrix = multiply(
+        &invert(&to.to_xyz()),
+        &multiply(&adaptation, &from.to_xyz()),
+    );
+
+    matrix.map(|row| row.map(|value| value as f32))
+}
+
+/// Convert the linear radiance of the image from the primaries of one color space to another.
+/// This must be applied before tone mapping or sRGB encoding. See [`conversion_matrix`] for the
+/// conversion and [`apply_color_matrix`] for the requirements on the image.
+///
+/// # Errors
+///
+/// - If the image doesn't have three color channels
+pub fn convert_color_space(
+    hdr: &HDRImage,
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Result<HDRImage, Error> {
+    apply_color_matrix(hdr, conversion_matrix(from, to))
+}
+
+/// Multiply the color of every pixel by a 3x3 matrix, e.g. a camera specific color matrix. The
+/// alpha channel is left untouched. Values outside of the gamut of the target primaries become
+/// negative, and are kept so that the conversion can be inverted.
+///
+/// # Arguments
+///
+/// * `hdr`: Image with three color channels holding linear values
+/// * `matrix`: Row major matrix, multiplied with the pixels as column vectors of `(r, g, b)`
+///
+/// returns: `Result<HDRImage, Error>`
+///
+/// # Errors
+///
+/// - If the image doesn't have three color channels
+/// - If any of the coefficients of the matrix is not a finite number
+pub fn apply_color_matrix(hdr: &HDRImage, matrix: [[f32; 3]; 3]) -> Result<HDRImage, Error> {
+    let channels = hdr.get_channels() - usize::from(hdr.has_alpha());
+    if channels != 3 {
+        return Err(Error::InputError {
+            parameter_name: "hdr".to_string(),
+            message: format!(
+                "Color matrices only apply to images with three color channels, got {channels}"
+            ),
+        });
+    }
+
+    if !matrix.iter().flatten().all(|value| value.is_finite()) {
+        return Err(Error::InputError {
+            parameter_name: "matrix".to_string(),
+            message: format!("Color matrix coefficients must be finite numbers, got {matrix:?}"),
+        });
+    }
+
+    let mut result = hdr.clone();
+    Zip::from(result.color_channels_mut().lanes_mut(Axis(2))).par_for_each(|mut pixel| {
+        let color = [pixel[0], pixel[1], pixel[2]];
+
+        for (value, row) in pixel.iter_mut().zip(&matrix) {
+            *value = row[0] * color[0] + row[1] * color[1] + row[2] * color[2];
+        }
+    });
+
+    Ok(result)
+}
+
+/// CIE XYZ of the chromaticity `(x, y)` with a luminance of `1`.
+fn chromaticity_to_xyz([x, y]: [f64; 2]) -> [f64; 3] {
+    [x / y, 1., (1. - x - y) / y]
+}
+
+fn transpose(matrix: Matrix) -> Matrix {
+    [0, 1, 2].map(|row| [0, 1, 2].map(|column| matrix[column][row]))
+}
+
+fn multiply(left: &Matrix, right: &Matrix) -> Matrix {
+    [0, 1, 2].map(|row| {
+        [0, 1, 2].map(|column| {
+            (0..3)
+                .map(|index| left[row][index] * right[index][column])
+                .sum()
+        })
+    })
+}
+
+fn multiply_vector(matrix: &Matrix, vector: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+/// Invert a matrix with the adjugate. The matrices of this module are never singular.
+fn invert(matrix: &Matrix) -> Matrix {
+    let cofactor = |row: usize, column: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((column + 1) % 3, (column + 2) % 3);
+
+        matrix[r0][c0] * matrix[r1][c1] - matrix[r0][c1] * matrix[r1][c0]
+    };
+    let determinant = (0..3)
+        .map(|column| matrix[0][column] * cofactor(0, column))
+        .sum::<f64>();
+
+    [0, 1, 2].map(|row| [0, 1, 2].map(|column| cofactor(column, row) / determinant))
+}
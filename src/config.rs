@@ -0,0 +1,952 @@
+//! Configuration for the HDR merge
+
+use crate::calibration::Calibration;
+use crate::color::decode_srgb;
+use crate::denoise::validate_strength;
+use crate::output::count_non_finite;
+#[cfg(not(feature = "parallel"))]
+use crate::parallel::prelude::*;
+use crate::Error;
+use image::imageops::FilterType;
+use ndarray::{Array1, Array3};
+
+/// Per-channel coefficients applied while converting pixel values to radiance. Each channel of an
+/// image is divided by `exposure * gain * coefficient`, so a coefficient larger than `1.0` darkens
+/// that channel in the result and a coefficient smaller than `1.0` brightens it. This can be used
+/// to white balance the stack at merge time.
+///
+/// There is one coefficient per channel, in the channel order of the images, e.g. red, green and
+/// blue, or red, green, blue and near infrared for a multispectral stack. Grayscale images only
+/// use the first coefficient. Coefficients that are all equal, like the default ones, apply to
+/// stacks with any number of channels. Otherwise, the stack must have as many channels as there
+/// are coefficients.
+///
+/// All coefficients must be finite, positive and non-zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorCoefficients(Vec<f32>);
+
+impl ColorCoefficients {
+    /// Create new [`ColorCoefficients`] for RGB images. See [`ColorCoefficients::red`],
+    /// [`ColorCoefficients::green`] and [`ColorCoefficients::blue`] to read them back.
+    ///
+    /// # Errors
+    ///
+    /// - If any of the coefficients is not a finite, positive and non-zero number
+    pub fn new(red: f32, green: f32, blue: f32) -> Result<Self, Error> {
+        Self::with_channels(vec![red, green, blue])
+    }
+
+    /// Create new [`ColorCoefficients`] with one coefficient for each channel of the images, in
+    /// their channel order.
+    ///
+    /// # Errors
+    ///
+    /// - If no coefficient is provided
+    /// - If any of the coefficients is not a finite, positive and non-zero number
+    pub fn with_channels(coefficients: Vec<f32>) -> Result<Self, Error> {
+        let coefficients = Self(coefficients);
+        coefficients.validate()?;
+
+        Ok(coefficients)
+    }
+
+    /// Get the coefficient of each channel, in channel order
+    #[must_use]
+    pub fn as_slice(&self) -> &[f32] {
+        &self.0
+    }
+
+    /// Get the coefficient of the red channel of RGB images, or `None` if the coefficients don't
+    /// apply to RGB images
+    #[must_use]
+    pub fn red(&self) -> Option<f32> {
+        self.rgb().map(|[red, _, _]| red)
+    }
+
+    /// Get the coefficient of the green channel of RGB images, or `None` if the coefficients
+    /// don't apply to RGB images
+    #[must_use]
+    pub fn green(&self) -> Option<f32> {
+        self.rgb().map(|[_, green, _]| green)
+    }
+
+    /// Get the coefficient of the blue channel of RGB images, or `None` if the coefficients
+    /// don't apply to RGB images
+    #[must_use]
+    pub fn blue(&self) -> Option<f32> {
+        self.rgb().map(|[_, _, blue]| blue)
+    }
+
+    /// Coefficients of the red, green and blue channels, if the coefficients apply to RGB images.
+    fn rgb(&self) -> Option<[f32; 3]> {
+        match self.for_channels(3).ok()?[..] {
+            [red, green, blue] => Some([red, green, blue]),
+            _ => None,
+        }
+    }
+
+    /// Coefficients of each channel of images with `channels` channels.
+    ///
+    /// # Errors
+    ///
+    /// - If the coefficients differ from each other and there is more than one channel, but not
+    ///   as many channels as coefficients
+    #[allow(clippy::float_cmp)]
+    pub(crate) fn for_channels(&self, channels: usize) -> Result<Vec<f32>, Error> {
+        let first = self.0.first().copied().unwrap_or(1.);
+
+        if channels == self.0.len() {
+            Ok(self.0.clone())
+        } else if channels == 1 || self.0.iter().all(|coefficient| *coefficient == first) {
+            Ok(vec![first; channels])
+        } else {
+            Err(Error::InputError {
+                parameter_name: "coefficients".to_string(),
+                message: format!(
+                    "Got {} coefficients, which don't apply to images with {channels} channels",
+                    self.0.len()
+                ),
+            })
+        }
+    }
+
+    /// Create [`ColorCoefficients`] that white balance a scene lit by a black body of the given
+    /// color temperature, i.e. that make such light neutral. See
+    /// [`ColorCoefficients::from_temperature_and_tint`].
+    ///
+    /// # Errors
+    ///
+    /// - If `kelvin` is not between [`MIN_TEMPERATURE`] and [`MAX_TEMPERATURE`]
+    /// - If the color of the light is outside of the sRGB gamut, which happens below about 1900 K
+    pub fn from_temperature(kelvin: f32) -> Result<Self, Error> {
+        Self::from_temperature_and_tint(kelvin, 0.)
+    }
+
+    /// Create [`ColorCoefficients`] that white balance a scene lit by light of the given color
+    /// temperature and tint, i.e. that make such light neutral. Lower temperatures are warmer, so
+    /// balancing to them makes the result cooler, e.g. `3200.0` for tungsten light and `5500.0`
+    /// for daylight.
+    ///
+    /// The chromaticity of a black body at `kelvin` is approximated with the cubic splines of
+    /// Kim et al., and moved `tint` away from the Planckian locus along its normal in the CIE 1960
+    /// UCS diagram. The resulting color is converted to linear sRGB, and the coefficients are its
+    /// channels relative to green. This assumes the pixel values of the images are linear sRGB,
+    /// and that [`ColorCoefficients::default`] is balanced for the D65 white point of sRGB. Since
+    /// D65 lies slightly above the Planckian locus, even `6504.0` K is not exactly neutral.
+    ///
+    /// # Arguments
+    ///
+    /// * `kelvin`: Color temperature of the light, between [`MIN_TEMPERATURE`] and
+    ///   [`MAX_TEMPERATURE`]
+    /// * `tint`: Distance from the Planckian locus, usually called `Duv`. Positive values
+    ///   describe greenish light, so balancing to them makes the result more magenta, and
+    ///   negative values describe magenta light. Common light sources lie within `±0.02`.
+    ///
+    /// returns: `Result<ColorCoefficients, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If `kelvin` is not between [`MIN_TEMPERATURE`] and [`MAX_TEMPERATURE`]
+    /// - If `tint` is not finite
+    /// - If the color of the light is outside of the sRGB gamut, e.g. below about 1900 K or with a
+    ///   large tint
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_temperature_and_tint(kelvin: f32, tint: f32) -> Result<Self, Error> {
+        if !(MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&kelvin) {
+            return Err(Error::InputError {
+                parameter_name: "kelvin".to_string(),
+                message: format!(
+                    "Color temperature must be between {MIN_TEMPERATURE} and {MAX_TEMPERATURE} K, got {kelvin}"
+                ),
+            });
+        }
+
+        if !tint.is_finite() {
+            return Err(Error::InputError {
+                parameter_name: "tint".to_string(),
+                message: format!("Tint must be a finite number, got {tint}"),
+            });
+        }
+
+        let kelvin = f64::from(kelvin);
+        let (u, v) = planckian_uv(kelvin);
+
+        // The normal of the locus, pointing towards green, from the direction it runs in.
+        let step = 1.;
+        let (u_low, v_low) = planckian_uv((kelvin - step).max(f64::from(MIN_TEMPERATURE)));
+        let (u_high, v_high) = planckian_uv((kelvin + step).min(f64::from(MAX_TEMPERATURE)));
+        let (du, dv) = (u_high - u_low, v_high - v_low);
+        let length = du.hypot(dv);
+        let (u, v) = (
+            u + f64::from(tint) * dv / length,
+            v - f64::from(tint) * du / length,
+        );
+
+        let (red, green, blue) = linear_srgb_from_uv(u, v);
+
+        Self::new((red / green) as f32, 1., (blue / green) as f32).map_err(|_| Error::InputError {
+            parameter_name: "kelvin".to_string(),
+            message: format!(
+                "Light of {kelvin} K with a tint of {tint} is outside of the sRGB gamut"
+            ),
+        })
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.0.is_empty() {
+            return Err(Error::InputError {
+                parameter_name: "coefficients".to_string(),
+                message: "At least one coefficient is required".to_string(),
+            });
+        }
+
+        for (channel, value) in self.0.iter().enumerate() {
+            if !value.is_finite() || *value <= 0. {
+                return Err(Error::InputError {
+                    parameter_name: "coefficients".to_string(),
+                    message: format!(
+                        "Coefficient for channel {channel} must be a valid positive and non-zero floating point number, got {value}"
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lowest color temperature, in Kelvin, accepted by [`ColorCoefficients::from_temperature`].
+pub const MIN_TEMPERATURE: f32 = 1667.;
+
+/// Highest color temperature, in Kelvin, accepted by [`ColorCoefficients::from_temperature`].
+pub const MAX_TEMPERATURE: f32 = 25000.;
+
+/// CIE 1960 UCS chromaticity of a black body at the given temperature, using the approximation
+/// of the Planckian locus in CIE 1931 xy by Kim et al., valid from 1667 K to 25000 K.
+fn planckian_uv(kelvin: f64) -> (f64, f64) {
+    let (t, t2, t3) = (kelvin, kelvin * kelvin, kelvin * kelvin * kelvin);
+
+    let x = if kelvin <= 4000. {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_037_9e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if kelvin <= 2222. {
+        -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+    } else if kelvin <= 4000. {
+        -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+    };
+
+    let denominator = -2. * x + 12. * y + 3.;
+
+    (4. * x / denominator, 6. * y / denominator)
+}
+
+/// Linear sRGB color of the given CIE 1960 UCS chromaticity, with a luminance of `1.0`.
+fn linear_srgb_from_uv(u: f64, v: f64) -> (f64, f64, f64) {
+    let denominator = 2. * u - 8. * v + 4.;
+    let (x, y) = (3. * u / denominator, 2. * v / denominator);
+    let (tristimulus_x, tristimulus_z) = (x / y, (1. - x - y) / y);
+
+    (
+        3.240_454_2 * tristimulus_x - 1.537_138_5 - 0.498_531_4 * tristimulus_z,
+        -0.969_266 * tristimulus_x + 1.876_010_8 + 0.041_556 * tristimulus_z,
+        0.055_643_4 * tristimulus_x - 0.204_025_9 + 1.057_225_2 * tristimulus_z,
+    )
+}
+
+impl Default for ColorCoefficients {
+    /// A coefficient of `1.0` for every channel, which leaves the radiance untouched.
+    fn default() -> Self {
+        Self(vec![1.; 3])
+    }
+}
+
+/// Sensor noise model used to weight the samples of each pixel, following the noise model of
+/// [Noise-Aware Merging of High Dynamic Range Image Stacks without Camera Calibration](https://www.cl.cam.ac.uk/research/rainbow/projects/noise-aware-merging/2020-ppne-mle.pdf).
+///
+/// The variance of a normalised pixel value `y` captured with gain `g` is modelled as
+/// `photon_scale * g * y + read_noise²`. The first term is the photon (shot) noise, the paper's
+/// signal dependent variance term, which grows linearly with the signal and is amplified by the
+/// gain. The second term is the signal independent read noise, the paper's read and ADC noise
+/// terms combined, expressed as a standard deviation in normalised pixel values.
+///
+/// Each sample is weighted by the inverse variance of its radiance estimate, which is
+/// proportional to `exposure / (1 + read_noise² / (photon_scale * g * y))`. This is the squared
+/// signal to noise ratio of the sample up to a factor shared by every frame of the pixel, so the
+/// weighted average is the maximum likelihood estimate of the radiance. Without read noise, this
+/// is just `exposure`, the Poisson Photon Noise Estimator. Read noise lowers the weight of
+/// dark samples, and does so more for short exposures and low gains. Only the ratio between
+/// `read_noise²` and `photon_scale` matters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseModel {
+    /// Standard deviation of the signal independent noise, in normalised pixel values. For
+    /// example, a read noise of 2 DN on a 12-bit sensor is `2 / 4095`.
+    pub read_noise: f32,
+    /// Variance of the photon noise per unit of normalised signal at unity gain, i.e. the inverse
+    /// of the number of photo-electrons that make up the full normalised range.
+    pub photon_scale: f32,
+}
+
+impl NoiseModel {
+    /// Create new [`NoiseModel`]
+    ///
+    /// # Errors
+    ///
+    /// - If `read_noise` is not a finite non-negative number
+    /// - If `photon_scale` is not a finite, positive and non-zero number
+    pub fn new(read_noise: f32, photon_scale: f32) -> Result<Self, Error> {
+        let noise_model = Self {
+            read_noise,
+            photon_scale,
+        };
+        noise_model.validate()?;
+
+        Ok(noise_model)
+    }
+
+    pub(crate) fn validate(self) -> Result<(), Error> {
+        if !self.read_noise.is_finite() || self.read_noise < 0. {
+            return Err(Error::InputError {
+                parameter_name: "read_noise".to_string(),
+                message: format!(
+                    "Read noise must be a valid non-negative floating point number, got {}",
+                    self.read_noise
+                ),
+            });
+        }
+
+        if !self.photon_scale.is_finite() || self.photon_scale <= 0. {
+            return Err(Error::InputError {
+                parameter_name: "photon_scale".to_string(),
+                message: format!(
+                    "Photon scale must be a valid positive and non-zero floating point number, got {}",
+                    self.photon_scale
+                ),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for NoiseModel {
+    /// Pure photon noise, which weights every sample by its exposure.
+    fn default() -> Self {
+        Self {
+            read_noise: 0.,
+            photon_scale: 1.,
+        }
+    }
+}
+
+/// Radial vignetting correction, multiplying each pixel by `1 + k1 * r^2 + k2 * r^4 + k3 * r^6`,
+/// where `r` is the distance of the pixel from the image center, normalised so that `r` is `1.0`
+/// in the corners. These are the coefficients of common lens profiles. The default coefficients
+/// are all zero, which leaves the images untouched.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VignettingCorrection {
+    /// Coefficient of `r^2`
+    pub k1: f32,
+    /// Coefficient of `r^4`
+    pub k2: f32,
+    /// Coefficient of `r^6`
+    pub k3: f32,
+}
+
+impl VignettingCorrection {
+    /// Create new [`VignettingCorrection`]
+    ///
+    /// # Errors
+    ///
+    /// - If any of the coefficients is not a finite number
+    /// - If the correction is not positive everywhere between the center and the corners
+    pub fn new(k1: f32, k2: f32, k3: f32) -> Result<Self, Error> {
+        let correction = Self { k1, k2, k3 };
+        correction.validate()?;
+
+        Ok(correction)
+    }
+
+    /// Correction factor at the given squared normalised radius.
+    pub(crate) fn factor(self, radius_squared: f32) -> f32 {
+        1. + radius_squared * (self.k1 + radius_squared * (self.k2 + radius_squared * self.k3))
+    }
+
+    /// Whether the correction leaves the images untouched.
+    pub(crate) fn is_identity(self) -> bool {
+        self == Self::default()
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn validate(self) -> Result<(), Error> {
+        if ![self.k1, self.k2, self.k3].iter().all(|k| k.is_finite()) {
+            return Err(Error::InputError {
+                parameter_name: "vignetting".to_string(),
+                message: format!("Vignetting coefficients must be finite numbers, got {self:?}"),
+            });
+        }
+
+        // The polynomial is sampled densely enough to catch any sign change in the unit range
+        // for practical lens profiles.
+        if (0..=64).any(|step| self.factor((step as f32 / 64.).powi(2)) <= 0.) {
+            return Err(Error::InputError {
+                parameter_name: "vignetting".to_string(),
+                message: format!(
+                    "Vignetting correction must be positive from the center to the corners, got {self:?}"
+                ),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// How infinite and NaN values in the merged radiance are handled, see
+/// [`MergeConfig::non_finite`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NonFiniteHandling {
+    /// Leave non-finite values in the result.
+    #[default]
+    Keep,
+    /// Replace positive infinity with the given value, and negative infinity and NaN with `0`.
+    Clamp(f32),
+    /// Fail with [`Error::NonFinite`], counting the pixels with non-finite values.
+    Error,
+}
+
+impl NonFiniteHandling {
+    pub(crate) fn validate(self) -> Result<(), Error> {
+        if let Self::Clamp(max) = self {
+            if !max.is_finite() {
+                return Err(Error::InputError {
+                    parameter_name: "non_finite".to_string(),
+                    message: format!("Clamp value must be a finite number, got {max}"),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle the non-finite values of a merged `(height, width, channels)` buffer.
+    pub(crate) fn apply(self, buffer: &mut Array3<f32>) -> Result<(), Error> {
+        match self {
+            Self::Keep => Ok(()),
+            Self::Clamp(max) => {
+                buffer.par_mapv_inplace(|value| match value {
+                    f32::INFINITY => max,
+                    value if value.is_finite() => value,
+                    _ => 0.,
+                });
+
+                Ok(())
+            }
+            Self::Error => match count_non_finite(buffer) {
+                0 => Ok(()),
+                count => Err(Error::NonFinite(count)),
+            },
+        }
+    }
+}
+
+/// Camera settings that make up the effective exposure of a frame, see
+/// [`MergeConfig::exposure_model`]. The light that reaches the sensor is proportional to the
+/// exposure time and to `1 / N^2` for an f-number of `N`, and the signal recorded from it is
+/// proportional to the gain. Each pixel is divided by its effective exposure and the coefficient
+/// of its channel, see [`ColorCoefficients`], to convert it to radiance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExposureModel {
+    /// The effective exposure is `exposure * gain`, for stacks bracketed by exposure time or
+    /// gain at a constant aperture.
+    #[default]
+    TimeAndGain,
+    /// The effective exposure is `exposure * gain / N^2`, for stacks bracketed by aperture
+    /// instead of, or in addition to, the exposure time. The merged radiance is relative to an
+    /// f/1 aperture, i.e. `N^2` times that of [`ExposureModel::TimeAndGain`] when the aperture
+    /// is constant. Every frame must have a known aperture, see
+    /// [`crate::input::HDRInput::get_aperture`].
+    TimeGainAndAperture,
+}
+
+/// How frames with extreme clipping are handled, see [`MergeConfig::max_clipped_fraction`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExtremeFrameHandling {
+    /// Merge them like every other frame. They are only reported, see
+    /// [`crate::output::MergeReport::clipped_frames`].
+    #[default]
+    Keep,
+    /// Leave them out of the merge. The inputs are modified in place, like when they are sorted.
+    /// Stacks with fewer than two frames left are rejected with an error.
+    Drop,
+}
+
+/// Transfer function the pixel values of the inputs are encoded with, see
+/// [`MergeConfig::input_encoding`]. The merge assumes pixel values proportional to the light that
+/// reached the sensor, so encoded values are linearized before they are converted to radiance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InputEncoding {
+    /// Pixel values are already linear, e.g. RAW files, linear TIFFs and floating point images.
+    #[default]
+    Linear,
+    /// Pixel values are encoded with the sRGB transfer function, like most JPEG and PNG images.
+    /// They are decoded with [`crate::color::decode_srgb`], which clamps them to `[0, 1]`.
+    Srgb,
+    /// Pixel values are encoded with a pure power law, and are decoded as `value^gamma`, e.g.
+    /// `2.2`. Negative values are treated as `0`.
+    Gamma(f32),
+}
+
+impl InputEncoding {
+    pub(crate) fn validate(self) -> Result<(), Error> {
+        if let Self::Gamma(gamma) = self {
+            if !gamma.is_finite() || gamma <= 0. {
+                return Err(Error::InputError {
+                    parameter_name: "input_encoding".to_string(),
+                    message: format!(
+                        "Gamma must be a valid positive and non-zero floating point number, got {gamma}"
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert an encoded, normalised pixel value to a linear one.
+    #[must_use]
+    pub fn linearize(self, value: f32) -> f32 {
+        match self {
+            Self::Linear => value,
+            Self::Srgb => decode_srgb(value),
+            Self::Gamma(gamma) => value.max(0.).powf(gamma),
+        }
+    }
+}
+
+/// Function of a normalised pixel value `y` in `[0, 1]` that scales the weight of each sample on
+/// top of the [`NoiseModel`], see [`MergeConfig::weight_function`]. Functions other than
+/// [`WeightFunction::Uniform`] favour mid-tones over samples close to black or saturation.
+///
+/// The value of the function is never less than a tiny floor, so that pixels close to black or
+/// saturation in every frame still get a defined radiance. Samples below the noise floor or at or
+/// above the saturation threshold are penalised as usual.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WeightFunction {
+    /// `1`, weighting every sample between the noise floor and the saturation threshold equally.
+    /// This is the Poisson Photon Noise Estimator, where the weight only depends on the exposure
+    /// and the noise model.
+    #[default]
+    Uniform,
+    /// `1 - |2y - 1|`, the hat function of Debevec and Malik, peaking at mid-grey and falling
+    /// linearly to zero at black and white.
+    Triangle,
+    /// `exp(-(y - 0.5)² / (2 * 0.2²))`, a bell curve centered on mid-grey, as used by exposure
+    /// fusion to measure how well exposed a pixel is.
+    Gaussian,
+    /// `1 - (2y - 1)¹²`, flat over most of the range and only falling off close to black and
+    /// white, as proposed by Reinhard et al.
+    Plateau,
+}
+
+impl WeightFunction {
+    /// Smallest value of the function, see [`WeightFunction`].
+    pub(crate) const MIN_WEIGHT: f32 = 1e-4;
+
+    /// Evaluate the function for a normalised pixel value, which is clamped to `[0, 1]` first.
+    #[must_use]
+    pub fn weight(self, value: f32) -> f32 {
+        let value = value.clamp(0., 1.);
+        let centered = 2. * value - 1.;
+
+        let weight = match self {
+            Self::Uniform => return 1.,
+            Self::Triangle => 1. - centered.abs(),
+            Self::Gaussian => (-(value - 0.5) * (value - 0.5) / (2. * 0.2 * 0.2)).exp(),
+            Self::Plateau => 1. - centered.powi(12),
+        };
+
+        weight.max(Self::MIN_WEIGHT)
+    }
+}
+
+/// How the alpha channels of the frames are combined, see [`MergeConfig::alpha`].
+///
+/// Frames whose image has no alpha channel, or a fully opaque one, count as opaque. In every mode
+/// but [`AlphaMode::Discard`], the samples of each pixel are weighted by their alpha, so that
+/// transparent regions, e.g. areas masked out of a frame, don't contribute to the radiance, and
+/// the merged radiance is the radiance of the opaque parts of the stack. Pixels that are fully
+/// transparent in every frame have a radiance of `0`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Drop the alpha channel. Frames that are not fully opaque are merged as if they were
+    /// composited over black, see [`crate::input::HDRInput::with_image`].
+    #[default]
+    Discard,
+    /// Keep the smallest alpha of the stack, so that a pixel is only opaque if it is opaque in
+    /// every frame, e.g. to intersect the valid regions of masked frames.
+    Min,
+    /// Keep the mean alpha of the stack, e.g. to soften the edges of masks that differ between
+    /// frames.
+    Mean,
+}
+
+/// Default value of [`MergeConfig::saturation_threshold`].
+pub const DEFAULT_SATURATION_THRESHOLD: f32 = 0.98;
+
+/// Default value of [`MergeConfig::noise_floor`].
+pub const DEFAULT_NOISE_FLOOR: f32 = 0.02;
+
+/// Default value of [`MergeConfig::deghost_threshold`].
+pub const DEFAULT_DEGHOST_THRESHOLD: f32 = 0.5;
+
+/// Default value of [`MergeConfig::max_clipped_fraction`].
+pub const DEFAULT_MAX_CLIPPED_FRACTION: f32 = 0.5;
+
+/// Options controlling how a stack of images is merged. The default configuration is what
+/// [`crate::hdr_merge_images`] uses.
+#[derive(Clone, Debug)]
+pub struct MergeConfig {
+    /// Per-channel coefficients applied while converting pixel values to radiance.
+    pub coefficients: ColorCoefficients,
+    /// Camera settings that make up the effective exposure of each frame. This applies to
+    /// [`crate::hdr_merge_images_with_config`], the merges built on it, and [`crate::gradient`].
+    pub exposure_model: ExposureModel,
+    /// Transfer function the pixel values of the inputs are encoded with. Values are linearized
+    /// while they are converted to radiance, and saturation, the noise floor, the noise model and
+    /// the weight function still apply to the encoded values. The default assumes linear inputs,
+    /// which biases the radiance of gamma encoded images like JPEGs, so set this to
+    /// [`InputEncoding::Srgb`] when merging them.
+    pub input_encoding: InputEncoding,
+    /// Whether to suppress ghosts caused by subjects moving between exposures. Pixels whose
+    /// radiance varies too much across the stack are taken from the reference frame (see
+    /// [`crate::input::HDRInputList::reference_index`]) instead of being averaged.
+    pub deghost: bool,
+    /// Maximum relative standard deviation (standard deviation divided by mean) of a pixel's
+    /// luminance across the stack before the pixel is considered a ghost. Lower values detect more
+    /// ghosts, at the cost of treating more noise as motion. Only used when `deghost` is enabled.
+    pub deghost_threshold: f32,
+    /// Normalised pixel values at or above this are considered saturated. Saturated samples only
+    /// contribute a tiny fraction of their usual weight, so that highlights clipped in long
+    /// exposures are recovered from shorter ones. Values above `1.0` disable this.
+    pub saturation_threshold: f32,
+    /// When set, per-channel saturation thresholds that replace `saturation_threshold`, for
+    /// sensors whose channels clip at different levels, e.g. `vec![0.98, 1.0, 0.95]` for a
+    /// sensor whose red and blue channels clip before green. There must be one threshold per
+    /// channel of the stack, and each must be greater than the noise floor.
+    pub channel_saturation_thresholds: Option<Vec<f32>>,
+    /// Normalised pixel values below this are considered to be lost in noise, and only contribute
+    /// a tiny fraction of their usual weight. `0.0` disables this.
+    pub noise_floor: f32,
+    /// Largest share of the pixels of a frame, between `0.0` and `1.0`, that may be clipped,
+    /// i.e. saturated in every channel or below the noise floor in every channel. Frames with
+    /// more clipped pixels carry little information and often point at a mis-bracketed stack.
+    /// They are listed in [`crate::output::MergeReport::clipped_frames`], logged with the
+    /// `tracing` feature, and handled according to `extreme_frames`.
+    pub max_clipped_fraction: f32,
+    /// How frames with more than `max_clipped_fraction` of their pixels clipped are handled.
+    pub extreme_frames: ExtremeFrameHandling,
+    /// Whether to sort the inputs by exposure, shortest first, before merging. The inputs are
+    /// sorted in place, so any per-input results, like alignment shifts, follow the sorted order.
+    /// The merged radiance doesn't depend on the order of the inputs, but sorting makes it
+    /// reproducible bit for bit regardless of the order in which files were supplied.
+    pub sort_by_exposure: bool,
+    /// When set, stacks whose frames differ in size, e.g. because the camera crops at some ISOs,
+    /// are resized to the smallest width and height of the stack with this filter before
+    /// merging. See [`crate::input::HDRInputList::resize_to_smallest`]. When `None`, such stacks
+    /// are rejected with an error.
+    pub resize_filter: Option<FilterType>,
+    /// Sensor noise model used to weight the samples of each pixel.
+    pub noise_model: NoiseModel,
+    /// Function of the pixel value that scales the weight of each sample, favouring mid-tones
+    /// over samples close to black or saturation. The default leaves the weights of the noise
+    /// model untouched.
+    pub weight_function: WeightFunction,
+    /// When set, hot and dead pixels are corrected in every frame before merging. A pixel whose
+    /// normalised value differs from the median of its 3x3 neighbourhood by more than this
+    /// threshold is replaced with that median. See [`crate::input::HDRInput::correct_hot_pixels`].
+    pub hot_pixel_threshold: Option<f32>,
+    /// Radial vignetting correction applied to every frame while converting it to radiance.
+    /// Saturation and the noise floor are still detected on the uncorrected pixel values.
+    pub vignetting: VignettingCorrection,
+    /// Dark frame and flat field applied to every frame while converting it to radiance, after
+    /// its pixel values are linearized. The calibration frames must have the dimensions of the
+    /// frames, after resizing if [`MergeConfig::resize_filter`] is set. Saturation and the noise
+    /// floor are still detected on the uncorrected pixel values. See [`crate::calibration`].
+    pub calibration: Calibration,
+    /// When set, only the `(x, y, width, height)` region of the frames is merged, and the result
+    /// has the dimensions of that region. Frames are cropped before they are converted to
+    /// radiance, after resizing if [`MergeConfig::resize_filter`] is set. See
+    /// [`crate::input::HDRInput::crop`].
+    pub crop: Option<(u32, u32, u32, u32)>,
+    /// When set, stacks whose longest effective exposure (`exposure * gain`) is less than this
+    /// many times the shortest one are rejected with an error that includes the exposure range.
+    /// Merging frames taken with the same exposure only averages them, which usually means the
+    /// shots were not bracketed. Use [`crate::input::HDRInputList::exposure_range`] to inspect
+    /// the range without failing.
+    pub min_exposure_ratio: Option<f32>,
+    /// When set, the value each pixel is divided by to convert it to radiance, i.e.
+    /// `exposure * gain * coefficient`, is raised to at least this. Frames with a tiny effective
+    /// exposure, e.g. because of a mistyped exposure override or a misreported EXIF value,
+    /// otherwise turn their noise into huge radiance values that dominate the pixels where
+    /// they are the only well exposed frame. Frames below the minimum are merged as if they
+    /// were exposed longer, so set it below the shortest exposure of any sane stack. By default
+    /// no minimum is applied.
+    pub min_scaling_factor: Option<f32>,
+    /// How infinite and NaN values in the merged radiance are handled. Radiance can only be
+    /// non-finite when the inputs hold non-finite values, e.g. floating point images, or when
+    /// `exposure * gain * coefficient` is so small that dividing by it overflows. The gradient
+    /// domain merge of [`crate::gradient`] can also overflow while exponentiating the log
+    /// radiance. Keeping them is the default, see [`crate::output::HDRImage::count_non_finite`]
+    /// to detect them.
+    pub non_finite: NonFiniteHandling,
+    /// When set, the merged radiance is denoised with an edge preserving filter in log radiance
+    /// space, with this strength. Shadows are smoothed more than highlights. This is expensive,
+    /// and only applies to [`crate::hdr_merge_images_with_config`] and the merges built on it.
+    /// See [`crate::denoise::denoise`] for the meaning of the strength.
+    pub denoise_strength: Option<f32>,
+    /// How the alpha channels of the frames are combined. In every mode but
+    /// [`AlphaMode::Discard`], the merged image gets an alpha channel after its color channels,
+    /// see [`crate::output::HDRImage::has_alpha`]. This only applies to
+    /// [`crate::hdr_merge_images_with_config`] and the merges built on it.
+    pub alpha: AlphaMode,
+    /// Whether to multiply the merged color channels by the combined alpha. By default the
+    /// merged image has straight, i.e. not premultiplied, alpha. Only used when `alpha` is not
+    /// [`AlphaMode::Discard`].
+    pub premultiply_alpha: bool,
+}
+
+impl MergeConfig {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        self.coefficients.validate()?;
+        self.input_encoding.validate()?;
+        self.noise_model.validate()?;
+        self.vignetting.validate()?;
+        self.non_finite.validate()?;
+
+        if !self.deghost_threshold.is_finite() || self.deghost_threshold <= 0. {
+            return Err(Error::InputError {
+                parameter_name: "deghost_threshold".to_string(),
+                message:
+                    "Deghost threshold must be a valid positive and non-zero floating point number"
+                        .to_string(),
+            });
+        }
+
+        if !self.noise_floor.is_finite() || self.noise_floor < 0. {
+            return Err(Error::InputError {
+                parameter_name: "noise_floor".to_string(),
+                message: "Noise floor must be a valid non-negative floating point number"
+                    .to_string(),
+            });
+        }
+
+        if !(0. ..=1.).contains(&self.max_clipped_fraction) {
+            return Err(Error::InputError {
+                parameter_name: "max_clipped_fraction".to_string(),
+                message: format!(
+                    "Maximum clipped fraction must be between 0 and 1, got {}",
+                    self.max_clipped_fraction
+                ),
+            });
+        }
+
+        if let Some(threshold) = self.hot_pixel_threshold {
+            if !threshold.is_finite() || threshold <= 0. {
+                return Err(Error::InputError {
+                    parameter_name: "hot_pixel_threshold".to_string(),
+                    message: "Hot pixel threshold must be a valid positive and non-zero floating point number".to_string(),
+                });
+            }
+        }
+
+        if let Some(strength) = self.denoise_strength {
+            validate_strength(strength)?;
+        }
+
+        if let Some(ratio) = self.min_exposure_ratio {
+            if !ratio.is_finite() || ratio < 1. {
+                return Err(Error::InputError {
+                    parameter_name: "min_exposure_ratio".to_string(),
+                    message: format!(
+                        "Minimum exposure ratio must be a finite number of at least 1, got {ratio}"
+                    ),
+                });
+            }
+        }
+
+        if let Some(factor) = self.min_scaling_factor {
+            if !factor.is_finite() || factor <= 0. {
+                return Err(Error::InputError {
+                    parameter_name: "min_scaling_factor".to_string(),
+                    message: format!(
+                        "Minimum scaling factor must be a valid positive and non-zero floating point number, got {factor}"
+                    ),
+                });
+            }
+        }
+
+        if self.saturation_threshold.is_nan() || self.saturation_threshold <= self.noise_floor {
+            return Err(Error::InputError {
+                parameter_name: "saturation_threshold".to_string(),
+                message: "Saturation threshold must be greater than the noise floor".to_string(),
+            });
+        }
+
+        if let Some(thresholds) = &self.channel_saturation_thresholds {
+            if let Some((channel, threshold)) = thresholds
+                .iter()
+                .enumerate()
+                .find(|(_, threshold)| threshold.is_nan() || **threshold <= self.noise_floor)
+            {
+                return Err(Error::InputError {
+                    parameter_name: "channel_saturation_thresholds".to_string(),
+                    message: format!(
+                        "Saturation threshold of channel {channel} must be greater than the noise floor, got {threshold}"
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saturation threshold of each channel of a stack with the given number of channels.
+    ///
+    /// # Errors
+    /// If per-channel thresholds are set, but not one per channel.
+    pub(crate) fn saturation_thresholds(&self, channels: usize) -> Result<Array1<f32>, Error> {
+        match &self.channel_saturation_thresholds {
+            Some(thresholds) if thresholds.len() == channels => {
+                Ok(Array1::from_vec(thresholds.clone()))
+            }
+            Some(thresholds) => Err(Error::InputError {
+                parameter_name: "channel_saturation_thresholds".to_string(),
+                message: format!(
+                    "Got {} saturation thresholds, which don't apply to images with {channels} channels",
+                    thresholds.len()
+                ),
+            }),
+            None => Ok(Array1::from_elem(channels, self.saturation_threshold)),
+        }
+    }
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        Self {
+            coefficients: ColorCoefficients::default(),
+            exposure_model: ExposureModel::default(),
+            input_encoding: InputEncoding::default(),
+            deghost: false,
+            deghost_threshold: DEFAULT_DEGHOST_THRESHOLD,
+            saturation_threshold: DEFAULT_SATURATION_THRESHOLD,
+            channel_saturation_thresholds: None,
+            noise_floor: DEFAULT_NOISE_FLOOR,
+            max_clipped_fraction: DEFAULT_MAX_CLIPPED_FRACTION,
+            extreme_frames: ExtremeFrameHandling::default(),
+            sort_by_exposure: false,
+            resize_filter: None,
+            noise_model: NoiseModel::default(),
+            weight_function: WeightFunction::default(),
+            hot_pixel_threshold: None,
+            vignetting: VignettingCorrection::default(),
+            calibration: Calibration::default(),
+            crop: None,
+            min_exposure_ratio: None,
+            min_scaling_factor: None,
+            non_finite: NonFiniteHandling::default(),
+            denoise_strength: None,
+            alpha: AlphaMode::default(),
+            premultiply_alpha: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEIGHT_FUNCTIONS: [WeightFunction; 3] = [
+        WeightFunction::Triangle,
+        WeightFunction::Gaussian,
+        WeightFunction::Plateau,
+    ];
+
+    /// Normalised pixel values from `0.0` to `1.0` in steps of `0.05`
+    #[allow(clippy::cast_precision_loss)]
+    fn gradient() -> Vec<f32> {
+        (0..=20).map(|step| step as f32 / 20.).collect()
+    }
+
+    #[test]
+    fn uniform_weights_every_value_equally() {
+        assert!(gradient()
+            .into_iter()
+            .all(|value| (WeightFunction::Uniform.weight(value) - 1.).abs() < f32::EPSILON));
+    }
+
+    #[test]
+    fn weight_functions_peak_at_mid_grey() {
+        for function in WEIGHT_FUNCTIONS {
+            let weights = gradient()
+                .into_iter()
+                .map(|value| function.weight(value))
+                .collect::<Vec<f32>>();
+
+            assert!((function.weight(0.5) - 1.).abs() < 1e-6, "{function:?}");
+            assert!(
+                weights[..=10].windows(2).all(|pair| pair[0] <= pair[1]),
+                "{function:?} must rise towards mid-grey, got {weights:?}"
+            );
+            assert!(
+                weights[10..].windows(2).all(|pair| pair[0] >= pair[1]),
+                "{function:?} must fall towards white, got {weights:?}"
+            );
+            // Symmetric around mid-grey
+            for (dark, bright) in weights.iter().zip(weights.iter().rev()) {
+                assert!((dark - bright).abs() < 1e-5, "{function:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn weight_functions_never_drop_below_the_floor() {
+        for function in WEIGHT_FUNCTIONS {
+            for value in [-1., 0., 1., 2.] {
+                assert!(
+                    function.weight(value) >= WeightFunction::MIN_WEIGHT,
+                    "{function:?} at {value}"
+                );
+            }
+        }
+
+        // Black and white get the floor from the functions that fall to zero there.
+        for function in [WeightFunction::Triangle, WeightFunction::Plateau] {
+            for value in [0., 1.] {
+                assert!(
+                    (function.weight(value) - WeightFunction::MIN_WEIGHT).abs() < 1e-6,
+                    "{function:?} at {value}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn plateau_favours_mid_tones_less_than_triangle() {
+        for value in gradient() {
+            assert!(
+                WeightFunction::Plateau.weight(value) >= WeightFunction::Triangle.weight(value),
+                "At {value}"
+            );
+        }
+
+        // Flat over most of the range
+        assert!(WeightFunction::Plateau.weight(0.2) > 0.99);
+        assert!(WeightFunction::Triangle.weight(0.2) < 0.5);
+    }
+}
@@ -0,0 +1,513 @@
+//! An implementation of HDR merging with camera response recovery as introduced in
+//! [Recovering High Dynamic Range Radiance Maps from Photographs](https://www.pauldebevec.com/Research/HDR/debevec-siggraph97.pdf)
+//! by Debevec and Malik.
+//!
+//! Unlike the poisson estimate, this doesn't assume that pixel values are linear in radiance. The
+//! camera response, i.e. the mapping from pixel values to log exposure, is either supplied or
+//! recovered from the stack itself, which makes it suitable for images that went through an
+//! unknown tone curve, like most JPEGs.
+
+use crate::extensions::NDArrayBuffer;
+use crate::input::HDRInputList;
+use crate::output::HDRImage;
+use crate::parallel::prelude::*;
+use crate::{validate_image_count, validate_inputs, Error};
+use ndarray::{Array3, Axis, Zip};
+use std::path::Path;
+
+/// Number of pixel values the response curve is defined for. Pixel values are quantized to this
+/// many levels, so images with a higher bit depth are treated as 8-bit images.
+pub const RESPONSE_LEVELS: usize = 256;
+
+/// Default weight of the smoothness term used by [`ResponseCurve::recover`], the `λ` of the paper.
+pub const DEFAULT_SMOOTHNESS: f32 = 10.;
+
+/// Number of pixels sampled along each axis by [`ResponseCurve::recover`].
+const SAMPLES_PER_AXIS: usize = 16;
+
+/// Relative weight of samples at the extremes of the pixel range, where the hat weighting
+/// function is zero. This keeps the radiance defined for pixels that are clipped in every frame.
+const MIN_SAMPLE_WEIGHT: f32 = 1e-4;
+
+/// Camera response of each channel, stored as the log exposure `g(z) = ln(E * Δt)` producing each
+/// pixel value `z` in `0..RESPONSE_LEVELS`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseCurve {
+    log_exposures: Vec<[f32; RESPONSE_LEVELS]>,
+}
+
+impl ResponseCurve {
+    /// Create new [`ResponseCurve`] from the log exposure of each pixel value, one curve per
+    /// channel.
+    ///
+    /// # Errors
+    ///
+    /// - If there is no curve
+    /// - If any of the values is not finite
+    pub fn new(log_exposures: Vec<[f32; RESPONSE_LEVELS]>) -> Result<Self, Error> {
+        if log_exposures.is_empty() {
+            return Err(Error::InputError {
+                parameter_name: "log_exposures".to_string(),
+                message: "Expected one curve per channel, got none".to_string(),
+            });
+        }
+
+        if log_exposures
+            .iter()
+            .flatten()
+            .any(|value| !value.is_finite())
+        {
+            return Err(Error::InputError {
+                parameter_name: "log_exposures".to_string(),
+                message: "Response curves must only contain finite values".to_string(),
+            });
+        }
+
+        Ok(Self { log_exposures })
+    }
+
+    /// Recover the camera response from a stack of images of a static scene, by solving the least
+    /// squares system of the paper on a grid of sampled pixels. The response of each channel is
+    /// recovered independently, and is anchored so that the middle pixel value maps to a log
+    /// exposure of `0`.
+    ///
+    /// The paper solves the system with SVD. Since the smoothness and anchor terms make it full
+    /// rank, it is solved through its normal equations here instead, which gives the same
+    /// solution.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs`: The stack, with exposures spread widely enough for every pixel value to occur
+    /// * `smoothness`: Weight of the smoothness term. Higher values produce smoother curves.
+    ///
+    /// returns: `Result<ResponseCurve, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If fewer than two images are provided
+    /// - If images are of different dimensions.
+    /// - If `smoothness` is not a finite positive number
+    /// - If the stack doesn't constrain the response enough for the system to be solved
+    pub fn recover(inputs: &HDRInputList, smoothness: f32) -> Result<Self, Error> {
+        validate_inputs(inputs)?;
+
+        if !smoothness.is_finite() || smoothness <= 0. {
+            return Err(Error::InputError {
+                parameter_name: "smoothness".to_string(),
+                message: format!("Smoothness must be a finite positive number, got {smoothness}"),
+            });
+        }
+
+        let inputs = inputs.as_slice();
+        let (height, width, channels) = inputs[0].get_buffer().dim();
+        let positions = (0..SAMPLES_PER_AXIS)
+            .flat_map(|row| {
+                (0..SAMPLES_PER_AXIS).map(move |column| {
+                    (
+                        (2 * row + 1) * height / (2 * SAMPLES_PER_AXIS),
+                        (2 * column + 1) * width / (2 * SAMPLES_PER_AXIS),
+                    )
+                })
+            })
+            .collect::<Vec<(usize, usize)>>();
+
+        let log_exposures = (0..channels)
+            .into_par_iter()
+            .map(|channel| {
+                let samples = positions
+                    .iter()
+                    .map(|(y, x)| {
+                        inputs
+                            .iter()
+                            .map(|input| {
+                                (
+                                    quantize(input.get_buffer()[[*y, *x, channel]]),
+                                    log_exposure_time(input.get_exposure(), input.get_gain()),
+                                )
+                            })
+                            .collect::<Vec<(usize, f32)>>()
+                    })
+                    // Samples clipped in every frame carry no information about the response
+                    .filter(|frames| frames.iter().any(|(level, _)| hat_weight(*level) > 0.))
+                    .collect::<Vec<Vec<(usize, f32)>>>();
+
+                solve_response(&samples, smoothness)
+            })
+            .collect::<Option<Vec<[f32; RESPONSE_LEVELS]>>>()
+            .ok_or_else(|| Error::InputError {
+                parameter_name: "inputs".to_string(),
+                message: "The stack doesn't constrain the camera response enough to recover it, use images with more varied exposures".to_string(),
+            })?;
+
+        Ok(Self { log_exposures })
+    }
+
+    /// Create the response of a camera whose pixel values are already linear in exposure, i.e.
+    /// `g(z) = ln(z / (RESPONSE_LEVELS - 1))`. Merging with it scales the result like
+    /// [`crate::hdr_merge_images`]. The value `0` is treated as half a level, to keep its log
+    /// exposure finite.
+    ///
+    /// # Errors
+    ///
+    /// - If `channels` is zero
+    #[allow(clippy::cast_precision_loss)]
+    pub fn linear(channels: usize) -> Result<Self, Error> {
+        let mut curve = [0_f32; RESPONSE_LEVELS];
+        for (level, value) in curve.iter_mut().enumerate() {
+            *value = ((level as f32).max(0.5) / (RESPONSE_LEVELS - 1) as f32).ln();
+        }
+
+        Self::new(vec![curve; channels])
+    }
+
+    /// Serialize the curve to text, with one line per pixel value holding the natural log
+    /// exposure of each channel separated by spaces. Lines starting with `#` are comments. The
+    /// format is easy to convert to and from the response files of tools like `pfscalibration`.
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "# Camera response: natural log exposure of each of the {} channel(s), one line per pixel value\n",
+            self.get_channels()
+        );
+
+        for level in 0..RESPONSE_LEVELS {
+            let line = self
+                .log_exposures
+                .iter()
+                .map(|curve| curve[level].to_string())
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            text.push_str(&line);
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /// Parse a curve serialized with [`ResponseCurve::to_text`]. Empty lines and lines starting
+    /// with `#` are ignored.
+    ///
+    /// # Errors
+    ///
+    /// - If there is not exactly one line per pixel value
+    /// - If lines don't all hold one or three numbers
+    /// - If any of the values is not a finite number
+    pub fn from_text(text: &str) -> Result<Self, Error> {
+        let error = |message: String| Error::InputError {
+            parameter_name: "text".to_string(),
+            message,
+        };
+
+        let rows = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| {
+                line.split_whitespace()
+                    .map(str::parse::<f32>)
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|parse_error| error(format!("Line {number}: {parse_error}")))
+            })
+            .collect::<Result<Vec<Vec<f32>>, Error>>()?;
+
+        if rows.len() != RESPONSE_LEVELS {
+            return Err(error(format!(
+                "Expected {RESPONSE_LEVELS} lines of values, got {}",
+                rows.len()
+            )));
+        }
+
+        let channels = rows[0].len();
+        if channels != 1 && channels != 3 {
+            return Err(error(format!(
+                "Expected one or three values per line, got {channels}"
+            )));
+        }
+
+        let mut log_exposures = vec![[0_f32; RESPONSE_LEVELS]; channels];
+
+        for (level, row) in rows.iter().enumerate() {
+            if row.len() != channels {
+                return Err(error(format!(
+                    "Expected {channels} value(s) for pixel value {level}, got {}",
+                    row.len()
+                )));
+            }
+
+            for (curve, value) in log_exposures.iter_mut().zip(row) {
+                curve[level] = *value;
+            }
+        }
+
+        Self::new(log_exposures)
+    }
+
+    /// Write the curve to a file, see [`ResponseCurve::to_text`].
+    ///
+    /// # Errors
+    ///
+    /// - If the file cannot be written
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::write(path, self.to_text())?;
+
+        Ok(())
+    }
+
+    /// Read a curve from a file, see [`ResponseCurve::from_text`].
+    ///
+    /// # Errors
+    ///
+    /// - If the file cannot be read
+    /// - If the file doesn't hold a valid curve
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_text(&std::fs::read_to_string(path)?)
+    }
+
+    /// Get the log exposure of each pixel value for the given channel, if the curve has it
+    #[must_use]
+    pub fn get_log_exposures(&self, channel: usize) -> Option<&[f32; RESPONSE_LEVELS]> {
+        self.log_exposures.get(channel)
+    }
+
+    /// Get the number of channels the curve is defined for
+    #[must_use]
+    pub fn get_channels(&self) -> usize {
+        self.log_exposures.len()
+    }
+}
+
+/// Given a set of file paths, attempt to read the images along with their exposure and gain from
+/// EXIF metadata, and merge them with the Debevec and Malik method. See [`merge_inputs_debevec`].
+///
+/// # Errors
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If the response has to be recovered and cannot be, see [`ResponseCurve::recover`]
+/// - If the channels of the response don't match the images
+pub fn merge_hdr_debevec<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    response: Option<&ResponseCurve>,
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let inputs = HDRInputList::try_from(paths)?;
+
+    merge_inputs_debevec(&inputs, response)
+}
+
+/// Merge a stack with the Debevec and Malik method. Each pixel value is mapped to log exposure
+/// through the camera response, and the log radiance is the weighted average of
+/// `g(z) - ln(exposure * gain)` across the stack, using a hat weighting function that favours
+/// values in the middle of the range.
+///
+/// If `response` is `None`, it is recovered from the stack with [`DEFAULT_SMOOTHNESS`]. The
+/// absolute scale of the result depends on the anchor of the response, so it differs from the
+/// scale produced by [`crate::hdr_merge_images`].
+///
+/// # Errors
+/// - If fewer than two images are provided
+/// - If images are of different dimensions.
+/// - If the response has to be recovered and cannot be, see [`ResponseCurve::recover`]
+/// - If the channels of the response don't match the images
+pub fn merge_inputs_debevec(
+    inputs: &HDRInputList,
+    response: Option<&ResponseCurve>,
+) -> Result<HDRImage, Error> {
+    validate_inputs(inputs)?;
+
+    let recovered;
+    let response = if let Some(response) = response {
+        response
+    } else {
+        recovered = ResponseCurve::recover(inputs, DEFAULT_SMOOTHNESS)?;
+        &recovered
+    };
+
+    let shape = inputs.as_slice()[0].get_buffer().dim();
+    if response.get_channels() != shape.2 {
+        return Err(Error::InputError {
+            parameter_name: "response".to_string(),
+            message: format!(
+                "Response has {} channel(s) while the images have {}",
+                response.get_channels(),
+                shape.2
+            ),
+        });
+    }
+
+    let mut log_radiance = Array3::<f32>::zeros(shape);
+    let mut total_weights = Array3::<f32>::zeros(shape);
+
+    for input in inputs.as_slice() {
+        let log_time = log_exposure_time(input.get_exposure(), input.get_gain());
+
+        Zip::from(log_radiance.lanes_mut(Axis(2)))
+            .and(total_weights.lanes_mut(Axis(2)))
+            .and(input.get_buffer().lanes(Axis(2)))
+            .par_for_each(|mut log_radiance, mut total_weights, pixel| {
+                for (channel, value) in pixel.iter().enumerate() {
+                    let level = quantize(*value);
+                    let weight = hat_weight(level).max(MIN_SAMPLE_WEIGHT);
+
+                    log_radiance[channel] +=
+                        weight * (response.log_exposures[channel][level] - log_time);
+                    total_weights[channel] += weight;
+                }
+            });
+    }
+
+    Zip::from(&mut log_radiance)
+        .and(&total_weights)
+        .par_for_each(|log_radiance, total_weight| {
+            *log_radiance = (*log_radiance / total_weight).exp();
+        });
+
+    Ok(HDRImage::from_nd_array_buffer(log_radiance))
+}
+
+/// Quantize a normalised pixel value to a level of the response curve.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_sign_loss)]
+fn quantize(value: f32) -> usize {
+    (value.clamp(0., 1.) * (RESPONSE_LEVELS - 1) as f32).round() as usize
+}
+
+/// Hat weighting function of the paper, peaking in the middle of the range and reaching zero at
+/// both extremes.
+#[allow(clippy::cast_precision_loss)]
+fn hat_weight(level: usize) -> f32 {
+    let max = RESPONSE_LEVELS - 1;
+
+    level.min(max - level) as f32 / (max / 2) as f32
+}
+
+fn log_exposure_time(exposure: f32, gain: f32) -> f32 {
+    (exposure * gain).ln()
+}
+
+/// Solve for the response curve of a single channel, given for each sampled pixel the level and
+/// log exposure time of every frame. Returns `None` if the system cannot be solved.
+#[allow(clippy::cast_possible_truncation)]
+fn solve_response(
+    samples: &[Vec<(usize, f32)>],
+    smoothness: f32,
+) -> Option<[f32; RESPONSE_LEVELS]> {
+    // Unknowns are the response for each level followed by the log radiance of each sample.
+    let unknowns = RESPONSE_LEVELS + samples.len();
+    let mut normal_matrix = vec![0_f64; unknowns * unknowns];
+    let mut normal_vector = vec![0_f64; unknowns];
+
+    let mut add_row = |entries: &[(usize, f64)], target: f64| {
+        for (row, row_value) in entries {
+            for (column, column_value) in entries {
+                normal_matrix[row * unknowns + column] += row_value * column_value;
+            }
+
+            normal_vector[*row] += row_value * target;
+        }
+    };
+
+    // Data term: g(z) - ln(E) = ln(Δt)
+    for (sample, frames) in samples.iter().enumerate() {
+        for (level, log_time) in frames {
+            let weight = f64::from(hat_weight(*level));
+            if weight > 0. {
+                add_row(
+                    &[(*level, weight), (RESPONSE_LEVELS + sample, -weight)],
+                    weight * f64::from(*log_time),
+                );
+            }
+        }
+    }
+
+    // Anchor: g(middle) = 0
+    add_row(&[(RESPONSE_LEVELS / 2, 1.)], 0.);
+
+    // Smoothness: g''(z) = 0
+    for level in 1..RESPONSE_LEVELS - 1 {
+        let weight = f64::from(smoothness * hat_weight(level));
+        add_row(
+            &[
+                (level - 1, weight),
+                (level, -2. * weight),
+                (level + 1, weight),
+            ],
+            0.,
+        );
+    }
+
+    let solution = solve_cholesky(normal_matrix, normal_vector, unknowns)?;
+
+    let mut response = [0_f32; RESPONSE_LEVELS];
+    for (value, solved) in response.iter_mut().zip(&solution) {
+        *value = *solved as f32;
+    }
+
+    Some(response)
+}
+
+/// Solve `matrix * x = vector` for a symmetric positive definite `size` by `size` matrix stored
+/// in row major order. Returns `None` if the matrix is not positive definite.
+fn solve_cholesky(mut matrix: Vec<f64>, mut vector: Vec<f64>, size: usize) -> Option<Vec<f64>> {
+    // Decompose in place into the lower triangular factor `L` with `matrix = L * L^T`.
+    for column in 0..size {
+        let mut diagonal = matrix[column * size + column];
+        for k in 0..column {
+            diagonal -= matrix[column * size + k] * matrix[column * size + k];
+        }
+
+        if diagonal <= f64::EPSILON || !diagonal.is_finite() {
+            return None;
+        }
+
+        let diagonal = diagonal.sqrt();
+        matrix[column * size + column] = diagonal;
+
+        for row in column + 1..size {
+            let mut value = matrix[row * size + column];
+            for k in 0..column {
+                value -= matrix[row * size + k] * matrix[column * size + k];
+            }
+
+            matrix[row * size + column] = value / diagonal;
+        }
+    }
+
+    // Forward substitution with `L`, then backward substitution with `L^T`.
+    for row in 0..size {
+        for k in 0..row {
+            vector[row] -= matrix[row * size + k] * vector[k];
+        }
+
+        vector[row] /= matrix[row * size + row];
+    }
+
+    for row in (0..size).rev() {
+        for k in row + 1..size {
+            vector[row] -= matrix[k * size + row] * vector[k];
+        }
+
+        vector[row] /= matrix[row * size + row];
+    }
+
+    Some(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_with_two_columns_is_rejected() {
+        let text = "0 1\n".repeat(RESPONSE_LEVELS);
+
+        assert!(matches!(
+            ResponseCurve::from_text(&text),
+            Err(Error::InputError { parameter_name, .. }) if parameter_name == "text"
+        ));
+    }
+}
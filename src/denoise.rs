@@ -0,0 +1,273 @@
+//! Edge preserving denoising of merged radiance.
+//!
+//! Merging averages the noise of the stack away where several frames are well exposed, but deep
+//! shadows that are only captured by the longest exposure keep its noise. The filter of this
+//! module is a guided filter, as introduced in
+//! [Guided Image Filtering](https://kaiminghe.github.io/publications/eccv10guidedfilter.pdf) by
+//! He, Sun and Tang, applied to log radiance with the log luminance as its guide. Within every
+//! window, each channel is fitted as a linear function of the guide. The fit follows the guide
+//! across edges, and flattens to the mean of the window where the guide varies less than the
+//! expected noise.
+//!
+//! Photon noise shrinks relative to the signal as radiance grows, so the expected noise variance
+//! of each window is scaled by the inverse of its radiance relative to the log-average luminance
+//! of the image. Shadows are smoothed strongly, while highlights are left almost untouched.
+
+use crate::output::HDRImage;
+#[cfg(not(feature = "parallel"))]
+use crate::parallel::prelude::*;
+use crate::tonemap::luminance;
+use crate::Error;
+use ndarray::{s, Array2, ArrayView1, ArrayViewMut1, ArrayViewMut3, Zip};
+
+/// Radius of the square window the linear fits are computed over, in pixels.
+const RADIUS: usize = 3;
+
+/// Smallest radiance whose log is taken, keeping the log radiance of black pixels finite.
+const MIN_RADIANCE: f32 = 1e-6;
+
+/// Largest difference of log luminance between a window and the whole image used to scale the
+/// expected noise, which keeps the scale finite in extremely dark or bright windows.
+const MAX_LOG_LUMINANCE_OFFSET: f32 = 16.;
+
+/// Denoise the merged radiance in place. See the [module documentation](self) for the filter.
+///
+/// Pixels with non-finite values are left untouched and ignored by the filter, see
+/// [`crate::config::MergeConfig::non_finite`]. The alpha channel, if any, is left untouched. The
+/// pixels are processed in parallel.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `strength`: Standard deviation of the noise, in natural log radiance, of pixels at the
+///   log-average luminance of the image, e.g. `0.1` for noise of about 10%. Darker pixels are
+///   assumed to be noisier and brighter ones less noisy.
+///
+/// returns: `Result<(), Error>`
+///
+/// # Errors
+///
+/// - If `strength` is not a finite, positive and non-zero number
+pub fn denoise(hdr: &mut HDRImage, strength: f32) -> Result<(), Error> {
+    validate_strength(strength)?;
+
+    denoise_buffer(hdr.color_channels_mut(), strength);
+
+    Ok(())
+}
+
+pub(crate) fn validate_strength(strength: f32) -> Result<(), Error> {
+    if !strength.is_finite() || strength <= 0. {
+        return Err(Error::InputError {
+            parameter_name: "denoise_strength".to_string(),
+            message: format!(
+                "Denoise strength must be a valid positive and non-zero floating point number, got {strength}"
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Apply the guided filter to a `(height, width, channels)` radiance buffer in place. `strength`
+/// must have been validated.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn denoise_buffer(mut buffer: ArrayViewMut3<f32>, strength: f32) {
+    let (height, width, channels) = buffer.dim();
+    let log = |value: f32| value.max(MIN_RADIANCE).ln();
+
+    let weights = Array2::from_shape_fn((height, width), |(y, x)| {
+        if buffer
+            .slice(s![y, x, ..])
+            .iter()
+            .all(|value| value.is_finite())
+        {
+            1.
+        } else {
+            0.
+        }
+    });
+    let guide = Array2::from_shape_fn((height, width), |(y, x)| {
+        if weights[[y, x]] > 0. {
+            log(luminance(buffer.slice(s![y, x, ..])))
+        } else {
+            0.
+        }
+    });
+
+    // Mean of each window, only counting the pixels with finite values.
+    let counts = box_sum(&weights);
+    let window_mean = |values: &Array2<f32>| {
+        let mut means = box_sum(&(values * &weights));
+        Zip::from(&mut means)
+            .and(&counts)
+            .par_for_each(|mean, count| *mean = if *count > 0. { *mean / count } else { 0. });
+
+        means
+    };
+
+    let total_weight = weights.iter().map(|weight| f64::from(*weight)).sum::<f64>();
+    let reference = if total_weight > 0. {
+        (guide.iter().map(|value| f64::from(*value)).sum::<f64>() / total_weight) as f32
+    } else {
+        0.
+    };
+
+    // Variance of the guide in each window, regularised by the expected noise variance.
+    let mean_guide = window_mean(&guide);
+    let mut regularised_variance = window_mean(&guide.mapv(|value| value * value));
+    Zip::from(&mut regularised_variance)
+        .and(&mean_guide)
+        .par_for_each(|variance, mean| {
+            let noise_variance = strength
+                * strength
+                * (reference - mean)
+                    .clamp(-MAX_LOG_LUMINANCE_OFFSET, MAX_LOG_LUMINANCE_OFFSET)
+                    .exp();
+
+            *variance = (*variance - mean * mean).max(0.) + noise_variance;
+        });
+
+    for channel in 0..channels {
+        let values = Array2::from_shape_fn((height, width), |(y, x)| {
+            if weights[[y, x]] > 0. {
+                log(buffer[[y, x, channel]])
+            } else {
+                0.
+            }
+        });
+
+        let mean_values = window_mean(&values);
+        let correlation = window_mean(&(&guide * &values));
+
+        let mut slopes = Array2::<f32>::zeros((height, width));
+        let mut offsets = Array2::<f32>::zeros((height, width));
+        Zip::from(&mut slopes)
+            .and(&mut offsets)
+            .and(&correlation)
+            .and(&mean_guide)
+            .and(&mean_values)
+            .and(&regularised_variance)
+            .par_for_each(
+                |slope, offset, correlation, mean_guide, mean_value, variance| {
+                    *slope = (correlation - mean_guide * mean_value) / variance;
+                    *offset = mean_value - *slope * mean_guide;
+                },
+            );
+
+        // Windows centered on non-finite pixels are ignored like the pixels themselves.
+        let (slopes, offsets) = (window_mean(&slopes), window_mean(&offsets));
+
+        Zip::from(buffer.slice_mut(s![.., .., channel]))
+            .and(&weights)
+            .and(&guide)
+            .and(&slopes)
+            .and(&offsets)
+            .par_for_each(|value, weight, guide, slope, offset| {
+                if *weight > 0. {
+                    *value = (slope * guide + offset).exp();
+                }
+            });
+    }
+}
+
+/// Sum of the values in the square window of [`RADIUS`] around each pixel, clipped to the image.
+fn box_sum(values: &Array2<f32>) -> Array2<f32> {
+    let mut rows = Array2::<f32>::zeros(values.dim());
+    Zip::from(rows.rows_mut())
+        .and(values.rows())
+        .par_for_each(|sums, values| window_sum(values, sums));
+
+    let mut sums = Array2::<f32>::zeros(values.dim());
+    Zip::from(sums.columns_mut())
+        .and(rows.columns())
+        .par_for_each(|sums, values| window_sum(values, sums));
+
+    sums
+}
+
+/// Running sum of the `2 * RADIUS + 1` values centered on each index, clipped to the lane.
+#[allow(clippy::cast_possible_truncation)]
+fn window_sum(values: ArrayView1<f32>, mut sums: ArrayViewMut1<f32>) {
+    let length = values.len();
+    let mut sum = values
+        .iter()
+        .take(RADIUS)
+        .map(|value| f64::from(*value))
+        .sum::<f64>();
+
+    for index in 0..length {
+        if index + RADIUS < length {
+            sum += f64::from(values[index + RADIUS]);
+        }
+        if index > RADIUS {
+            sum -= f64::from(values[index - RADIUS - 1]);
+        }
+
+        sums[index] = sum as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::NDArrayBuffer;
+    use crate::test_support::Noise;
+    use ndarray::Array3;
+
+    /// 32x32 RGB image of a flat radiance of `1.0` with multiplicative noise of up to ±20%.
+    fn noisy_flat_image() -> HDRImage {
+        let mut noise = Noise::new(7);
+
+        HDRImage::from_nd_array_buffer(Array3::from_shape_simple_fn((32, 32, 3), || {
+            1. + 0.4 * (noise.next() - 0.5)
+        }))
+    }
+
+    /// Variance of the values of the central 16x16 pixels, away from the borders.
+    #[allow(clippy::cast_precision_loss)]
+    fn central_variance(hdr: &HDRImage) -> f32 {
+        let region = hdr.get_buffer().slice(s![8..24, 8..24, ..]);
+        let mean = region.sum() / region.len() as f32;
+
+        region.mapv(|value| (value - mean) * (value - mean)).sum() / region.len() as f32
+    }
+
+    #[test]
+    fn noise_of_a_flat_region_is_reduced() {
+        let mut hdr = noisy_flat_image();
+        let before = central_variance(&hdr);
+
+        denoise(&mut hdr, 0.2).expect("The strength is valid");
+
+        assert!(
+            central_variance(&hdr) < before / 4.,
+            "Variance went from {before} to {}",
+            central_variance(&hdr)
+        );
+    }
+
+    #[test]
+    fn edges_are_preserved() {
+        let buffer = Array3::from_shape_fn((16, 16, 3), |(_, x, _)| if x < 8 { 0.1 } else { 1. });
+        let mut hdr = HDRImage::from_nd_array_buffer(buffer.clone());
+
+        denoise(&mut hdr, 0.2).expect("The strength is valid");
+
+        // A box blur of the same radius would bring the pixels next to the edge halfway to the
+        // other side.
+        for (value, original) in hdr.get_buffer().iter().zip(&buffer) {
+            assert!(
+                (value - original).abs() < original * 0.1,
+                "Expected {original}, got {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_strength_is_rejected() {
+        for strength in [0., -0.1, f32::NAN, f32::INFINITY] {
+            assert!(denoise(&mut noisy_flat_image(), strength).is_err());
+        }
+    }
+}
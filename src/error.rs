@@ -5,6 +5,7 @@ use image::ImageError;
 use rawloader::RawLoaderError;
 use std::fmt::{Debug, Display, Formatter};
 use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 /// Represents error occurred during the raw image decoding pipeline.
@@ -39,6 +40,42 @@ impl From<String> for UnknownError {
     }
 }
 
+/// Represents an image of a list that could not be read.
+#[derive(Debug)]
+pub struct ReadFailure {
+    /// Index of the image in the list
+    pub index: usize,
+    /// Path of the image, if it was read from a file
+    pub path: Option<PathBuf>,
+    /// Reason the image could not be read
+    pub error: Error,
+}
+
+impl Display for ReadFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.display(), self.error)?,
+            None => write!(f, "Image at index {}: {}", self.index, self.error)?,
+        }
+
+        let mut source = std::error::Error::source(&self.error);
+        while let Some(error) = source {
+            write!(f, ": {error}")?;
+            source = error.source();
+        }
+
+        Ok(())
+    }
+}
+
+fn format_read_failures(failures: &[ReadFailure]) -> String {
+    failures
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
 /// Error that can be returned by any of the methods in the library.
 #[derive(Debug, Error)]
 pub enum Error {
@@ -63,6 +100,10 @@ pub enum Error {
     /// buffers from raw/processed pixel data.
     #[error("Unable to process image")]
     ImageError(#[from] ImageError),
+    /// Represents error occurred while writing `OpenEXR` files
+    #[cfg(feature = "exr")]
+    #[error("Unable to write `OpenEXR` image")]
+    ExrError(#[from] exr::error::Error),
     /// Represents error caused by invalid input to the crate's functions
     #[error("Invalid value for {parameter_name:?}: {message:?}")]
     InputError {
@@ -71,6 +112,17 @@ pub enum Error {
         /// A message explaining why parameter is invalid
         message: String,
     },
+    /// Represents one or more images of a list that could not be read, listing every failure
+    /// rather than just the first one.
+    #[error("Unable to read {} image(s): {}", .0.len(), format_read_failures(.0))]
+    ReadErrors(Vec<ReadFailure>),
+    /// Represents a merge result with non-finite radiance, holding the number of affected pixels.
+    /// See [`crate::config::NonFiniteHandling::Error`].
+    #[error("Merged radiance is not finite in {0} pixel(s)")]
+    NonFinite(usize),
+    /// Represents an empty stack, e.g. an empty list of paths, rejected before any work is done
+    #[error("No images were provided, at least two images are required")]
+    NoImages,
     /// Represents errors that cannot be categorised as any other error types.
     #[error("{0}")]
     UnknownError(#[from] UnknownError),
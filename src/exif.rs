@@ -1,12 +1,82 @@
 //! Helpers to extract necessary EXIF information from source images
 
+use crate::input::exposure_from_exif;
+use crate::io::read_file;
 use crate::Error;
-use exif::{Exif, In, Tag, Value};
+use exif::{Exif, Field, In, Tag, Value};
+use image::metadata::Orientation;
+use std::path::Path;
+use std::time::Duration;
+
+/// Exposure settings of a single file, parsed from its EXIF metadata. Values that are missing or
+/// invalid are `None`.
+///
+/// Every field is public, so that detected values can be displayed, and corrected before merging
+/// with [`crate::hdr_merge_paths_with_exif_data`]. Values are read from the primary image, with
+/// the following tags:
+///
+/// - `exposure`: `ExposureTime`, or `ShutterSpeedValue` converted from APEX units when it is
+///   missing, see [`get_exposures`]
+/// - `iso`: the first of `ISOSpeed`, `StandardOutputSensitivity` and `PhotographicSensitivity`
+///   that is present
+/// - `gain`: derived from the ISO, see [`get_gains`]
+/// - `aperture`: `FNumber`, or `ApertureValue` converted from APEX units when it is missing
+///
+/// The values can also be cached to merge the same stack repeatedly, e.g. while tweaking other
+/// settings in an interactive tool: read them once with [`read_exif_data`], and pass them to
+/// [`crate::hdr_merge_paths_with_exif_data`] for every merge. When both `exposure` and `gain`
+/// are set, the metadata of the files is not parsed again. The cache is owned by the caller,
+/// which is responsible for reading the values again when the files change.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExifData {
+    /// Exposure time
+    pub exposure: Option<Duration>,
+    /// Gain used to merge the file
+    pub gain: Option<f32>,
+    /// ISO speed rating
+    pub iso: Option<f32>,
+    /// Aperture as an f-number, e.g. `8.0` for f/8
+    pub aperture: Option<f32>,
+}
+
+impl From<&Exif> for ExifData {
+    fn from(exif: &Exif) -> Self {
+        Self {
+            exposure: exposure_from_exif(exif).ok(),
+            gain: get_gains(exif).ok(),
+            iso: get_iso(exif).ok(),
+            aperture: get_aperture(exif),
+        }
+    }
+}
+
+/// Parse the exposure settings from the EXIF metadata in the bytes of an image file. See
+/// [`ExifData`].
+///
+/// # Errors
+/// - failed to extract exif data
+pub fn parse_exif_data(data: &[u8]) -> Result<ExifData, Error> {
+    Ok(ExifData::from(&get_exif_data(data)?))
+}
+
+/// Read the file at the given path and parse the exposure settings from its EXIF metadata,
+/// without decoding the image. See [`ExifData`].
+///
+/// # Errors
+/// - If the file cannot be read
+/// - failed to extract exif data
+pub fn read_exif_data(path: impl AsRef<Path>) -> Result<ExifData, Error> {
+    parse_exif_data(&read_file(path.as_ref())?)
+}
 
 /// Extract the exif information from the bytes of an image file
 ///
 /// # Errors
 /// - failed to extract exif data
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "trace", skip_all, fields(bytes = data.len()), err(Debug))
+)]
 pub fn get_exif_data(data: &[u8]) -> Result<Exif, Error> {
     let mut buf_reader = std::io::Cursor::new(data);
     let exif_reader = exif::Reader::new();
@@ -15,42 +85,379 @@ pub fn get_exif_data(data: &[u8]) -> Result<Exif, Error> {
     Ok(exif)
 }
 
-/// Extract the exposure time in seconds from exif information
+/// Extract the exposure time in seconds from exif information. The exposure time is read from
+/// `ExposureTime`, a rational number of seconds, or from the APEX `ShutterSpeedValue` `Tv` when
+/// it is missing or invalid, where the exposure time is `2^(-Tv)` seconds. `ExposureTime` is
+/// preferred since it holds the exact value, while cameras often round `Tv`.
 ///
 /// # Errors
 /// - failed to exposure from exif data
 pub fn get_exposures(exif: &Exif) -> Result<f32, Error> {
-    match exif
-        .get_field(Tag::ExposureTime, In::PRIMARY)
+    let value = |tag| match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(ref v) if !v.is_empty() && v[0].denom != 0 => Some(v[0].to_f32()),
+        Value::SRational(ref v) if !v.is_empty() && v[0].denom != 0 => Some(v[0].to_f32()),
+        _ => None,
+    };
+    let is_valid = |exposure: &f32| exposure.is_finite() && *exposure > 0.;
+
+    value(Tag::ExposureTime)
+        .filter(is_valid)
+        .or_else(|| {
+            value(Tag::ShutterSpeedValue)
+                .map(|apex| (-apex).exp2())
+                .filter(is_valid)
+        })
         .ok_or(Error::ExifError(exif::Error::NotFound(
             "ExposureTime not found",
-        )))?
-        .value
+        )))
+}
+
+/// Tags describing the camera, lens and capture date, which remain accurate for an HDR image
+/// merged from the stack. Exposure related tags are deliberately left out since they only
+/// describe a single frame.
+pub const DEFAULT_PRESERVED_TAGS: &[Tag] = &[
+    Tag::Make,
+    Tag::Model,
+    Tag::LensMake,
+    Tag::LensModel,
+    Tag::FocalLength,
+    Tag::FocalLengthIn35mmFilm,
+    Tag::DateTime,
+    Tag::DateTimeOriginal,
+    Tag::DateTimeDigitized,
+];
+
+/// Encode the given tags of the primary image from exif information into a new EXIF blob, which
+/// can be embedded into an output file with [`crate::export::save_with_exif`]. Tags that are not
+/// present are skipped.
+///
+/// returns: `Result<Vec<u8>, Error>` with the encoded EXIF data, which is empty if none of the
+/// tags are present.
+///
+/// # Errors
+/// - failed to encode exif data
+pub fn encode_exif_tags(exif: &Exif, tags: &[Tag]) -> Result<Vec<u8>, Error> {
+    let mut writer = exif::experimental::Writer::new();
+    let mut empty = true;
+
+    for field in exif
+        .fields()
+        .filter(|field| field.ifd_num == In::PRIMARY && tags.contains(&field.tag))
     {
-        Value::Rational(ref v) if !v.is_empty() => Ok(v[0].to_f32()),
-        _ => Ok(0.),
+        writer.push_field(field);
+        empty = false;
+    }
+
+    if empty {
+        return Ok(Vec::new());
     }
+
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    writer.write(&mut buffer, exif.little_endian())?;
+
+    Ok(buffer.into_inner())
 }
 
+/// ISO speed rating that corresponds to a gain of `1.0`.
+pub const UNITY_GAIN_ISO: f32 = 100.;
+
 /// Extract the gains from exif information
 ///
+/// EXIF doesn't have a standard tag for sensor gain, so the gain is derived from the ISO speed
+/// rating as `gain = iso / UNITY_GAIN_ISO`, i.e. ISO 100 is a gain of `1.0`. The ISO is read from
+/// the first of `ISOSpeed`, `StandardOutputSensitivity` and `PhotographicSensitivity` present.
+///
 /// # Errors
 /// - failed to gains from exif data
-#[allow(clippy::cast_precision_loss)]
 pub fn get_gains(exif: &Exif) -> Result<f32, Error> {
-    match exif
-        .get_field(Tag::ISOSpeed, In::PRIMARY)
-        .unwrap_or(
-            exif.get_field(Tag::StandardOutputSensitivity, In::PRIMARY)
-                .unwrap_or(
-                    exif.get_field(Tag::PhotographicSensitivity, In::PRIMARY)
-                        .ok_or(Error::ExifError(exif::Error::NotFound("ISO not found")))?,
-                ),
-        )
-        .value
+    Ok(get_iso(exif)? / UNITY_GAIN_ISO)
+}
+
+/// Read the ISO speed rating from the first of `ISOSpeed`, `StandardOutputSensitivity` and
+/// `PhotographicSensitivity` present. Values that are not a positive integer are rejected.
+#[allow(clippy::cast_precision_loss)]
+fn get_iso(exif: &Exif) -> Result<f32, Error> {
+    let iso = match exif_field(
+        exif,
+        &[
+            Tag::ISOSpeed,
+            Tag::StandardOutputSensitivity,
+            Tag::PhotographicSensitivity,
+        ],
+    )
+    .ok_or(Error::ExifError(exif::Error::NotFound("ISO not found")))?
+    .value
     {
-        Value::Long(ref v) if !v.is_empty() => Ok(v[0] as f32),
-        Value::Short(ref v) if !v.is_empty() => Ok(f32::from(v[0])),
-        _ => Ok(0.),
+        Value::Long(ref v) if !v.is_empty() => Some(v[0] as f32),
+        Value::Short(ref v) if !v.is_empty() => Some(f32::from(v[0])),
+        _ => None,
+    };
+
+    iso.filter(|iso| *iso > 0.)
+        .ok_or(Error::ExifError(exif::Error::InvalidFormat(
+            "ISO is not a positive integer",
+        )))
+}
+
+/// Extract the aperture as an f-number from exif information, e.g. `8.0` for f/8. The f-number is
+/// read from `FNumber`, or from the APEX `ApertureValue` `Av` when it is missing, where the
+/// f-number is `2^(Av / 2)`. See [`crate::config::ExposureModel`].
+///
+/// # Errors
+/// - failed to aperture from exif data
+pub fn get_apertures(exif: &Exif) -> Result<f32, Error> {
+    get_aperture(exif).ok_or(Error::ExifError(exif::Error::NotFound("FNumber not found")))
+}
+
+/// Extract the orientation from exif information, i.e. the rotation and flip that display the
+/// image upright. Images read from files are oriented accordingly before they are merged, so that
+/// every frame of a stack shares the same orientation, e.g. photos taken by a phone that was
+/// turned between exposures.
+///
+/// # Errors
+/// - failed to orientation from exif data
+pub fn get_orientation(exif: &Exif) -> Result<Orientation, Error> {
+    exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .and_then(|value| u8::try_from(value).ok())
+        .and_then(Orientation::from_exif)
+        .ok_or(Error::ExifError(exif::Error::NotFound(
+            "Orientation not found",
+        )))
+}
+
+/// Read the f-number from `FNumber`, or from the APEX `ApertureValue` `Av`, where the f-number is
+/// `2^(Av / 2)`.
+fn get_aperture(exif: &Exif) -> Option<f32> {
+    let rational = |tag| match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(ref v) if !v.is_empty() && v[0].denom != 0 => Some(v[0].to_f32()),
+        _ => None,
+    };
+
+    rational(Tag::FNumber)
+        .or_else(|| rational(Tag::ApertureValue).map(|apex| (apex / 2.).exp2()))
+        .filter(|aperture| aperture.is_finite() && *aperture > 0.)
+}
+
+/// Get the first of the given tags that is present in the exif information
+fn exif_field<'a>(exif: &'a Exif, tags: &[Tag]) -> Option<&'a Field> {
+    tags.iter()
+        .find_map(|tag| exif.get_field(*tag, In::PRIMARY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MergeConfig;
+    use crate::input::HDRInput;
+    use crate::test_support::{exif, exposure_fields, field, jpeg};
+    use image::{DynamicImage, RgbImage};
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() <= expected * 1e-6,
+            "Expected {expected}, got {value}"
+        );
+    }
+
+    #[test]
+    fn gain_is_derived_from_the_iso() {
+        let exif = exif(&[field(Tag::PhotographicSensitivity, Value::Short(vec![400]))]);
+
+        assert_close(get_gains(&exif).expect("The ISO is present"), 4.);
+    }
+
+    #[test]
+    fn iso_speed_takes_precedence_over_photographic_sensitivity() {
+        let exif = exif(&[
+            field(Tag::ISOSpeed, Value::Long(vec![800])),
+            field(Tag::PhotographicSensitivity, Value::Short(vec![400])),
+        ]);
+
+        assert_close(get_gains(&exif).expect("The ISO is present"), 8.);
+    }
+
+    #[test]
+    fn missing_iso_is_an_error() {
+        let exif = exif(&exposure_fields(1, 4, 100)[..1]);
+
+        assert!(get_gains(&exif).is_err());
+        assert_eq!(ExifData::from(&exif).gain, None);
+    }
+
+    #[test]
+    fn iso_of_an_unexpected_type_is_an_error() {
+        let exif = exif(&[field(
+            Tag::PhotographicSensitivity,
+            Value::Ascii(vec![b"400".to_vec()]),
+        )]);
+
+        assert!(get_gains(&exif).is_err());
+        assert_eq!(ExifData::from(&exif).gain, None);
+    }
+
+    #[test]
+    fn zero_iso_is_an_error() {
+        let exif = exif(&[field(Tag::PhotographicSensitivity, Value::Short(vec![0]))]);
+
+        assert!(get_gains(&exif).is_err());
+    }
+
+    #[test]
+    fn derived_gain_scales_the_radiance() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        let data = jpeg(&image, &exposure_fields(1, 4, 200));
+
+        let input =
+            HDRInput::with_decoded_file(&data, &image, None, None).expect("The metadata is valid");
+
+        assert_close(input.get_gain(), 2.);
+        assert_close(
+            input
+                .scaling_factor(&MergeConfig::default())
+                .expect("The exposure model doesn't need the aperture"),
+            0.25 * 2.,
+        );
+    }
+
+    fn rational(tag: Tag, numerator: u32, denominator: u32) -> Field {
+        field(
+            tag,
+            Value::Rational(vec![exif::Rational::from((numerator, denominator))]),
+        )
+    }
+
+    #[test]
+    fn aperture_is_read_from_the_f_number() {
+        let exif = exif(&[rational(Tag::FNumber, 56, 10)]);
+
+        assert_close(get_apertures(&exif).expect("The f-number is present"), 5.6);
+    }
+
+    #[test]
+    fn aperture_falls_back_to_the_apex_aperture_value() {
+        // Av = 2 * log2(N), so f/8 is Av 6.
+        let exif = exif(&[rational(Tag::ApertureValue, 6, 1)]);
+
+        assert_close(
+            get_apertures(&exif).expect("The aperture value is present"),
+            8.,
+        );
+    }
+
+    #[test]
+    fn f_number_takes_precedence_over_the_aperture_value() {
+        let exif = exif(&[
+            rational(Tag::FNumber, 4, 1),
+            rational(Tag::ApertureValue, 6, 1),
+        ]);
+
+        assert_close(get_apertures(&exif).expect("The f-number is present"), 4.);
+    }
+
+    #[test]
+    fn missing_aperture_is_an_error() {
+        let exif = exif(&exposure_fields(1, 4, 100));
+
+        assert!(get_apertures(&exif).is_err());
+    }
+
+    #[test]
+    fn aperture_from_the_file_scales_the_radiance() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        let mut fields = exposure_fields(1, 4, 100);
+        fields.push(rational(Tag::FNumber, 8, 1));
+        let data = jpeg(&image, &fields);
+
+        let input =
+            HDRInput::with_decoded_file(&data, &image, None, None).expect("The metadata is valid");
+        let config = MergeConfig {
+            exposure_model: crate::config::ExposureModel::TimeGainAndAperture,
+            ..MergeConfig::default()
+        };
+
+        assert_eq!(input.get_aperture(), Some(8.));
+        assert_close(
+            input
+                .scaling_factor(&config)
+                .expect("The aperture is known"),
+            0.25 / 64.,
+        );
+    }
+
+    fn shutter_speed_value(numerator: i32, denominator: i32) -> Field {
+        field(
+            Tag::ShutterSpeedValue,
+            Value::SRational(vec![exif::SRational::from((numerator, denominator))]),
+        )
+    }
+
+    #[test]
+    fn exposure_time_is_read_in_seconds() {
+        let exif = exif(&[rational(Tag::ExposureTime, 1, 250)]);
+
+        assert_close(
+            get_exposures(&exif).expect("The exposure is present"),
+            0.004,
+        );
+    }
+
+    #[test]
+    fn shutter_speed_value_gives_the_same_seconds_as_the_exposure_time() {
+        // Tv = -log2(t), so 1/256s is Tv 8 and 2s is Tv -1.
+        for (exposure_time, apex, seconds) in [((1, 256), (8, 1), 1. / 256.), ((2, 1), (-1, 1), 2.)]
+        {
+            let from_exposure_time = get_exposures(&exif(&[rational(
+                Tag::ExposureTime,
+                exposure_time.0,
+                exposure_time.1,
+            )]))
+            .expect("The exposure time is present");
+            let from_apex = get_exposures(&exif(&[shutter_speed_value(apex.0, apex.1)]))
+                .expect("The shutter speed value is present");
+
+            assert_close(from_exposure_time, seconds);
+            assert_close(from_apex, seconds);
+        }
+    }
+
+    #[test]
+    fn exposure_time_takes_precedence_over_the_shutter_speed_value() {
+        let exif = exif(&[
+            rational(Tag::ExposureTime, 1, 100),
+            shutter_speed_value(8, 1),
+        ]);
+
+        assert_close(get_exposures(&exif).expect("The exposure is present"), 0.01);
+    }
+
+    #[test]
+    fn zero_and_negative_exposure_times_are_rejected() {
+        let invalid = [
+            rational(Tag::ExposureTime, 0, 1),
+            rational(Tag::ExposureTime, 1, 0),
+            field(
+                Tag::ExposureTime,
+                Value::SRational(vec![exif::SRational::from((-1, 100))]),
+            ),
+        ];
+
+        for exposure_time in invalid {
+            assert!(get_exposures(&exif(std::slice::from_ref(&exposure_time))).is_err());
+
+            // The shutter speed value is used instead when it is present.
+            let exif = exif(&[exposure_time, shutter_speed_value(8, 1)]);
+            assert_close(
+                get_exposures(&exif).expect("The shutter speed value is valid"),
+                1. / 256.,
+            );
+        }
+    }
+
+    #[test]
+    fn missing_exposure_is_an_error() {
+        let exif = exif(&[field(Tag::PhotographicSensitivity, Value::Short(vec![100]))]);
+
+        assert!(get_exposures(&exif).is_err());
     }
 }
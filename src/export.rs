@@ -0,0 +1,212 @@
+//! Writers to persist merged HDR radiance in floating point image formats.
+
+use crate::output::HDRImage;
+use crate::Error;
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::error::{UnsupportedError, UnsupportedErrorKind};
+use image::{DynamicImage, ImageEncoder, ImageError, ImageFormat, Rgb};
+use ndarray::Axis;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Collect the pixels of the image as RGB triplets. Images with fewer than three channels have
+/// their first channel replicated into all three components, and only the first three channels
+/// of images with more channels are kept.
+fn rgb_pixels(hdr: &HDRImage) -> Vec<Rgb<f32>> {
+    hdr.get_buffer()
+        .lanes(Axis(2))
+        .into_iter()
+        .map(|pixel| {
+            if pixel.len() < 3 {
+                Rgb([pixel[0]; 3])
+            } else {
+                Rgb([pixel[0], pixel[1], pixel[2]])
+            }
+        })
+        .collect()
+}
+
+/// Save the image in the Radiance RGBE (`.hdr`) format.
+///
+/// Each pixel is stored as an 8-bit mantissa per channel with a shared 8-bit exponent, which
+/// covers a very wide dynamic range at roughly 1% relative precision. Scanlines are run-length
+/// encoded where the format allows it.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `path`: Path of the file to write
+///
+/// returns: `Result<(), Error>`
+///
+/// # Errors
+///
+/// - If the file cannot be created or written to
+pub fn save_radiance(hdr: &HDRImage, path: impl AsRef<Path>) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    HdrEncoder::new(&mut writer).encode(
+        &rgb_pixels(hdr),
+        hdr.get_width() as usize,
+        hdr.get_height() as usize,
+    )?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Save the image as a 32-bit float RGB `OpenEXR` (`.exr`) file.
+///
+/// Radiance values are written as is, so no precision is lost. Grayscale images are written as
+/// RGB with identical channels. Images with an alpha channel, see [`HDRImage::has_alpha`], are
+/// written as RGBA. `OpenEXR` readers expect premultiplied alpha, see
+/// [`crate::config::MergeConfig::premultiply_alpha`].
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `path`: Path of the file to write
+///
+/// returns: `Result<(), Error>`
+///
+/// # Errors
+///
+/// - If the file cannot be created or the `OpenEXR` encoder fails
+#[cfg(feature = "exr")]
+pub fn save_exr(hdr: &HDRImage, path: impl AsRef<Path>) -> Result<(), Error> {
+    let width = hdr.get_width() as usize;
+    let pixels = rgb_pixels(hdr);
+
+    if hdr.has_alpha() {
+        let buffer = hdr.get_buffer();
+        let alpha = hdr.get_channels() - 1;
+
+        exr::prelude::write_rgba_file(path, width, hdr.get_height() as usize, |x, y| {
+            let Rgb([red, green, blue]) = pixels[y * width + x];
+
+            (red, green, blue, buffer[[y, x, alpha]])
+        })?;
+    } else {
+        exr::prelude::write_rgb_file(path, width, hdr.get_height() as usize, |x, y| {
+            let Rgb([red, green, blue]) = pixels[y * width + x];
+
+            (red, green, blue)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Save the image as a Portable Float Map (`.pfm`) file.
+///
+/// RGB images are written with the `PF` header and grayscale images with the `Pf` header. Only
+/// the first three channels of images with more channels are written, and only the first one of
+/// images with two channels.
+/// Values are written as little-endian 32-bit floats, which is marked by the negative scale, with
+/// the bottom row first as the format requires. No precision is lost, and reading the file back
+/// reproduces the exact radiance values.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `path`: Path of the file to write
+///
+/// returns: `Result<(), Error>`
+///
+/// # Errors
+///
+/// - If the file cannot be created or written to
+pub fn save_pfm(hdr: &HDRImage, path: impl AsRef<Path>) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let channels = if hdr.get_channels() < 3 { 1 } else { 3 };
+    let header = if channels == 1 { "Pf" } else { "PF" };
+
+    write!(
+        writer,
+        "{header}\n{} {}\n-1.0\n",
+        hdr.get_width(),
+        hdr.get_height()
+    )?;
+
+    for row in hdr.get_buffer().outer_iter().rev() {
+        for pixel in row.outer_iter() {
+            for value in pixel.iter().take(channels) {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Save an image, e.g. a tone mapped result, with the given EXIF metadata embedded. The format
+/// is inferred from the file extension, and must be one of JPEG, PNG or WebP. Use
+/// [`crate::exif::encode_exif_tags`] to pick the tags to carry over from a frame of the stack.
+///
+/// # Arguments
+///
+/// * `image`: The image to save
+/// * `path`: Path of the file to write
+/// * `exif`: Encoded EXIF data. If empty, no metadata is written.
+///
+/// returns: `Result<(), Error>`
+///
+/// # Errors
+///
+/// - If the format cannot be inferred from the path, or doesn't support EXIF metadata
+/// - If the file cannot be created or written to
+pub fn save_with_exif(
+    image: &DynamicImage,
+    path: impl AsRef<Path>,
+    exif: Vec<u8>,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)?;
+
+    if !matches!(
+        format,
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP
+    ) {
+        return Err(Error::ImageError(ImageError::Unsupported(
+            UnsupportedError::from_format_and_kind(
+                format.into(),
+                UnsupportedErrorKind::GenericFeature("EXIF metadata".to_string()),
+            ),
+        )));
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        ImageFormat::Jpeg => write_with_exif(image, JpegEncoder::new(&mut writer), exif),
+        ImageFormat::Png => write_with_exif(image, PngEncoder::new(&mut writer), exif),
+        _ => write_with_exif(image, WebPEncoder::new_lossless(&mut writer), exif),
+    }?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_with_exif(
+    image: &DynamicImage,
+    mut encoder: impl ImageEncoder,
+    exif: Vec<u8>,
+) -> Result<(), Error> {
+    if !exif.is_empty() {
+        encoder
+            .set_exif_metadata(exif)
+            .map_err(ImageError::Unsupported)?;
+    }
+
+    image.write_with_encoder(encoder)?;
+
+    Ok(())
+}
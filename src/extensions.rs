@@ -13,6 +13,8 @@ pub trait NDArrayBuffer {
 }
 
 impl NDArrayBuffer for DynamicImage {
+    /// Grayscale images produce a single channel and other images three RGB channels, normalised
+    /// to `[0, 1]` at the full bit depth of the image.
     fn to_nd_array_buffer(&self) -> Array3<f32> {
         match self {
             DynamicImage::ImageLuma8(_)
@@ -45,10 +47,14 @@ impl NDArrayBuffer for DynamicImage {
         }
     }
 
+    /// Buffers with fewer than three channels produce a grayscale image of their first channel,
+    /// and other buffers an RGB image of their first three channels.
     #[allow(clippy::cast_possible_truncation)]
     #[allow(clippy::cast_sign_loss)]
     fn from_nd_array_buffer(buffer: Array3<f32>) -> Self {
-        if let (height, width, 1) = buffer.dim() {
+        let (height, width, channels) = buffer.dim();
+
+        if channels < 3 {
             let mut result = ImageBuffer::<Luma<u16>, Vec<u16>>::new(width as u32, height as u32);
             for (x, y, pixel) in result.enumerate_pixels_mut() {
                 let intensity = buffer[[y as usize, x as usize, 0]] * f32::from(u16::MAX);
@@ -56,7 +62,7 @@ impl NDArrayBuffer for DynamicImage {
             }
 
             DynamicImage::ImageLuma16(result)
-        } else if let (height, width, 3) = buffer.dim() {
+        } else {
             let mut result = ImageBuffer::<Rgb<f32>, Vec<f32>>::new(width as u32, height as u32);
             for (x, y, pixel) in result.enumerate_pixels_mut() {
                 let red = buffer[[y as usize, x as usize, 0]];
@@ -67,8 +73,6 @@ impl NDArrayBuffer for DynamicImage {
             }
 
             DynamicImage::ImageRgb32F(result)
-        } else {
-            panic!("Unexpected dimensions encountered");
         }
     }
 }
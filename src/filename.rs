@@ -0,0 +1,152 @@
+//! Exposure times encoded in file names, for files whose EXIF metadata was stripped, e.g. scanned
+//! film or published HDR datasets.
+
+use crate::Error;
+use std::path::Path;
+use std::time::Duration;
+
+/// Pattern used by [`ExposurePattern::default`]. It matches file names ending with an underscore
+/// followed by the exposure time in seconds and an `s`, e.g. `shot_1-250s.jpg` for 1/250 s or
+/// `shot_2s.jpg` for 2 s.
+pub const DEFAULT_EXPOSURE_PATTERN: &str = "*_{exposure}s";
+
+/// Placeholder for the exposure time in an [`ExposurePattern`].
+const EXPOSURE_PLACEHOLDER: &str = "{exposure}";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Literal(char),
+    Wildcard,
+}
+
+/// Pattern extracting the exposure time from a file name.
+///
+/// The pattern is matched against the whole file name without its extension, ignoring ASCII case.
+/// `{exposure}` matches the exposure time in seconds, and must appear exactly once. `*` matches
+/// any text, possibly empty, and every other character matches itself. The exposure time is
+/// written either as a decimal number, e.g. `0.5` or `30`, or as a fraction whose numerator and
+/// denominator are separated by `-`, since `/` cannot appear in file names, e.g. `1-250`.
+///
+/// See [`DEFAULT_EXPOSURE_PATTERN`] for the default pattern. A dataset named like
+/// `IMG_0042-exp0.004.tif` would use `*-exp{exposure}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExposurePattern {
+    pattern: String,
+    /// Tokens before and after the exposure time
+    tokens: (Vec<Token>, Vec<Token>),
+}
+
+impl ExposurePattern {
+    /// Create new [`ExposurePattern`]
+    ///
+    /// # Errors
+    ///
+    /// - If the pattern doesn't contain `{exposure}` exactly once
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        if pattern.matches(EXPOSURE_PLACEHOLDER).count() != 1 {
+            return Err(Error::InputError {
+                parameter_name: "pattern".to_string(),
+                message: format!(
+                    "Pattern must contain {EXPOSURE_PLACEHOLDER} exactly once, got \"{pattern}\""
+                ),
+            });
+        }
+
+        let tokenize = |text: &str| {
+            text.chars()
+                .map(|character| {
+                    if character == '*' {
+                        Token::Wildcard
+                    } else {
+                        Token::Literal(character)
+                    }
+                })
+                .collect::<Vec<Token>>()
+        };
+        let (before, after) = pattern
+            .split_once(EXPOSURE_PLACEHOLDER)
+            .unwrap_or_else(|| unreachable!("The placeholder was found"));
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            tokens: (tokenize(before), tokenize(after)),
+        })
+    }
+
+    /// Get the pattern as it was written
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Extract the exposure time from the file name of `path`.
+    ///
+    /// returns: The exposure time, or `None` if the file name doesn't match the pattern, or the
+    /// matched exposure time is not a positive duration.
+    #[must_use]
+    pub fn exposure(&self, path: impl AsRef<Path>) -> Option<Duration> {
+        let name = path
+            .as_ref()
+            .file_stem()?
+            .to_str()?
+            .chars()
+            .collect::<Vec<char>>();
+        let (before, after) = &self.tokens;
+
+        // Wildcards match as little as possible, and the exposure time as much as possible.
+        (0..name.len()).find_map(|start| {
+            if !matches(before, &name[..start]) {
+                return None;
+            }
+
+            (start + 1..=name.len()).rev().find_map(|end| {
+                parse_exposure(&name[start..end].iter().collect::<String>())
+                    .filter(|_| matches(after, &name[end..]))
+            })
+        })
+    }
+}
+
+impl Default for ExposurePattern {
+    fn default() -> Self {
+        Self::new(DEFAULT_EXPOSURE_PATTERN)
+            .unwrap_or_else(|_| unreachable!("The default pattern is valid"))
+    }
+}
+
+/// Whether `tokens` match the whole of `text`.
+fn matches(tokens: &[Token], text: &[char]) -> bool {
+    match tokens.split_first() {
+        None => text.is_empty(),
+        Some((Token::Literal(expected), rest)) => {
+            text.split_first().is_some_and(|(character, text)| {
+                character.eq_ignore_ascii_case(expected) && matches(rest, text)
+            })
+        }
+        Some((Token::Wildcard, rest)) => (0..=text.len()).any(|skip| matches(rest, &text[skip..])),
+    }
+}
+
+/// Parse an exposure time in seconds written as a decimal number or as a `-` separated fraction.
+fn parse_exposure(text: &str) -> Option<Duration> {
+    let parse = |text: &str| {
+        if text.is_empty()
+            || !text
+                .chars()
+                .all(|character| character.is_ascii_digit() || character == '.')
+        {
+            return None;
+        }
+
+        text.parse::<f64>().ok()
+    };
+
+    let seconds = match text.split_once('-') {
+        Some((numerator, denominator)) => parse(numerator)? / parse(denominator)?,
+        None => parse(text)?,
+    };
+
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|exposure| !exposure.is_zero())
+}
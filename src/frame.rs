@@ -0,0 +1,79 @@
+//! Frames decoded outside of this crate, e.g. by a custom RAW pipeline, a camera SDK or a
+//! synthetic source, without going through the `image` crate or EXIF metadata.
+//!
+//! Any iterator of `Result<Frame, Error>` is a source of frames for [`crate::hdr_merge_frames`],
+//! so a decoder only has to produce [`Frame`]s, e.g. with [`std::iter::from_fn`] around a
+//! camera callback or with [`Iterator::map`] over a list of files.
+
+use crate::input::HDRInput;
+use crate::Error;
+use ndarray::Array3;
+use std::time::Duration;
+
+/// A single decoded frame of a stack along with its exposure and gain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Normalised and linear pixel values, with `1.0` being the largest value the sensor can
+    /// record, stored row-major with interleaved channels
+    pub pixels: Vec<f32>,
+    /// Width of the frame in pixels
+    pub width: u32,
+    /// Height of the frame in pixels
+    pub height: u32,
+    /// Number of channels per pixel, e.g. `3` for RGB
+    pub channels: usize,
+    /// Exposure time
+    pub exposure: Duration,
+    /// Gain
+    pub gain: f32,
+}
+
+impl Frame {
+    /// Create new RGB [`Frame`] with three interleaved channels per pixel.
+    #[must_use]
+    pub fn rgb(pixels: Vec<f32>, width: u32, height: u32, exposure: Duration, gain: f32) -> Self {
+        Self {
+            pixels,
+            width,
+            height,
+            channels: 3,
+            exposure,
+            gain,
+        }
+    }
+}
+
+impl TryFrom<Frame> for HDRInput {
+    type Error = Error;
+
+    /// Convert the frame with [`HDRInput::with_buffer`].
+    ///
+    /// # Errors
+    ///
+    /// - If the number of pixel values doesn't match the dimensions and channels of the frame
+    /// - If the frame has no pixels or no channels
+    /// - invalid gain
+    /// - invalid exposure duration
+    fn try_from(frame: Frame) -> Result<Self, Error> {
+        let Frame {
+            pixels,
+            width,
+            height,
+            channels,
+            exposure,
+            gain,
+        } = frame;
+        let length = pixels.len();
+
+        let buffer = Array3::from_shape_vec((height as usize, width as usize, channels), pixels)
+            .map_err(|_| Error::InputError {
+                parameter_name: "pixels".to_string(),
+                message: format!(
+                    "Expected {} values for a {width}x{height} frame with {channels} channel(s), got {length}",
+                    width as usize * height as usize * channels
+                ),
+            })?;
+
+        HDRInput::with_buffer(buffer, exposure, gain)
+    }
+}
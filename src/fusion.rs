@@ -0,0 +1,292 @@
+//! An implementation of exposure fusion as introduced in
+//! [Exposure Fusion](https://mericam.github.io/papers/exposure_fusion_reduced.pdf) by Mertens,
+//! Kautz and Van Reeth.
+//!
+//! Unlike the HDR merge, exposure fusion doesn't estimate radiance and doesn't need exposure
+//! times or gains. Each image is weighted per pixel by its local contrast, color saturation and
+//! well-exposedness, and the images are blended across a Laplacian pyramid to avoid seams. The
+//! result is a displayable image that doesn't need to be tone mapped.
+
+use crate::extensions::NDArrayBuffer;
+use crate::input::read_each;
+use crate::io::read_image_file;
+use crate::parallel::prelude::*;
+use crate::tonemap::{luminance, quantize_to_dynamic_image, BitDepth};
+use crate::{validate_image_count, Error};
+use image::DynamicImage;
+use ndarray::{s, Array2, Array3, Axis, Zip};
+use std::path::Path;
+
+/// Spread of the gaussian curve around `0.5` used for the well-exposedness weight.
+const WELL_EXPOSEDNESS_SIGMA: f32 = 0.2;
+
+/// Small offset added to the weights, so that pixels where every weight is zero are averaged.
+const WEIGHT_EPSILON: f32 = 1e-12;
+
+/// Smallest dimension of the coarsest pyramid level.
+const MIN_PYRAMID_SIZE: usize = 8;
+
+/// Separable 5-tap binomial kernel used to blur pyramid levels.
+const KERNEL: [f32; 5] = [1. / 16., 4. / 16., 6. / 16., 4. / 16., 1. / 16.];
+
+/// Given a set of file paths, attempt to read the images and fuse them into a single 8-bit image
+/// with exposure fusion. EXIF metadata is not needed.
+///
+/// # Errors
+/// - If any of the images cannot be read, listing every failure
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+pub fn exposure_fusion<P: AsRef<Path> + Sync>(paths: &[P]) -> Result<DynamicImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let images = read_each(
+        paths,
+        |path| Some(path.as_ref()),
+        |path, _| read_image_file(path.as_ref()).map(|(_, image)| image),
+    )?;
+
+    exposure_fusion_images(&images)
+}
+
+/// Fuse a set of already decoded images into a single 8-bit image with exposure fusion.
+///
+/// The images are blended as they are, without any linearization, and the result uses the same
+/// encoding as the inputs. Stacks of grayscale images produce a grayscale image, everything else
+/// produces an RGB image.
+///
+/// # Errors
+/// - If fewer than two images are provided
+/// - If images are of different dimensions.
+pub fn exposure_fusion_images(images: &[DynamicImage]) -> Result<DynamicImage, Error> {
+    validate_image_count(images.len())?;
+
+    let buffers = images
+        .par_iter()
+        .map(NDArrayBuffer::to_nd_array_buffer)
+        .collect::<Vec<Array3<f32>>>();
+
+    let shape = buffers[0].dim();
+    if let Some(index) = buffers.iter().position(|buffer| buffer.dim() != shape) {
+        let (height, width, _) = buffers[index].dim();
+
+        return Err(Error::InputError {
+            parameter_name: "images".to_string(),
+            message: format!(
+                "Image at index {index} is {width}x{height}, expected {}x{} with the same color type as the first image",
+                shape.1, shape.0
+            ),
+        });
+    }
+
+    let weights = normalized_weights(&buffers);
+    let (height, width, channels) = shape;
+    let mut levels = 1;
+    while height.min(width) >> levels >= MIN_PYRAMID_SIZE {
+        levels += 1;
+    }
+
+    let weight_pyramids = weights
+        .par_iter()
+        .map(|weight| gaussian_pyramid(weight.clone(), levels))
+        .collect::<Vec<_>>();
+
+    let fused_channels = (0..channels)
+        .into_par_iter()
+        .map(|channel| {
+            let mut blended: Option<Vec<Array2<f32>>> = None;
+
+            for (buffer, weights) in buffers.iter().zip(&weight_pyramids) {
+                let pyramid =
+                    laplacian_pyramid(buffer.index_axis(Axis(2), channel).to_owned(), levels);
+
+                let blended = blended.get_or_insert_with(|| {
+                    pyramid
+                        .iter()
+                        .map(|level| Array2::zeros(level.dim()))
+                        .collect()
+                });
+
+                for ((blended, level), weight) in blended.iter_mut().zip(&pyramid).zip(weights) {
+                    Zip::from(blended)
+                        .and(level)
+                        .and(weight)
+                        .for_each(|blended, level, weight| *blended += level * weight);
+                }
+            }
+
+            collapse(blended.unwrap_or_default())
+        })
+        .collect::<Vec<Array2<f32>>>();
+
+    let mut fused = Array3::<f32>::zeros(shape);
+    for (channel, values) in fused_channels.iter().enumerate() {
+        fused.index_axis_mut(Axis(2), channel).assign(values);
+    }
+
+    Ok(quantize_to_dynamic_image(
+        &fused,
+        |value| value,
+        BitDepth::Eight,
+    ))
+}
+
+/// Compute the weight of each pixel of each image from its contrast, saturation and
+/// well-exposedness, normalized so that the weights of each pixel sum up to one.
+#[allow(clippy::cast_precision_loss)]
+#[cfg_attr(not(feature = "parallel"), allow(clippy::needless_for_each))]
+fn normalized_weights(buffers: &[Array3<f32>]) -> Vec<Array2<f32>> {
+    let mut weights = buffers
+        .par_iter()
+        .map(|buffer| {
+            let gray = Array2::from_shape_fn((buffer.dim().0, buffer.dim().1), |(y, x)| {
+                luminance(buffer.slice(s![y, x, ..]))
+            });
+            let contrast = laplacian_magnitude(&gray);
+
+            let mut weight = Array2::<f32>::zeros(gray.dim());
+            Zip::from(&mut weight)
+                .and(&contrast)
+                .and(buffer.lanes(Axis(2)))
+                .par_for_each(|weight, contrast, pixel| {
+                    let mean = pixel.sum() / pixel.len() as f32;
+                    let saturation = if pixel.len() < 3 {
+                        1.
+                    } else {
+                        (pixel.mapv(|value| (value - mean).powi(2)).sum() / pixel.len() as f32)
+                            .sqrt()
+                    };
+                    let well_exposedness = pixel.fold(1., |product, value| {
+                        product
+                            * (-(value - 0.5).powi(2)
+                                / (2. * WELL_EXPOSEDNESS_SIGMA * WELL_EXPOSEDNESS_SIGMA))
+                                .exp()
+                    });
+
+                    *weight = contrast * saturation * well_exposedness + WEIGHT_EPSILON;
+                });
+
+            weight
+        })
+        .collect::<Vec<Array2<f32>>>();
+
+    let mut total = Array2::<f32>::zeros(weights[0].dim());
+    for weight in &weights {
+        total += weight;
+    }
+
+    weights.par_iter_mut().for_each(|weight| *weight /= &total);
+
+    weights
+}
+
+/// Absolute response of a 3x3 laplacian filter, with edges replicated.
+fn laplacian_magnitude(gray: &Array2<f32>) -> Array2<f32> {
+    let (height, width) = gray.dim();
+
+    Array2::from_shape_fn((height, width), |(y, x)| {
+        let up = gray[[y.saturating_sub(1), x]];
+        let down = gray[[(y + 1).min(height - 1), x]];
+        let left = gray[[y, x.saturating_sub(1)]];
+        let right = gray[[y, (x + 1).min(width - 1)]];
+
+        (up + down + left + right - 4. * gray[[y, x]]).abs()
+    })
+}
+
+/// Blur with the binomial kernel along both axes, with edges replicated.
+#[allow(clippy::cast_possible_wrap)]
+#[allow(clippy::cast_sign_loss)]
+fn blur(image: &Array2<f32>) -> Array2<f32> {
+    let (height, width) = image.dim();
+    let clamp = |value: isize, size: usize| value.clamp(0, size as isize - 1) as usize;
+
+    let horizontal = Array2::from_shape_fn((height, width), |(y, x)| {
+        KERNEL
+            .iter()
+            .enumerate()
+            .map(|(offset, factor)| {
+                factor * image[[y, clamp(x as isize + offset as isize - 2, width)]]
+            })
+            .sum::<f32>()
+    });
+
+    Array2::from_shape_fn((height, width), |(y, x)| {
+        KERNEL
+            .iter()
+            .enumerate()
+            .map(|(offset, factor)| {
+                factor * horizontal[[clamp(y as isize + offset as isize - 2, height), x]]
+            })
+            .sum::<f32>()
+    })
+}
+
+/// Blur and halve the resolution of an image.
+fn reduce(image: &Array2<f32>) -> Array2<f32> {
+    let blurred = blur(image);
+    let (height, width) = image.dim();
+
+    Array2::from_shape_fn((height.div_ceil(2), width.div_ceil(2)), |(y, x)| {
+        blurred[[2 * y, 2 * x]]
+    })
+}
+
+/// Upscale an image to the given dimensions with bilinear interpolation.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_sign_loss)]
+fn expand(image: &Array2<f32>, (height, width): (usize, usize)) -> Array2<f32> {
+    let (source_height, source_width) = image.dim();
+
+    Array2::from_shape_fn((height, width), |(y, x)| {
+        let source_y = (y as f32 / 2.).min((source_height - 1) as f32);
+        let source_x = (x as f32 / 2.).min((source_width - 1) as f32);
+        let (y0, x0) = (source_y as usize, source_x as usize);
+        let (y1, x1) = (
+            (y0 + 1).min(source_height - 1),
+            (x0 + 1).min(source_width - 1),
+        );
+        let (fraction_y, fraction_x) = (source_y - y0 as f32, source_x - x0 as f32);
+
+        let top = image[[y0, x0]] * (1. - fraction_x) + image[[y0, x1]] * fraction_x;
+        let bottom = image[[y1, x0]] * (1. - fraction_x) + image[[y1, x1]] * fraction_x;
+
+        top * (1. - fraction_y) + bottom * fraction_y
+    })
+}
+
+fn gaussian_pyramid(image: Array2<f32>, levels: usize) -> Vec<Array2<f32>> {
+    let mut pyramid = vec![image];
+
+    for level in 1..levels {
+        let reduced = reduce(&pyramid[level - 1]);
+        pyramid.push(reduced);
+    }
+
+    pyramid
+}
+
+fn laplacian_pyramid(image: Array2<f32>, levels: usize) -> Vec<Array2<f32>> {
+    let gaussian = gaussian_pyramid(image, levels);
+
+    (0..levels)
+        .map(|level| {
+            if level + 1 == levels {
+                gaussian[level].clone()
+            } else {
+                &gaussian[level] - &expand(&gaussian[level + 1], gaussian[level].dim())
+            }
+        })
+        .collect()
+}
+
+/// Reconstruct an image from its laplacian pyramid.
+fn collapse(mut pyramid: Vec<Array2<f32>>) -> Array2<f32> {
+    let mut image = pyramid.pop().unwrap_or_default();
+
+    while let Some(level) = pyramid.pop() {
+        image = expand(&image, level.dim()) + level;
+    }
+
+    image
+}
@@ -0,0 +1,674 @@
+//! Poisson estimate on the GPU with [wgpu](https://docs.rs/wgpu), enabled with the `gpu` feature.
+//!
+//! The radiance scaling and weighted accumulation run as a compute shader. Frames are uploaded
+//! one at a time as textures and accumulated into float buffers on the device, and only the
+//! merged radiance, its confidence and the saturated pixel counts are read back. The result is the
+//! same as on the CPU up to floating point rounding, so callers don't need to know which backend
+//! merged their stack.
+//!
+//! Stacks are merged on the CPU instead when the machine has no hardware adapter, when the frames
+//! don't fit the limits of the device, or when the merge needs steps the shader doesn't
+//! implement: cropping, calibration frames, vignetting correction, deghosting, weight maps and
+//! combining alpha. Unlike the CPU path, the pixel buffers of the inputs are left as they are
+//! rather than converted to radiance in place.
+
+use crate::config::{AlphaMode, InputEncoding, MergeConfig, WeightFunction};
+use crate::input::HDRInput;
+use crate::poisson::{Estimate, RadianceScale, CLIPPED_SAMPLE_WEIGHT};
+use crate::Error;
+use ndarray::{Array2, Array3, Axis};
+use std::sync::{mpsc, OnceLock};
+
+/// Size of the square workgroups of the shader, see `gpu.wgsl`
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Size of the `Frame` uniform of the shader, in 32-bit words
+const FRAME_WORDS: usize = 24;
+
+/// Calculate the poisson estimate on the shared GPU, see [`Gpu::shared`].
+///
+/// returns: `None` if the stack has to be merged on the CPU, see the [module](self) documentation.
+///
+/// # Errors
+/// If the coefficients or saturation thresholds don't apply to the number of channels of a frame,
+/// or the exposure model includes the aperture while the aperture of a frame is unknown.
+pub(crate) fn calculate_estimate(
+    inputs: &[HDRInput],
+    config: &MergeConfig,
+    progress: &dyn Fn(f32),
+) -> Result<Option<Estimate>, Error> {
+    match Gpu::shared() {
+        Some(gpu) => gpu.estimate(inputs, config, progress),
+        None => Ok(None),
+    }
+}
+
+/// Device the shader runs on, with its pipelines.
+pub(crate) struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layout: wgpu::BindGroupLayout,
+    accumulate: wgpu::ComputePipeline,
+    finish: wgpu::ComputePipeline,
+    limits: wgpu::Limits,
+}
+
+impl Gpu {
+    /// Device of the first hardware adapter, created on first use and shared by every merge.
+    /// `None` if there is no such adapter. Software adapters, e.g. llvmpipe, are skipped since
+    /// they are slower than the CPU path.
+    fn shared() -> Option<&'static Self> {
+        static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+
+        GPU.get_or_init(|| Self::new(false)).as_ref()
+    }
+
+    /// Create a device on the preferred adapter, or `None` if there is no adapter, or only a
+    /// software one while `allow_software` is `false`.
+    fn new(allow_software: bool) -> Option<Self> {
+        let instance =
+            wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))
+        .ok()?;
+
+        let info = adapter.get_info();
+        let compute = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        if !compute || (!allow_software && info.device_type == wgpu::DeviceType::Cpu) {
+            return None;
+        }
+
+        let limits = adapter.limits();
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("image-hdr"),
+            required_limits: limits.clone(),
+            ..Default::default()
+        }))
+        .ok()?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(adapter = info.name, backend = ?info.backend, "Created GPU device");
+
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("image-hdr"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage(2),
+                storage(3),
+                storage(4),
+                storage(5),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("image-hdr"),
+            bind_group_layouts: &[Some(&layout)],
+            immediate_size: 0,
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("gpu.wgsl"));
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        Some(Self {
+            accumulate: pipeline("accumulate"),
+            finish: pipeline("finish"),
+            device,
+            queue,
+            layout,
+            limits,
+        })
+    }
+
+    /// Whether the shader implements every step of the merge of the stack, and its frames fit the
+    /// limits of the device.
+    fn supports(&self, inputs: &[HDRInput], config: &MergeConfig) -> bool {
+        let Some(first) = inputs.first() else {
+            return false;
+        };
+        let (height, width, channels) = first.get_buffer().dim();
+        let pixels = (height * width) as u64;
+        let limits = &self.limits;
+        let fits = |size: usize, limit: u32| u32::try_from(size).is_ok_and(|size| size <= limit);
+
+        let supported_config = config.crop.is_none()
+            && config.calibration.is_empty()
+            && config.vignetting.is_identity()
+            && !config.deghost;
+        let supported_inputs = inputs.iter().all(|input| {
+            input.get_buffer().dim() == (height, width, channels)
+                && input.get_weight_map().is_none()
+                && (input.get_alpha().is_none() || config.alpha == AlphaMode::Discard)
+        });
+        let buffer_size = pixels * 16;
+
+        supported_config
+            && supported_inputs
+            && (1..=4).contains(&channels)
+            && fits(width, limits.max_texture_dimension_2d)
+            && fits(height, limits.max_texture_dimension_2d)
+            && fits(
+                width.div_ceil(WORKGROUP_SIZE as usize),
+                limits.max_compute_workgroups_per_dimension,
+            )
+            && fits(
+                height.div_ceil(WORKGROUP_SIZE as usize),
+                limits.max_compute_workgroups_per_dimension,
+            )
+            && buffer_size <= limits.max_storage_buffer_binding_size
+            && buffer_size <= limits.max_buffer_size
+    }
+
+    /// Calculate the poisson estimate of the stack, like [`crate::poisson::accumulate`].
+    ///
+    /// returns: `None` if the stack has to be merged on the CPU, see [`Gpu::supports`], or if the
+    /// device runs out of memory or is lost during the merge.
+    ///
+    /// # Errors
+    /// See [`calculate_estimate`].
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn estimate(
+        &self,
+        inputs: &[HDRInput],
+        config: &MergeConfig,
+        progress: &dyn Fn(f32),
+    ) -> Result<Option<Estimate>, Error> {
+        if !self.supports(inputs, config) {
+            return Ok(None);
+        }
+
+        let (height, width, channels) = inputs[0].get_buffer().dim();
+        let pixels = height * width;
+        let frames = inputs.len();
+        let Some(resources) = self.allocate((width as u32, height as u32), frames) else {
+            return Ok(None);
+        };
+
+        let mut texels = vec![0_f32; pixels * 4];
+        let mut total_exposure = 0.;
+
+        for (index, input) in inputs.iter().enumerate() {
+            let frame = FrameUniform::new(input, config, index, resources.size, channels)?;
+            for (texel, pixel) in texels
+                .chunks_exact_mut(4)
+                .zip(input.get_buffer().lanes(Axis(2)))
+            {
+                texel.iter_mut().zip(pixel).for_each(|(texel, value)| {
+                    *texel = *value;
+                });
+            }
+
+            self.queue.write_texture(
+                resources.texture.as_image_copy(),
+                bytemuck::cast_slice(&texels),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(resources.size.width * 16),
+                    rows_per_image: None,
+                },
+                resources.size,
+            );
+            let encoder = self.dispatch(&resources, &self.accumulate, &frame);
+            self.queue.submit([encoder.finish()]);
+            total_exposure += input.get_exposure();
+
+            progress((index + 1) as f32 / frames as f32);
+        }
+
+        let mut frame = FrameUniform::default();
+        frame.set_float(Field::TotalExposure, total_exposure);
+        frame.set(Field::Width, resources.size.width);
+        frame.set(Field::Height, resources.size.height);
+        let Some((phi, well_exposed, counts)) = self.read_back(&resources, &frame, pixels) else {
+            return Ok(None);
+        };
+
+        let phi = phi
+            .chunks_exact(4)
+            .flat_map(|texel| &texel[..channels])
+            .copied()
+            .collect();
+        let shape = (height, width);
+
+        Ok(Some(Estimate {
+            phi: Array3::from_shape_vec((height, width, channels), phi)
+                .unwrap_or_else(|_| unreachable!("Every pixel has a value for each channel")),
+            confidence: Array2::from_shape_vec(shape, well_exposed)
+                .unwrap_or_else(|_| unreachable!("Every pixel has a confidence")),
+            alpha: match config.alpha {
+                AlphaMode::Discard => None,
+                // No frame has an alpha channel, see `supports`.
+                AlphaMode::Min | AlphaMode::Mean => Some(Array2::ones(shape)),
+            },
+            saturated_fractions: counts
+                .into_iter()
+                .map(|count| count as f32 / pixels as f32)
+                .collect(),
+            clipped_frames: Vec::new(),
+        }))
+    }
+
+    /// Allocate the texture and buffers of a merge of frames of the given width and height, or
+    /// `None` if the device is out of memory.
+    fn allocate(&self, (width, height): (u32, u32), frames: usize) -> Option<Resources> {
+        let pixels = u64::from(width) * u64::from(height);
+        let scope = self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        let buffer = |label, size: u64, usage| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let storage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
+        let uniform = buffer(
+            "frame",
+            FRAME_WORDS as u64 * 4,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        );
+        let phi = buffer("phi", pixels * 16, storage);
+        let total_weights = buffer("total_weights", pixels * 16, storage);
+        let well_exposed = buffer("well_exposed", pixels * 4, storage);
+        let saturated = buffer("saturated", frames as u64 * 4, storage);
+        let readback = buffer(
+            "readback",
+            pixels * 20 + frames as u64 * 4,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("frame"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        if pollster::block_on(scope.pop()).is_some() {
+            return None;
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("image-hdr"),
+            layout: &self.layout,
+            entries: &[
+                (0, uniform.as_entire_binding()),
+                (1, wgpu::BindingResource::TextureView(&view)),
+                (2, phi.as_entire_binding()),
+                (3, total_weights.as_entire_binding()),
+                (4, well_exposed.as_entire_binding()),
+                (5, saturated.as_entire_binding()),
+            ]
+            .map(|(binding, resource)| wgpu::BindGroupEntry { binding, resource }),
+        });
+
+        Some(Resources {
+            uniform,
+            phi,
+            well_exposed,
+            saturated,
+            readback,
+            texture,
+            bind_group,
+            size,
+        })
+    }
+
+    /// Record a dispatch of the given pipeline over every pixel, with the given contents of the
+    /// uniform.
+    fn dispatch(
+        &self,
+        resources: &Resources,
+        pipeline: &wgpu::ComputePipeline,
+        frame: &FrameUniform,
+    ) -> wgpu::CommandEncoder {
+        self.queue
+            .write_buffer(&resources.uniform, 0, bytemuck::cast_slice(&frame.0));
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &resources.bind_group, &[]);
+            pass.dispatch_workgroups(
+                resources.size.width.div_ceil(WORKGROUP_SIZE),
+                resources.size.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        encoder
+    }
+
+    /// Normalise the sums and read back the radiance, the confidence and the saturated pixel
+    /// counts of every frame, or `None` if the device is lost.
+    fn read_back(
+        &self,
+        resources: &Resources,
+        frame: &FrameUniform,
+        pixels: usize,
+    ) -> Option<(Vec<f32>, Vec<f32>, Vec<u32>)> {
+        let phi_size = pixels as u64 * 16;
+        let well_exposed_size = pixels as u64 * 4;
+        let readback = &resources.readback;
+
+        let mut encoder = self.dispatch(resources, &self.finish, frame);
+        encoder.copy_buffer_to_buffer(&resources.phi, 0, readback, 0, None);
+        encoder.copy_buffer_to_buffer(&resources.well_exposed, 0, readback, phi_size, None);
+        encoder.copy_buffer_to_buffer(
+            &resources.saturated,
+            0,
+            readback,
+            phi_size + well_exposed_size,
+            None,
+        );
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        readback.map_async(wgpu::MapMode::Read, .., move |result| {
+            // The receiver waits for the result below, so it is never dropped before this runs.
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+        receiver.recv().ok()?.ok()?;
+
+        let mapped = readback.get_mapped_range(..).ok()?;
+        let (phi, rest) = mapped.split_at(pixels * 16);
+        let (well_exposed, counts) = rest.split_at(pixels * 4);
+        let result = (
+            bytemuck::pod_collect_to_vec(phi),
+            bytemuck::pod_collect_to_vec(well_exposed),
+            bytemuck::pod_collect_to_vec(counts),
+        );
+        drop(mapped);
+        readback.unmap();
+
+        Some(result)
+    }
+}
+
+/// Texture and buffers of a merge. The bind group also holds the running sum of the weights,
+/// which is never read back.
+struct Resources {
+    uniform: wgpu::Buffer,
+    phi: wgpu::Buffer,
+    well_exposed: wgpu::Buffer,
+    saturated: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    size: wgpu::Extent3d,
+}
+
+/// Fields of the `Frame` uniform of the shader, by the index of their word
+#[derive(Clone, Copy)]
+enum Field {
+    Denominators = 0,
+    Thresholds = 4,
+    Exposure = 8,
+    PhotonScale,
+    ReadVariance,
+    NoiseFloor,
+    ClippedWeight,
+    MinWeight,
+    Gamma,
+    TotalExposure,
+    WeightFunction,
+    Encoding,
+    Channels,
+    Index,
+    Width,
+    Height,
+}
+
+/// Contents of the `Frame` uniform of the shader.
+#[derive(Default)]
+struct FrameUniform([u32; FRAME_WORDS]);
+
+impl FrameUniform {
+    /// Parameters of the conversion and weighting of a frame of the given size and number of
+    /// channels, at the given index in the stack.
+    ///
+    /// # Errors
+    /// See [`calculate_estimate`].
+    #[allow(clippy::cast_possible_truncation)]
+    fn new(
+        input: &HDRInput,
+        config: &MergeConfig,
+        index: usize,
+        size: wgpu::Extent3d,
+        channels: usize,
+    ) -> Result<Self, Error> {
+        let thresholds = config.saturation_thresholds(channels)?;
+        let scale = RadianceScale::new(input, config)?;
+        let noise_model = config.noise_model;
+        let mut frame = Self::default();
+
+        for channel in 0..channels {
+            frame.set_float_at(Field::Denominators, channel, scale.denominator(channel));
+            frame.set_float_at(Field::Thresholds, channel, thresholds[channel]);
+        }
+        frame.set_float(Field::Exposure, input.get_exposure());
+        frame.set_float(
+            Field::PhotonScale,
+            noise_model.photon_scale * input.get_gain(),
+        );
+        frame.set_float(
+            Field::ReadVariance,
+            noise_model.read_noise * noise_model.read_noise,
+        );
+        frame.set_float(Field::NoiseFloor, config.noise_floor);
+        frame.set_float(Field::ClippedWeight, CLIPPED_SAMPLE_WEIGHT);
+        frame.set_float(Field::MinWeight, WeightFunction::MIN_WEIGHT);
+        frame.set(
+            Field::WeightFunction,
+            match config.weight_function {
+                WeightFunction::Uniform => 0,
+                WeightFunction::Triangle => 1,
+                WeightFunction::Gaussian => 2,
+                WeightFunction::Plateau => 3,
+            },
+        );
+        let (encoding, gamma) = match config.input_encoding {
+            InputEncoding::Linear => (0, 1.),
+            InputEncoding::Srgb => (1, 1.),
+            InputEncoding::Gamma(gamma) => (2, gamma),
+        };
+        frame.set(Field::Encoding, encoding);
+        frame.set_float(Field::Gamma, gamma);
+        frame.set(Field::Channels, channels as u32);
+        frame.set(Field::Index, index as u32);
+        frame.set(Field::Width, size.width);
+        frame.set(Field::Height, size.height);
+
+        Ok(frame)
+    }
+
+    fn set(&mut self, field: Field, value: u32) {
+        self.0[field as usize] = value;
+    }
+
+    fn set_float(&mut self, field: Field, value: f32) {
+        self.set_float_at(field, 0, value);
+    }
+
+    /// Set a component of a vector field.
+    fn set_float_at(&mut self, field: Field, component: usize, value: f32) {
+        self.0[field as usize + component] = value.to_bits();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ColorCoefficients, NoiseModel};
+    use crate::poisson::accumulate;
+    use crate::test_support::Noise;
+    use std::time::Duration;
+
+    /// Device of any adapter, software ones included, so that the shader is tested on machines
+    /// without a GPU too. The tests using it are ignored by default, since some machines have no
+    /// adapter at all, and fail when run with `--ignored` on such a machine.
+    fn gpu() -> &'static Gpu {
+        static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+
+        GPU.get_or_init(|| Gpu::new(true))
+            .as_ref()
+            .expect("A wgpu adapter is available")
+    }
+
+    /// Noisy stack of the given number of channels, with highlights clipped in the longest
+    /// exposure and shadows below the noise floor in the shortest one. The dimensions are not a
+    /// multiple of the workgroup size.
+    fn stack(channels: usize) -> Vec<HDRInput> {
+        let mut noise = Noise::new(7);
+        let radiance = Array3::from_shape_simple_fn((13, 19, channels), || noise.next() * 8.);
+
+        [(0.01, 1.), (0.04, 2.), (0.2, 1.)]
+            .into_iter()
+            .map(|(exposure, gain)| {
+                let buffer = radiance.mapv(|radiance| (radiance * exposure * gain).min(1.));
+
+                HDRInput::with_buffer(buffer, Duration::from_secs_f32(exposure), gain)
+                    .expect("The exposure and gain are valid")
+            })
+            .collect()
+    }
+
+    fn assert_estimates_match(inputs: &[HDRInput], config: &MergeConfig) {
+        let gpu_estimate = gpu()
+            .estimate(inputs, config, &|_| {})
+            .expect("The stack is valid")
+            .expect("The configuration is supported");
+        let cpu_estimate = accumulate(
+            &mut inputs.to_vec(),
+            inputs[0].get_buffer().dim(),
+            config,
+            &|_| {},
+        )
+        .expect("The stack is valid");
+
+        assert_eq!(gpu_estimate.phi.dim(), cpu_estimate.phi.dim());
+        for (gpu, cpu) in gpu_estimate.phi.iter().zip(&cpu_estimate.phi) {
+            assert!(
+                (gpu - cpu).abs() <= cpu.abs() * 1e-4 + 1e-6,
+                "{gpu} != {cpu}"
+            );
+        }
+        for (gpu, cpu) in gpu_estimate.confidence.iter().zip(&cpu_estimate.confidence) {
+            assert!((gpu - cpu).abs() < 1e-5, "{gpu} != {cpu}");
+        }
+        assert_eq!(
+            gpu_estimate.saturated_fractions,
+            cpu_estimate.saturated_fractions
+        );
+        assert_eq!(gpu_estimate.alpha, cpu_estimate.alpha);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn gpu_estimate_matches_the_cpu_with_the_default_config() {
+        assert_estimates_match(&stack(3), &MergeConfig::default());
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn gpu_estimate_matches_the_cpu_with_noise_model_and_encoding() {
+        let config = MergeConfig {
+            coefficients: ColorCoefficients::new(0.9, 1., 1.2).expect("The coefficients are valid"),
+            channel_saturation_thresholds: Some(vec![0.95, 0.98, 0.9]),
+            noise_model: NoiseModel::new(0.01, 1e-3).expect("The noise model is valid"),
+            weight_function: WeightFunction::Triangle,
+            input_encoding: InputEncoding::Srgb,
+            min_scaling_factor: Some(0.02),
+            alpha: AlphaMode::Min,
+            ..MergeConfig::default()
+        };
+
+        assert_estimates_match(&stack(3), &config);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn gpu_estimate_matches_the_cpu_for_other_channel_counts() {
+        for (channels, weight_function) in [
+            (1, WeightFunction::Gaussian),
+            (2, WeightFunction::Plateau),
+            (4, WeightFunction::Uniform),
+        ] {
+            let config = MergeConfig {
+                coefficients: ColorCoefficients::with_channels(vec![1.; channels])
+                    .expect("The coefficients are valid"),
+                weight_function,
+                input_encoding: InputEncoding::Gamma(2.2),
+                ..MergeConfig::default()
+            };
+
+            assert_estimates_match(&stack(channels), &config);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn unsupported_steps_are_left_to_the_cpu() {
+        let config = MergeConfig {
+            deghost: true,
+            ..MergeConfig::default()
+        };
+
+        assert!(gpu()
+            .estimate(&stack(3), &config, &|_| {})
+            .expect("The stack is valid")
+            .is_none());
+    }
+}
@@ -0,0 +1,147 @@
+// Poisson estimate of a stack, see `Accumulator` in poisson.rs. `accumulate` converts one frame to
+// radiance and adds it to the running sums, `finish` normalises the sums once every frame is in.
+
+struct Frame {
+    // `exposure * gain * coefficient` of each channel, which linear values are divided by
+    denominators: vec4<f32>,
+    thresholds: vec4<f32>,
+    exposure: f32,
+    photon_scale: f32,
+    read_variance: f32,
+    noise_floor: f32,
+    clipped_weight: f32,
+    min_weight: f32,
+    gamma: f32,
+    total_exposure: f32,
+    // 0: uniform, 1: triangle, 2: gaussian, 3: plateau
+    weight_function: u32,
+    // 0: linear, 1: sRGB, 2: gamma
+    encoding: u32,
+    channels: u32,
+    index: u32,
+    width: u32,
+    height: u32,
+}
+
+@group(0) @binding(0) var<uniform> frame: Frame;
+@group(0) @binding(1) var pixels: texture_2d<f32>;
+@group(0) @binding(2) var<storage, read_write> phi: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> total_weights: array<vec4<f32>>;
+@group(0) @binding(4) var<storage, read_write> well_exposed: array<f32>;
+@group(0) @binding(5) var<storage, read_write> saturated: array<atomic<u32>>;
+
+// Saturated pixels of the workgroup, added to the count of the frame once per workgroup
+var<workgroup> saturated_pixels: atomic<u32>;
+
+// Same as `WeightFunction::weight`
+fn weight_function(value: f32) -> f32 {
+    let y = clamp(value, 0.0, 1.0);
+    let centered = 2.0 * y - 1.0;
+    var weight: f32;
+
+    switch frame.weight_function {
+        case 1u: {
+            weight = 1.0 - abs(centered);
+        }
+        case 2u: {
+            weight = exp(-(y - 0.5) * (y - 0.5) / (2.0 * 0.2 * 0.2));
+        }
+        case 3u: {
+            let cubed = centered * centered * centered;
+            weight = 1.0 - cubed * cubed * cubed * cubed;
+        }
+        default: {
+            return 1.0;
+        }
+    }
+
+    return max(weight, frame.min_weight);
+}
+
+// Same as `InputEncoding::linearize`
+fn linearize(value: f32) -> f32 {
+    switch frame.encoding {
+        case 1u: {
+            let y = clamp(value, 0.0, 1.0);
+            if y <= 0.04045 {
+                return y / 12.92;
+            }
+            return pow((y + 0.055) / 1.055, 2.4);
+        }
+        case 2u: {
+            if value <= 0.0 {
+                return 0.0;
+            }
+            return pow(value, frame.gamma);
+        }
+        default: {
+            return value;
+        }
+    }
+}
+
+// Accumulate the pixel at the given position, returning whether it is saturated in any channel
+fn add(position: vec2<u32>) -> bool {
+    let pixel = textureLoad(pixels, position, 0);
+    let index = position.y * frame.width + position.x;
+
+    var is_saturated = false;
+    var brightest = 0.0;
+    for (var channel = 0u; channel < frame.channels; channel++) {
+        is_saturated = is_saturated || pixel[channel] >= frame.thresholds[channel];
+        brightest = max(brightest, pixel[channel]);
+    }
+
+    if brightest >= frame.noise_floor && !is_saturated {
+        well_exposed[index] += frame.exposure;
+    }
+
+    for (var channel = 0u; channel < frame.channels; channel++) {
+        let value = pixel[channel];
+
+        var weight = frame.exposure;
+        if frame.read_variance > 0.0 {
+            // The smallest positive normal f32, like `f32::MIN_POSITIVE`
+            weight = frame.exposure
+                / (1.0 + frame.read_variance / (frame.photon_scale * max(value, 1.17549435e-38)));
+        }
+        weight *= weight_function(value);
+        if !(value >= frame.noise_floor && value < frame.thresholds[channel]) {
+            weight *= frame.clipped_weight;
+        }
+
+        let radiance = linearize(value) / frame.denominators[channel];
+        phi[index][channel] += radiance * weight;
+        total_weights[index][channel] += weight;
+    }
+
+    return is_saturated;
+}
+
+@compute @workgroup_size(8, 8)
+fn accumulate(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    if id.x < frame.width && id.y < frame.height && add(id.xy) {
+        atomicAdd(&saturated_pixels, 1u);
+    }
+
+    workgroupBarrier();
+    if local_index == 0u {
+        atomicAdd(&saturated[frame.index], atomicLoad(&saturated_pixels));
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn finish(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= frame.width || id.y >= frame.height {
+        return;
+    }
+
+    // Without masks every sample has a positive weight, the division is guarded like on the CPU.
+    let index = id.y * frame.width + id.x;
+    let weights = total_weights[index];
+    phi[index] = select(vec4<f32>(0.0), phi[index] / weights, weights > vec4<f32>(0.0));
+    well_exposed[index] /= frame.total_exposure;
+}
@@ -0,0 +1,304 @@
+//! Gradient domain HDR merging.
+//!
+//! Instead of averaging radiance, the log radiance gradients of the stack are combined into a
+//! single gradient field, taking each gradient from the frame in which both of its pixels are
+//! best exposed. The log radiance is then reconstructed from that field by solving the Poisson
+//! equation `∇²u = div G`. Seams between regions taken from different frames, e.g. around moving
+//! subjects, are spread out by the solver instead of showing up as edges.
+
+use crate::config::MergeConfig;
+use crate::extensions::NDArrayBuffer;
+use crate::input::{HDRInput, HDRInputList};
+use crate::output::HDRImage;
+#[cfg(not(feature = "parallel"))]
+use crate::parallel::prelude::*;
+use crate::poisson::calculate_radiance;
+use crate::{validate_image_count, validate_inputs, Error};
+use ndarray::{s, Array2, Array3, Axis, Zip};
+use std::path::Path;
+
+/// Spread of the gaussian curve around `0.5` used to rate how well exposed a sample is.
+const WELL_EXPOSEDNESS_SIGMA: f32 = 0.2;
+
+/// Relative quality of saturated samples and samples below the noise floor, so that they are
+/// only used when a pixel is clipped in every frame.
+const CLIPPED_SAMPLE_QUALITY: f32 = 1e-4;
+
+/// Smallest radiance whose log is taken, keeping the log radiance of black pixels finite.
+const MIN_RADIANCE: f32 = 1e-6;
+
+/// Maximum number of conjugate gradient iterations run for each channel.
+const MAX_SOLVER_ITERATIONS: usize = 1000;
+
+/// Residual, relative to the divergence of the gradient field, at which the solver stops.
+const SOLVER_TOLERANCE: f64 = 1e-4;
+
+/// Given a set of file paths, attempt to read the images along with their exposure and gain from
+/// EXIF metadata, and merge them in the gradient domain with the default [`MergeConfig`]. See
+/// [`merge_inputs_gradient`].
+///
+/// # Errors
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+pub fn merge_hdr_gradient<P: AsRef<Path> + Sync>(paths: &[P]) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let inputs = HDRInputList::try_from(paths)?;
+
+    merge_inputs_gradient(&inputs, &MergeConfig::default())
+}
+
+/// Merge a stack in the gradient domain. Every frame is converted to log radiance, and each
+/// horizontal and vertical gradient is taken from the frame in which the two pixels it spans are
+/// best exposed. The log radiance is then reconstructed from these gradients with a conjugate
+/// gradient Poisson solver, starting from a weighted average of the frames. The gradients only
+/// determine the log radiance up to a constant, which is chosen to match that average where the
+/// pixels are well exposed.
+///
+/// This is considerably slower than [`crate::hdr_merge_images_with_config`], but doesn't produce
+/// the seams that averaging leaves where the content differs between frames. Only
+/// [`MergeConfig::coefficients`], [`MergeConfig::vignetting`], [`MergeConfig::noise_floor`],
+/// [`MergeConfig::saturation_threshold`], [`MergeConfig::channel_saturation_thresholds`] and
+/// [`MergeConfig::non_finite`] affect the result. The inputs are left untouched.
+///
+/// # Errors
+/// - If fewer than two images are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+/// - If the result is not finite and [`MergeConfig::non_finite`] asks for an error
+#[allow(clippy::cast_possible_truncation)]
+pub fn merge_inputs_gradient(
+    inputs: &HDRInputList,
+    config: &MergeConfig,
+) -> Result<HDRImage, Error> {
+    config.validate()?;
+    validate_inputs(inputs)?;
+
+    let log_radiances = inputs
+        .as_slice()
+        .iter()
+        .map(|input| {
+            calculate_radiance(input, config)
+                .map(|radiance| radiance.mapv(|value| value.max(MIN_RADIANCE).ln()))
+        })
+        .collect::<Result<Vec<Array3<f32>>, Error>>()?;
+
+    let qualities = inputs
+        .as_slice()
+        .iter()
+        .map(|input| sample_quality(input, config))
+        .collect::<Result<Vec<Array3<f32>>, Error>>()?;
+
+    let shape = log_radiances[0].dim();
+    let mut merged = Array3::<f32>::zeros(shape);
+
+    for channel in 0..shape.2 {
+        let log_radiances = log_radiances
+            .iter()
+            .map(|log_radiance| log_radiance.index_axis(Axis(2), channel))
+            .collect::<Vec<_>>();
+        let qualities = qualities
+            .iter()
+            .map(|quality| quality.index_axis(Axis(2), channel))
+            .collect::<Vec<_>>();
+
+        let mut initial = Array2::<f32>::zeros((shape.0, shape.1));
+        let mut total_qualities = Array2::<f32>::zeros((shape.0, shape.1));
+        Zip::indexed(&mut initial)
+            .and(&mut total_qualities)
+            .par_for_each(|index, value, total| {
+                let (sum, total_quality) = log_radiances.iter().zip(&qualities).fold(
+                    (0., 0.),
+                    |(sum, total_quality), (log_radiance, quality)| {
+                        (
+                            sum + log_radiance[index] * quality[index],
+                            total_quality + quality[index],
+                        )
+                    },
+                );
+
+                *value = sum / total_quality;
+                *total = total_quality;
+            });
+
+        // Each gradient is taken from the frame in which the worse exposed of its two pixels is
+        // best exposed.
+        let best_gradient = |from: (usize, usize), to: (usize, usize)| {
+            log_radiances
+                .iter()
+                .zip(&qualities)
+                .map(|(log_radiance, quality)| {
+                    (
+                        quality[from].min(quality[to]),
+                        log_radiance[to] - log_radiance[from],
+                    )
+                })
+                .fold((f32::NEG_INFINITY, 0.), |best, candidate| {
+                    if candidate.0 > best.0 {
+                        candidate
+                    } else {
+                        best
+                    }
+                })
+                .1
+        };
+
+        let mut gradient_x = Array2::<f32>::zeros((shape.0, shape.1));
+        Zip::indexed(&mut gradient_x).par_for_each(|(y, x), gradient| {
+            if x + 1 < shape.1 {
+                *gradient = best_gradient((y, x), (y, x + 1));
+            }
+        });
+
+        let mut gradient_y = Array2::<f32>::zeros((shape.0, shape.1));
+        Zip::indexed(&mut gradient_y).par_for_each(|(y, x), gradient| {
+            if y + 1 < shape.0 {
+                *gradient = best_gradient((y, x), (y + 1, x));
+            }
+        });
+
+        // With `A = -∇²`, the system to solve is `A u = -div G`.
+        let mut rhs = Array2::<f32>::zeros((shape.0, shape.1));
+        Zip::indexed(&mut rhs).par_for_each(|(y, x), value| {
+            let mut divergence = gradient_x[[y, x]] + gradient_y[[y, x]];
+            if x > 0 {
+                divergence -= gradient_x[[y, x - 1]];
+            }
+            if y > 0 {
+                divergence -= gradient_y[[y - 1, x]];
+            }
+
+            *value = -divergence;
+        });
+
+        let mut log_radiance = solve_poisson(&rhs, initial.clone());
+
+        // Match the weighted average where the pixels are well exposed, rather than its plain
+        // mean, which is biased wherever every frame is clipped.
+        let total_quality = total_qualities.iter().map(|q| f64::from(*q)).sum::<f64>();
+        let offset = dot(&(&initial - &log_radiance), &total_qualities) / total_quality;
+        log_radiance += offset as f32;
+
+        Zip::from(merged.slice_mut(s![.., .., channel]))
+            .and(&log_radiance)
+            .par_for_each(|merged, log_radiance| *merged = log_radiance.exp());
+    }
+
+    config.non_finite.apply(&mut merged)?;
+
+    Ok(HDRImage::from_nd_array_buffer(merged))
+}
+
+/// How well exposed each sample of a frame is, from a gaussian curve around `0.5`. Clipped
+/// samples only get a tiny fraction of that quality.
+///
+/// # Errors
+/// If the saturation thresholds don't apply to the number of channels of the frame.
+fn sample_quality(input: &HDRInput, config: &MergeConfig) -> Result<Array3<f32>, Error> {
+    let noise_floor = config.noise_floor;
+    let thresholds = config.saturation_thresholds(input.get_buffer().dim().2)?;
+    let mut qualities = input.get_buffer().clone();
+
+    Zip::from(qualities.lanes_mut(Axis(2))).par_for_each(|mut pixel| {
+        pixel.zip_mut_with(&thresholds, |value, threshold| {
+            let quality = (-(*value - 0.5).powi(2)
+                / (2. * WELL_EXPOSEDNESS_SIGMA * WELL_EXPOSEDNESS_SIGMA))
+                .exp();
+
+            *value = if *value >= noise_floor && *value < *threshold {
+                quality
+            } else {
+                quality * CLIPPED_SAMPLE_QUALITY
+            };
+        });
+    });
+
+    Ok(qualities)
+}
+
+/// Negative Laplacian of `values`, with Neumann boundary conditions.
+fn negative_laplacian(values: &Array2<f32>) -> Array2<f32> {
+    let (height, width) = values.dim();
+    let mut result = Array2::<f32>::zeros((height, width));
+
+    Zip::indexed(&mut result).par_for_each(|(y, x), result| {
+        let center = values[[y, x]];
+        let mut sum = 0.;
+
+        if x > 0 {
+            sum += center - values[[y, x - 1]];
+        }
+        if x + 1 < width {
+            sum += center - values[[y, x + 1]];
+        }
+        if y > 0 {
+            sum += center - values[[y - 1, x]];
+        }
+        if y + 1 < height {
+            sum += center - values[[y + 1, x]];
+        }
+
+        *result = sum;
+    });
+
+    result
+}
+
+/// Dot product of two images, summing the products of each row and then the sums of the rows in
+/// order, so that the result doesn't depend on how the rows are split between threads.
+fn dot(a: &Array2<f32>, b: &Array2<f32>) -> f64 {
+    Zip::from(a.rows())
+        .and(b.rows())
+        .par_map_collect(|a, b| {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| f64::from(*a) * f64::from(*b))
+                .sum::<f64>()
+        })
+        .iter()
+        .sum()
+}
+
+/// Solve `-∇²u = rhs` with the conjugate gradient method, starting from `initial`. The Laplacian
+/// only determines `u` up to a constant, and the updates of the solver sum to zero, so the mean
+/// of `initial` is preserved.
+#[allow(clippy::cast_possible_truncation)]
+fn solve_poisson(rhs: &Array2<f32>, initial: Array2<f32>) -> Array2<f32> {
+    let mut solution = initial;
+    let mut residual = rhs - &negative_laplacian(&solution);
+    let mut direction = residual.clone();
+    let mut residual_norm = dot(&residual, &residual);
+    let target = dot(rhs, rhs) * SOLVER_TOLERANCE * SOLVER_TOLERANCE;
+
+    for _ in 0..MAX_SOLVER_ITERATIONS {
+        if residual_norm <= target {
+            break;
+        }
+
+        let projected = negative_laplacian(&direction);
+        let curvature = dot(&direction, &projected);
+        if curvature <= 0. {
+            break;
+        }
+
+        let step = (residual_norm / curvature) as f32;
+        Zip::from(&mut solution)
+            .and(&direction)
+            .par_for_each(|solution, direction| *solution += step * direction);
+        Zip::from(&mut residual)
+            .and(&projected)
+            .par_for_each(|residual, projected| *residual -= step * projected);
+
+        let next_norm = dot(&residual, &residual);
+        let scale = (next_norm / residual_norm) as f32;
+        Zip::from(&mut direction)
+            .and(&residual)
+            .par_for_each(|direction, residual| *direction = residual + scale * *direction);
+
+        residual_norm = next_norm;
+    }
+
+    solution
+}
@@ -1,13 +1,17 @@
 //! Input type for processing HDR merge
 
-use crate::exif::{get_exif_data, get_exposures, get_gains};
+use crate::config::{ExposureModel, MergeConfig};
+use crate::error::ReadFailure;
+use crate::exif::{get_apertures, get_exif_data, get_exposures, get_gains, ExifData};
 use crate::extensions::NDArrayBuffer;
-use crate::io::read_image;
+use crate::filename::ExposurePattern;
+use crate::io::read_image_file;
+use crate::parallel::prelude::*;
 use crate::Error;
-use image::DynamicImage;
-use ndarray::Array3;
-use rayon::prelude::*;
-use std::path::Path;
+use image::imageops::{self, FilterType};
+use image::{ColorType, DynamicImage, ImageBuffer, Luma};
+use ndarray::{s, Array3, Axis, Zip};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Base input item that is used to process the HDR merge
@@ -16,6 +20,13 @@ pub struct HDRInput {
     buffer: Array3<f32>,
     exposure: f32,
     gain: f32,
+    color_type: Option<ColorType>,
+    /// `(height, width, 1)` alpha of images that are not fully opaque
+    alpha: Option<Array3<f32>>,
+    /// `(height, width, 1)` weight map, see [`HDRInput::with_weight_map`]
+    weight_map: Option<Array3<f32>>,
+    /// f-number, see [`HDRInput::with_aperture`]
+    aperture: Option<f32>,
 }
 
 impl HDRInput {
@@ -32,19 +43,20 @@ impl HDRInput {
     ///
     /// - If image cannot be opened
     /// - If image doesn't contain EXIF metadata for exposure and/or gain.
-    pub fn new(path: &Path) -> Result<Self, crate::Error> {
-        let new_input = Self::try_from(path)?;
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let new_input = Self::try_from(path.as_ref())?;
 
         Ok(new_input)
     }
 
-    ///
+    /// Create new [`HDRInput`] from a given file path with the given exposure and gain. EXIF data
+    /// is not read.
     ///
     /// # Arguments
     ///
-    /// * `path`:
-    /// * `exposure`:
-    /// * `gain`:
+    /// * `path`: Path to file
+    /// * `exposure`: Exposure of the image
+    /// * `gain`: Gain of the image
     ///
     /// returns: `Result<HDRInput, Error>`
     ///
@@ -54,23 +66,151 @@ impl HDRInput {
     /// - invalid gain
     /// - invalid exposure duration
     pub fn with_exposure_and_gain(
-        path: &Path,
+        path: impl AsRef<Path>,
         exposure: Duration,
         gain: f32,
     ) -> Result<Self, Error> {
-        let data = std::fs::read(path)?;
-        let format = image::ImageFormat::from_path(path).ok();
-        let image = read_image(&data, format)?;
+        Self::with_overrides(path, Some(exposure), Some(gain))
+    }
+
+    /// Create new [`HDRInput`] from a given file path, optionally overriding the exposure and/or
+    /// gain. Values that are `None` are read from the file's EXIF data, and EXIF data is not parsed
+    /// at all when both values are provided.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path to file
+    /// * `exposure`: Exposure to use instead of the one in EXIF data
+    /// * `gain`: Gain to use instead of the one in EXIF data
+    ///
+    /// returns: `Result<HDRInput, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If image cannot be opened
+    /// - If a value is not overridden and image doesn't contain EXIF metadata for it.
+    /// - invalid gain
+    /// - invalid exposure duration
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()))
+    )]
+    pub fn with_overrides(
+        path: impl AsRef<Path>,
+        exposure: Option<Duration>,
+        gain: Option<f32>,
+    ) -> Result<Self, Error> {
+        let (data, image) = read_image_file(path.as_ref())?;
+
+        Self::with_decoded_file(&data, &image, exposure, gain)
+    }
+
+    /// Create new [`HDRInput`] from a given file path, reading the exposure time from the file
+    /// name with the given pattern when the file's EXIF data doesn't have it, e.g. because it
+    /// was stripped. The gain is read from EXIF data, and is `1.0` when it is missing too.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path to file
+    /// * `pattern`: Pattern to extract the exposure time from the file name with
+    ///
+    /// returns: `Result<HDRInput, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If image cannot be opened
+    /// - If neither the EXIF data nor the file name yield a valid exposure time
+    /// - invalid gain
+    pub fn with_exposure_pattern(
+        path: impl AsRef<Path>,
+        pattern: &ExposurePattern,
+    ) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let (data, image) = read_image_file(path)?;
+        let exif = get_exif_data(&data).ok();
+
+        let exposure = exif
+            .as_ref()
+            .and_then(|exif| exposure_from_exif(exif).ok())
+            .filter(|exposure| !exposure.is_zero())
+            .or_else(|| pattern.exposure(path))
+            .ok_or_else(|| Error::InputError {
+                parameter_name: "exposure".to_string(),
+                message: format!(
+                    "No exposure time in EXIF data, and the file name doesn't match the pattern \"{}\"",
+                    pattern.as_str()
+                ),
+            })?;
+        let gain = exif
+            .as_ref()
+            .and_then(|exif| get_gains(exif).ok())
+            .unwrap_or(1.);
 
-        Self::with_image(&image, exposure, gain)
+        Ok(Self {
+            aperture: exif.as_ref().and_then(|exif| get_apertures(exif).ok()),
+            ..Self::with_image(&image, exposure, gain)?
+        })
     }
 
+    /// Create new [`HDRInput`] from the raw bytes of a file and the image decoded from them,
+    /// reading the values that are not overridden from the EXIF data in the bytes. See
+    /// [`HDRInput::with_overrides`].
+    pub(crate) fn with_decoded_file(
+        data: &[u8],
+        image: &DynamicImage,
+        exposure: Option<Duration>,
+        gain: Option<f32>,
+    ) -> Result<Self, Error> {
+        #[cfg(feature = "tracing")]
+        let overridden = (exposure.is_some(), gain.is_some());
+
+        let (exposure, gain, aperture) = match (exposure, gain) {
+            (Some(exposure), Some(gain)) => (exposure, gain, None),
+            (exposure, gain) => {
+                let exif = get_exif_data(data)?;
+
+                (
+                    exposure.map_or_else(|| exposure_from_exif(&exif), Ok)?,
+                    gain.map_or_else(|| get_gains(&exif), Ok)?,
+                    get_apertures(&exif).ok(),
+                )
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            exposure = exposure.as_secs_f32(),
+            gain,
+            exposure_overridden = overridden.0,
+            gain_overridden = overridden.1,
+            width = image.width(),
+            height = image.height(),
+            "Read image"
+        );
+
+        Ok(Self {
+            aperture,
+            ..Self::with_image(image, exposure, gain)?
+        })
+    }
+
+    /// Create new [`HDRInput`] from an already decoded image.
+    ///
+    /// Pixel values are normalised to `[0, 1]` at the full precision of the image, i.e. divided
+    /// by `255` for 8-bit images and by `65535` for 16-bit images like 16-bit PNG and TIFF files
+    /// or decoded RAW files, so no precision is lost to an 8-bit intermediate. Floating point
+    /// images are taken as is.
+    ///
+    /// If the image has an alpha channel and is not fully opaque, its color channels are
+    /// premultiplied by alpha, i.e. the image is merged as if it was composited over black, and
+    /// the alpha channel is kept for [`crate::config::MergeConfig::alpha`]. Fully opaque images
+    /// are treated as plain RGB or grayscale images.
     ///
     /// # Arguments
     ///
-    /// * `image`:
-    /// * `exposure`:
-    /// * `gain`:
+    /// * `image`: The decoded image
+    /// * `exposure`: Exposure of the image
+    /// * `gain`: Gain of the image
     ///
     /// returns: `Result<HDRInput, Error>`
     ///
@@ -79,27 +219,75 @@ impl HDRInput {
     /// - invalid gain
     /// - invalid exposure duration
     pub fn with_image(image: &DynamicImage, exposure: Duration, gain: f32) -> Result<Self, Error> {
-        if gain.is_infinite() || gain.is_nan() || gain <= 0. {
-            return Err(Error::InputError {
-                parameter_name: "gain".to_string(),
-                message: "Gain must be a valid positive and non-zero floating point number"
-                    .to_string(),
-            });
-        }
+        validate_exposure_and_gain(exposure, gain)?;
+
+        let mut buffer = image.to_nd_array_buffer();
+        let alpha = if image.color().has_alpha() {
+            premultiply_alpha(image, &mut buffer)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            buffer,
+            exposure: exposure.as_secs_f32(),
+            gain,
+            color_type: Some(image.color()),
+            alpha,
+            weight_map: None,
+            aperture: None,
+        })
+    }
+
+    /// Create new [`HDRInput`] from normalised pixel values with any number of channels, e.g. the
+    /// red, green, blue and near infrared bands of a multispectral camera. Values should be
+    /// linear, with `1.0` being the largest value the sensor can record. Every channel is merged
+    /// independently, see [`crate::config::ColorCoefficients`] for per-channel coefficients.
+    ///
+    /// Tone mapping and saving to image formats only use the first three channels of the merged
+    /// result, or the first channel if it has fewer than three.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: Pixel values as a `(height, width, channels)` nd-array
+    /// * `exposure`: Exposure of the image
+    /// * `gain`: Gain of the image
+    ///
+    /// returns: `Result<HDRInput, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If the buffer has no pixels or no channels
+    /// - invalid gain
+    /// - invalid exposure duration
+    pub fn with_buffer(buffer: Array3<f32>, exposure: Duration, gain: f32) -> Result<Self, Error> {
+        validate_exposure_and_gain(exposure, gain)?;
+
+        if buffer.is_empty() {
+            let (height, width, channels) = buffer.dim();
 
-        if exposure.is_zero() {
             return Err(Error::InputError {
-                parameter_name: "exposure".to_string(),
-                message: "Exposure must be a positive non-zero duration".to_string(),
+                parameter_name: "buffer".to_string(),
+                message: format!(
+                    "Buffer must have pixels and channels, got {width}x{height} with {channels} channel(s)"
+                ),
             });
         }
 
-        let buffer = image.to_nd_array_buffer();
+        let buffer = if buffer.is_standard_layout() {
+            buffer
+        } else {
+            buffer.as_standard_layout().into_owned()
+        };
 
         Ok(Self {
             buffer,
             exposure: exposure.as_secs_f32(),
             gain,
+            color_type: None,
+            alpha: None,
+            weight_map: None,
+            aperture: None,
         })
     }
 
@@ -115,38 +303,636 @@ impl HDRInput {
         self.gain
     }
 
+    /// Get the aperture of the input item as an f-number, e.g. `8.0` for f/8, or `None` if it is
+    /// unknown. The aperture is read from EXIF data along with the exposure or gain, and is only
+    /// used with [`ExposureModel::TimeGainAndAperture`].
+    #[must_use]
+    pub fn get_aperture(&self) -> Option<f32> {
+        self.aperture
+    }
+
+    /// Set the aperture of the input item as an f-number, e.g. `8.0` for f/8, replacing the one
+    /// read from EXIF data if any. See [`ExposureModel::TimeGainAndAperture`].
+    ///
+    /// # Errors
+    ///
+    /// - If the aperture is not a finite, positive and non-zero number
+    pub fn with_aperture(mut self, aperture: f32) -> Result<Self, Error> {
+        if !aperture.is_finite() || aperture <= 0. {
+            return Err(Error::InputError {
+                parameter_name: "aperture".to_string(),
+                message: format!(
+                    "Aperture must be a valid positive and non-zero floating point number, got {aperture}"
+                ),
+            });
+        }
+
+        self.aperture = Some(aperture);
+
+        Ok(self)
+    }
+
+    /// Effective exposure the pixels are divided by to convert them to radiance, before color
+    /// coefficients, i.e. `exposure * gain` by default. See [`MergeConfig::exposure_model`].
+    ///
+    /// # Errors
+    /// If the exposure model includes the aperture, but the aperture is unknown.
+    pub(crate) fn scaling_factor(&self, config: &MergeConfig) -> Result<f32, Error> {
+        Ok(self.light_exposure(config)? * self.gain)
+    }
+
+    /// Exposure of the sensor to light, i.e. the exposure time, divided by the square of the
+    /// aperture if [`MergeConfig::exposure_model`] includes it.
+    ///
+    /// # Errors
+    /// If the exposure model includes the aperture, but the aperture is unknown.
+    pub(crate) fn light_exposure(&self, config: &MergeConfig) -> Result<f32, Error> {
+        match (config.exposure_model, self.aperture) {
+            (ExposureModel::TimeAndGain, _) => Ok(self.exposure),
+            (ExposureModel::TimeGainAndAperture, Some(aperture)) => {
+                Ok(self.exposure / (aperture * aperture))
+            }
+            (ExposureModel::TimeGainAndAperture, None) => Err(Error::InputError {
+                parameter_name: "aperture".to_string(),
+                message: "The exposure model includes the aperture, but the aperture of the image is unknown"
+                    .to_string(),
+            }),
+        }
+    }
+
+    /// Get color type of the image the input item was decoded from, e.g. [`ColorType::Rgb8`] for
+    /// a JPEG, or `None` for inputs created with [`HDRInput::with_buffer`]. Frames of different
+    /// color types merge as long as they have the same channel layout, see
+    /// [`HDRInputList::validate_channel_layouts`].
+    #[must_use]
+    pub fn get_color_type(&self) -> Option<ColorType> {
+        self.color_type
+    }
+
     /// Get underlying image data for the input item
     #[must_use]
     pub fn get_buffer(&self) -> &Array3<f32> {
         &self.buffer
     }
 
+    /// Get the alpha channel of the input item as a `(height, width, 1)` nd-array, or `None` if
+    /// the image has no alpha channel or is fully opaque
+    #[must_use]
+    pub fn get_alpha(&self) -> Option<&Array3<f32>> {
+        self.alpha.as_ref()
+    }
+
+    pub(crate) fn get_alpha_mut(&mut self) -> Option<&mut Array3<f32>> {
+        self.alpha.as_mut()
+    }
+
+    /// Set a weight map restricting the contribution of the input item to parts of the frame,
+    /// e.g. the region a frame of a stitched panorama covers. The weight of each pixel multiplies
+    /// the weights the merge gives the samples of that pixel, which follow the noise model, the
+    /// weight function, saturation and alpha, so a weight of `0.0` masks the pixel out, and a
+    /// weight of `0.5` halves the contribution of the pixel relative to the rest of the stack.
+    /// Pixels masked out of every frame of a stack are black. The weight map is resized, cropped
+    /// and aligned along with the image data. This only applies to
+    /// [`crate::hdr_merge_images_with_config`] and the merges built on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: Weight of each pixel, stored row-major, with `width * height` values
+    ///
+    /// returns: `Result<HDRInput, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If the number of weights doesn't match the dimensions of the image
+    /// - If any weight is negative or not finite
+    pub fn with_weight_map(mut self, weights: &[f32]) -> Result<Self, Error> {
+        let (height, width, _) = self.buffer.dim();
+
+        let weight_map =
+            Array3::from_shape_vec((height, width, 1), weights.to_vec()).map_err(|_| {
+                Error::InputError {
+                    parameter_name: "weight_map".to_string(),
+                    message: format!(
+                        "Expected {} weights for a {width}x{height} image, got {}",
+                        width * height,
+                        weights.len()
+                    ),
+                }
+            })?;
+
+        if let Some(weight) = weights
+            .iter()
+            .find(|weight| !weight.is_finite() || **weight < 0.)
+        {
+            return Err(Error::InputError {
+                parameter_name: "weight_map".to_string(),
+                message: format!(
+                    "Weights must be valid non-negative floating point numbers, got {weight}"
+                ),
+            });
+        }
+
+        self.weight_map = Some(weight_map);
+
+        Ok(self)
+    }
+
+    /// Get the weight map of the input item as a `(height, width, 1)` nd-array, or `None` if
+    /// none was set, see [`HDRInput::with_weight_map`]
+    #[must_use]
+    pub fn get_weight_map(&self) -> Option<&Array3<f32>> {
+        self.weight_map.as_ref()
+    }
+
+    pub(crate) fn get_weight_map_mut(&mut self) -> Option<&mut Array3<f32>> {
+        self.weight_map.as_mut()
+    }
+
+    /// Get the underlying image data for mutation along with the alpha channel and weight map.
+    pub(crate) fn get_buffer_mut_and_masks(
+        &mut self,
+    ) -> (&mut Array3<f32>, Option<&Array3<f32>>, Option<&Array3<f32>>) {
+        (
+            &mut self.buffer,
+            self.alpha.as_ref(),
+            self.weight_map.as_ref(),
+        )
+    }
+
     /// Get underlying image data for the input item
     #[must_use]
     pub fn get_buffer_mut(&mut self) -> &mut Array3<f32> {
         &mut self.buffer
     }
+
+    /// Downscale the image data with a triangle filter so that its longest side is at most
+    /// `max_dimension` pixels, keeping its aspect ratio. Inputs that are already small enough are
+    /// left untouched. The exposure and gain apply to the whole frame, so they remain valid.
+    ///
+    /// # Errors
+    ///
+    /// - If `max_dimension` is zero
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn downscale(&mut self, max_dimension: u32) -> Result<(), Error> {
+        validate_max_dimension(max_dimension)?;
+
+        let (height, width, _) = self.buffer.dim();
+        let longest = width.max(height);
+
+        if longest <= max_dimension as usize {
+            return Ok(());
+        }
+
+        let scale = max_dimension as f32 / longest as f32;
+        let resized = |length: usize| ((length as f32 * scale).round() as u32).max(1);
+
+        self.resize(resized(width), resized(height), FilterType::Triangle)
+    }
+
+    /// Scale the exposure by `2^ev`, so that an offset of `+1` EV doubles it and `-1` EV halves
+    /// it.
+    ///
+    /// # Errors
+    ///
+    /// - If `ev` is not a finite number, or the resulting exposure is not a finite positive number.
+    pub fn compensate_exposure(&mut self, ev: f32) -> Result<(), Error> {
+        let exposure = self.exposure * ev.exp2();
+
+        if !ev.is_finite() || !exposure.is_finite() || exposure <= 0. {
+            return Err(Error::InputError {
+                parameter_name: "ev_offsets".to_string(),
+                message: format!(
+                    "EV offset of {ev} turns an exposure of {} into an invalid exposure",
+                    self.exposure
+                ),
+            });
+        }
+
+        self.exposure = exposure;
+
+        Ok(())
+    }
+
+    /// Resize the image data, and its alpha channel and weight map if any, to the given dimensions
+    /// with the given filter. Inputs that already have these dimensions are left untouched.
+    ///
+    /// # Errors
+    ///
+    /// This currently never fails.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn resize(&mut self, width: u32, height: u32, filter: FilterType) -> Result<(), Error> {
+        let (input_height, input_width, _) = self.buffer.dim();
+
+        if (input_width as u32, input_height as u32) == (width, height) {
+            return Ok(());
+        }
+
+        self.buffer = resize_buffer(&self.buffer, width, height, filter);
+        if let Some(alpha) = &mut self.alpha {
+            *alpha = resize_buffer(alpha, width, height, filter);
+        }
+        if let Some(weight_map) = &mut self.weight_map {
+            *weight_map = resize_buffer(weight_map, width, height, filter).mapv(|w| w.max(0.));
+        }
+
+        Ok(())
+    }
+
+    /// Crop the image data to the region of the given size whose top left corner is at `(x, y)`.
+    ///
+    /// # Errors
+    ///
+    /// - If the region is empty or doesn't lie within the image.
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), Error> {
+        let (input_height, input_width, _) = self.buffer.dim();
+        let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+
+        if width == 0 || height == 0 || x + width > input_width || y + height > input_height {
+            return Err(Error::InputError {
+                parameter_name: "crop".to_string(),
+                message: format!(
+                    "Crop region of {width}x{height} at ({x}, {y}) must be non-empty and lie within the {input_width}x{input_height} image"
+                ),
+            });
+        }
+
+        self.buffer = self
+            .buffer
+            .slice(s![y..y + height, x..x + width, ..])
+            .to_owned();
+        if let Some(alpha) = &mut self.alpha {
+            *alpha = alpha.slice(s![y..y + height, x..x + width, ..]).to_owned();
+        }
+        if let Some(weight_map) = &mut self.weight_map {
+            *weight_map = weight_map
+                .slice(s![y..y + height, x..x + width, ..])
+                .to_owned();
+        }
+
+        Ok(())
+    }
+
+    /// Replace hot and dead pixels with the median of their 3x3 neighbourhood. Each channel is
+    /// handled on its own, and a value is replaced when it differs from the median of its
+    /// neighbours, excluding itself, by more than `threshold`. Long exposures accumulate hot
+    /// pixels that stand out as bright specks, which would otherwise survive the merge.
+    ///
+    /// returns: The number of values that were replaced.
+    pub fn correct_hot_pixels(&mut self, threshold: f32) -> usize {
+        let (height, width, _) = self.buffer.dim();
+        let original = self.buffer.clone();
+
+        let corrected = Zip::indexed(&mut self.buffer).par_map_collect(|(y, x, c), value| {
+            let mut neighbours = [0_f32; 8];
+            let mut count = 0;
+
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    if (ny, nx) != (y, x) {
+                        neighbours[count] = original[[ny, nx, c]];
+                        count += 1;
+                    }
+                }
+            }
+
+            if count == 0 {
+                return false;
+            }
+
+            let neighbours = &mut neighbours[..count];
+            neighbours.sort_unstable_by(f32::total_cmp);
+            let median = if count % 2 == 0 {
+                f32::midpoint(neighbours[count / 2 - 1], neighbours[count / 2])
+            } else {
+                neighbours[count / 2]
+            };
+
+            if (*value - median).abs() > threshold {
+                *value = median;
+                true
+            } else {
+                false
+            }
+        });
+
+        corrected.iter().filter(|corrected| **corrected).count()
+    }
 }
 
 impl TryFrom<&Path> for HDRInput {
     type Error = Error;
 
     fn try_from(value: &Path) -> Result<Self, Self::Error> {
-        let data = std::fs::read(value)?;
-        let format = image::ImageFormat::from_path(value).ok();
-        let image = read_image(&data, format)?;
-        let exif = get_exif_data(&data)?;
-        let exposure = get_exposures(&exif)?;
-        let gain = get_gains(&exif)?;
-
-        Self::with_image(&image, Duration::from_secs_f32(exposure), gain)
+        Self::with_overrides(value, None, None)
     }
 }
 
 /// A wrapper for list of [`HDRInput`] for ease of trait implementations.
+#[derive(Clone)]
 pub struct HDRInputList(Vec<HDRInput>);
 
 impl HDRInputList {
+    /// Create new [`HDRInputList`] from already decoded images, along with the exposure and gain
+    /// of each image. No file is read and no EXIF metadata is parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `images`: Decoded images of the stack
+    /// * `exposures`: Exposure of each image, in the same order as `images`
+    /// * `gains`: Gain of each image, in the same order as `images`
+    ///
+    /// returns: `Result<HDRInputList, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If `images`, `exposures` and `gains` are not of the same length
+    /// - If the images don't all have the same channel layout, listing every image that
+    ///   differs from the first one
+    /// - invalid gain
+    /// - invalid exposure duration
+    pub fn with_images(
+        images: &[DynamicImage],
+        exposures: &[Duration],
+        gains: &[f32],
+    ) -> Result<Self, Error> {
+        if exposures.len() != images.len() || gains.len() != images.len() {
+            return Err(Error::InputError {
+                parameter_name: "exposures".to_string(),
+                message: format!(
+                    "Expected one exposure and gain per image, got {} images, {} exposures and {} gains",
+                    images.len(),
+                    exposures.len(),
+                    gains.len()
+                ),
+            });
+        }
+
+        let inputs = images
+            .par_iter()
+            .zip(exposures)
+            .zip(gains)
+            .enumerate()
+            .map(|(index, ((image, exposure), gain))| {
+                HDRInput::with_image(image, *exposure, *gain)
+                    .map_err(|error| with_index(index, error))
+            })
+            .collect::<Result<Vec<HDRInput>, Error>>()?;
+
+        validate_channel_layouts(&inputs, |index| (index, None))?;
+
+        Ok(HDRInputList(inputs))
+    }
+
+    /// Create new [`HDRInputList`] from already decoded images, along with the EXIF metadata of
+    /// each image, e.g. extracted with [`crate::exif::get_exif_data`] while the files were read.
+    ///
+    /// # Arguments
+    ///
+    /// * `images`: Decoded images of the stack
+    /// * `exif`: EXIF metadata of each image, in the same order as `images`
+    ///
+    /// returns: `Result<HDRInputList, Error>`
+    ///
+    /// # Errors
+    ///
+    /// - If `images` and `exif` are not of the same length
+    /// - If the EXIF metadata of any image is missing its exposure or gain, listing every such
+    ///   image in [`Error::ReadErrors`]
+    /// - If the images don't all have the same channel layout, listing every image that
+    ///   differs from the first one
+    /// - invalid gain
+    /// - invalid exposure duration
+    pub fn with_exif(images: &[DynamicImage], exif: &[exif::Exif]) -> Result<Self, Error> {
+        if images.len() != exif.len() {
+            return Err(Error::InputError {
+                parameter_name: "exif".to_string(),
+                message: format!(
+                    "Expected EXIF metadata for each image, got {} images and {} EXIF entries",
+                    images.len(),
+                    exif.len()
+                ),
+            });
+        }
+
+        let mut exposures = Vec::with_capacity(exif.len());
+        let mut gains = Vec::with_capacity(exif.len());
+        let apertures = exif
+            .iter()
+            .map(|exif| get_apertures(exif).ok())
+            .collect::<Vec<Option<f32>>>();
+        let mut failures = Vec::new();
+
+        for (index, exif) in exif.iter().enumerate() {
+            match exposure_from_exif(exif).and_then(|exposure| Ok((exposure, get_gains(exif)?))) {
+                Ok((exposure, gain)) => {
+                    exposures.push(exposure);
+                    gains.push(gain);
+                }
+                Err(error) => failures.push(ReadFailure {
+                    index,
+                    path: None,
+                    error,
+                }),
+            }
+        }
+
+        if !failures.is_empty() {
+            return Err(Error::ReadErrors(failures));
+        }
+
+        let mut inputs = Self::with_images(images, &exposures, &gains)?;
+        for (input, aperture) in inputs.0.iter_mut().zip(apertures) {
+            input.aperture = aperture;
+        }
+
+        Ok(inputs)
+    }
+
+    /// Create new [`HDRInputList`] from a set of file paths, optionally overriding the exposures
+    /// and/or gains read from EXIF data. See [`HDRInput::with_overrides`].
+    ///
+    /// # Arguments
+    ///
+    /// * `paths`: Paths to files
+    /// * `exposures`: Exposure of each file, in the same order as `paths`
+    /// * `gains`: Gain of each file, in the same order as `paths`
+    ///
+    /// returns: `Result<HDRInputList, Error>`
+    ///
+    /// # Errors
+    ///
+    /// Every file is read even if some fail, and the failures are reported together in
+    /// [`Error::ReadErrors`].
+    ///
+    /// - If overrides are provided but their length doesn't match the number of paths
+    /// - If any of the images cannot be opened
+    /// - If a value is not overridden and image doesn't contain EXIF metadata for it.
+    /// - If the images don't all have the same channel layout, listing every image that
+    ///   differs from the first one
+    /// - invalid gain
+    /// - invalid exposure duration
+    pub fn with_overrides<P: AsRef<Path> + Sync>(
+        paths: &[P],
+        exposures: Option<&[Duration]>,
+        gains: Option<&[f32]>,
+    ) -> Result<Self, Error> {
+        if let Some(exposures) = exposures {
+            validate_override_length("exposures", exposures.len(), paths.len())?;
+        }
+
+        if let Some(gains) = gains {
+            validate_override_length("gains", gains.len(), paths.len())?;
+        }
+
+        let inputs = read_each(
+            paths,
+            |path| Some(path.as_ref()),
+            |path, index| {
+                HDRInput::with_overrides(
+                    path,
+                    exposures.map(|exposures| exposures[index]),
+                    gains.map(|gains| gains[index]),
+                )
+            },
+        )?;
+
+        validate_channel_layouts(&inputs, |index| {
+            (index, Some(paths[index].as_ref().to_path_buf()))
+        })?;
+
+        Ok(HDRInputList(inputs))
+    }
+
+    /// Create new [`HDRInputList`] from a set of file paths, using the exposure and gain of the
+    /// given [`ExifData`] of each file, e.g. read with [`crate::exif::read_exif_data`] and then
+    /// corrected. Values that are `None` are read from the file's EXIF data, and the EXIF data of
+    /// files whose exposure and gain are both set is not parsed at all. The aperture replaces the
+    /// one read from EXIF data when it is set, and the ISO is not used.
+    ///
+    /// # Errors
+    /// - If `exif_data` doesn't have one entry per path
+    /// - If any of the images cannot be read, or doesn't contain EXIF metadata for a value that
+    ///   is `None`, listing every failure
+    /// - If the images don't all have the same channel layout, listing every image that
+    ///   differs from the first one
+    /// - invalid gain
+    /// - invalid exposure duration
+    pub fn with_exif_data<P: AsRef<Path> + Sync>(
+        paths: &[P],
+        exif_data: &[ExifData],
+    ) -> Result<Self, Error> {
+        validate_override_length("exif_data", exif_data.len(), paths.len())?;
+
+        let inputs = read_each(
+            paths,
+            |path| Some(path.as_ref()),
+            |path, index| {
+                let input = HDRInput::with_overrides(
+                    path,
+                    exif_data[index].exposure,
+                    exif_data[index].gain,
+                )?;
+
+                match exif_data[index].aperture {
+                    Some(aperture) => input.with_aperture(aperture),
+                    None => Ok(input),
+                }
+            },
+        )?;
+
+        validate_channel_layouts(&inputs, |index| {
+            (index, Some(paths[index].as_ref().to_path_buf()))
+        })?;
+
+        Ok(HDRInputList(inputs))
+    }
+
+    /// Create new [`HDRInputList`] from a set of file paths, reading the exposure time of files
+    /// without it in their EXIF data from their file name with the given pattern. See
+    /// [`HDRInput::with_exposure_pattern`].
+    ///
+    /// # Errors
+    /// - If any of the images cannot be read, or has neither an exposure time in its EXIF data
+    ///   nor a file name matching the pattern, listing every failure
+    /// - If the images don't all have the same channel layout, listing every image that
+    ///   differs from the first one
+    /// - invalid gain
+    pub fn with_exposure_pattern<P: AsRef<Path> + Sync>(
+        paths: &[P],
+        pattern: &ExposurePattern,
+    ) -> Result<Self, Error> {
+        let inputs = read_each(
+            paths,
+            |path| Some(path.as_ref()),
+            |path, _| HDRInput::with_exposure_pattern(path, pattern),
+        )?;
+
+        validate_channel_layouts(&inputs, |index| {
+            (index, Some(paths[index].as_ref().to_path_buf()))
+        })?;
+
+        Ok(HDRInputList(inputs))
+    }
+
+    /// Same as [`HDRInputList::with_overrides`], but skips the files that cannot be read instead
+    /// of failing, as long as at least two files remain.
+    ///
+    /// returns: The list of inputs that could be read, and the failures of the skipped files.
+    ///
+    /// # Errors
+    /// - If overrides are provided but their length doesn't match the number of paths
+    /// - If fewer than two of the files can be read, listing every failure
+    /// - If the images that could be read don't all have the same channel layout, listing
+    ///   every image that differs from the first one
+    pub fn with_overrides_lenient<P: AsRef<Path> + Sync>(
+        paths: &[P],
+        exposures: Option<&[Duration]>,
+        gains: Option<&[f32]>,
+    ) -> Result<(Self, Vec<ReadFailure>), Error> {
+        if let Some(exposures) = exposures {
+            validate_override_length("exposures", exposures.len(), paths.len())?;
+        }
+
+        if let Some(gains) = gains {
+            validate_override_length("gains", gains.len(), paths.len())?;
+        }
+
+        let (inputs, failures) = read_each_lenient(
+            paths,
+            |path| Some(path.as_ref()),
+            |path, index| {
+                HDRInput::with_overrides(
+                    path,
+                    exposures.map(|exposures| exposures[index]),
+                    gains.map(|gains| gains[index]),
+                )
+            },
+        );
+
+        if inputs.len() < 2 && !failures.is_empty() {
+            return Err(Error::ReadErrors(failures));
+        }
+
+        let read_indices = (0..paths.len())
+            .filter(|index| failures.iter().all(|failure| failure.index != *index))
+            .collect::<Vec<usize>>();
+        validate_channel_layouts(&inputs, |index| {
+            let index = read_indices[index];
+            (index, Some(paths[index].as_ref().to_path_buf()))
+        })?;
+
+        #[cfg(feature = "tracing")]
+        for failure in &failures {
+            tracing::warn!(%failure, "Skipping unreadable image");
+        }
+
+        Ok((HDRInputList(inputs), failures))
+    }
+
     /// Get list of [`HDRInput`] as a vec.
     #[must_use]
     pub fn into_vec(self) -> Vec<HDRInput> {
@@ -165,6 +951,166 @@ impl HDRInputList {
         &mut self.0
     }
 
+    /// Resize every input to the smallest width and the smallest height found in the list, with
+    /// the given filter. Inputs that already have these dimensions are left untouched. Note that
+    /// frames cropped along a single axis are stretched along the other one, so the aspect ratio
+    /// of the stack may change.
+    ///
+    /// # Errors
+    ///
+    /// This currently never fails.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn resize_to_smallest(&mut self, filter: FilterType) -> Result<(), Error> {
+        let (width, height) =
+            self.0
+                .iter()
+                .fold((usize::MAX, usize::MAX), |(width, height), input| {
+                    let (input_height, input_width, _) = input.get_buffer().dim();
+
+                    (width.min(input_width), height.min(input_height))
+                });
+
+        self.0
+            .par_iter_mut()
+            .try_for_each(|input| input.resize(width as u32, height as u32, filter))
+    }
+
+    /// Scale the exposure of each input by `2^ev` of the EV offset at the same index. See
+    /// [`HDRInput::compensate_exposure`].
+    ///
+    /// # Errors
+    ///
+    /// - If the number of offsets doesn't match the number of inputs
+    /// - If an offset leads to an invalid exposure
+    pub fn compensate_exposures(&mut self, ev_offsets: &[f32]) -> Result<(), Error> {
+        validate_override_length("ev_offsets", ev_offsets.len(), self.len())?;
+
+        for (index, (input, ev)) in self.0.iter_mut().zip(ev_offsets).enumerate() {
+            input
+                .compensate_exposure(*ev)
+                .map_err(|error| with_index(index, error))?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy of the list with every input downscaled so that its longest side is at most
+    /// `max_dimension` pixels, see [`HDRInput::downscale`]. Merging the copy gives a fast low
+    /// resolution preview, with radiance values preserved up to the averaging done by
+    /// resampling, while the list itself can still be merged in full afterwards without reading
+    /// the files again.
+    ///
+    /// # Errors
+    ///
+    /// - If `max_dimension` is zero
+    pub fn downscaled(&self, max_dimension: u32) -> Result<Self, Error> {
+        validate_max_dimension(max_dimension)?;
+
+        Ok(HDRInputList(
+            self.0
+                .par_iter()
+                .map(|input| {
+                    let mut input = input.clone();
+                    input.downscale(max_dimension)?;
+
+                    Ok(input)
+                })
+                .collect::<Result<Vec<HDRInput>, Error>>()?,
+        ))
+    }
+
+    /// Sort the list by exposure, shortest exposure first. Inputs with equal exposures keep their
+    /// relative order.
+    pub fn sort_by_exposure(&mut self) {
+        self.0
+            .sort_by(|a, b| a.get_exposure().total_cmp(&b.get_exposure()));
+    }
+
+    /// Index of the reference frame of the stack, which is the frame with the median exposure.
+    /// For an even number of frames, this is the longer of the two middle exposures, and frames
+    /// with equal exposures are ordered by their position in the list. Operations that need a
+    /// single frame to compare the others against, like alignment and deghosting, use this frame
+    /// as the reference.
+    #[must_use]
+    pub fn reference_index(&self) -> usize {
+        reference_index(&self.0)
+    }
+
+    /// Make sure every input has the channel layout of the first one, i.e. that the stack doesn't
+    /// mix grayscale and color images. The constructors that read or decode images already do
+    /// this, so this is only needed for lists built from separately created inputs.
+    ///
+    /// # Errors
+    /// [`Error::ReadErrors`] listing every input whose layout differs from the first one, by
+    /// index only.
+    pub fn validate_channel_layouts(&self) -> Result<(), Error> {
+        validate_channel_layouts(&self.0, |index| (index, None))
+    }
+
+    /// Shortest and longest effective exposure of the stack, i.e. `exposure * gain`, or `None`
+    /// for an empty list. Frames that were not bracketed have a range that is close to a single
+    /// value, see [`crate::config::MergeConfig::min_exposure_ratio`].
+    #[must_use]
+    pub fn exposure_range(&self) -> Option<(f32, f32)> {
+        self.0
+            .iter()
+            .map(|input| input.get_exposure() * input.get_gain())
+            .fold(None, |range, exposure| match range {
+                Some((shortest, longest)) => Some((exposure.min(shortest), exposure.max(longest))),
+                None => Some((exposure, exposure)),
+            })
+    }
+
+    /// Smallest and largest value the frames are divided by to convert them to radiance, see
+    /// [`HDRInput::scaling_factor`], or `None` for an empty list. This is the same as
+    /// [`HDRInputList::exposure_range`], unless [`MergeConfig::exposure_model`] includes the
+    /// aperture.
+    ///
+    /// # Errors
+    /// If the exposure model includes the aperture, but the aperture of some frames is unknown,
+    /// listing every such frame.
+    pub(crate) fn scaling_factor_range(
+        &self,
+        config: &MergeConfig,
+    ) -> Result<Option<(f32, f32)>, Error> {
+        let mut range = None;
+        let mut failures = Vec::new();
+
+        for (index, input) in self.0.iter().enumerate() {
+            match input.scaling_factor(config) {
+                Ok(factor) => {
+                    range = Some(range.map_or(
+                        (factor, factor),
+                        |(smallest, largest): (f32, f32)| {
+                            (factor.min(smallest), factor.max(largest))
+                        },
+                    ));
+                }
+                Err(error) => failures.push(ReadFailure {
+                    index,
+                    path: None,
+                    error,
+                }),
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(range)
+        } else {
+            Err(Error::ReadErrors(failures))
+        }
+    }
+
+    /// Remove the images at the given indices, keeping the order of the others.
+    pub(crate) fn remove_indices(&mut self, indices: &[usize]) {
+        let mut index = 0;
+        self.0.retain(|_| {
+            let keep = !indices.contains(&index);
+            index += 1;
+            keep
+        });
+    }
+
     /// Returns the number of elements in the list
     #[must_use]
     pub fn len(&self) -> usize {
@@ -188,13 +1134,506 @@ impl<P: AsRef<Path> + Sync> TryFrom<&[P]> for HDRInputList {
     type Error = Error;
 
     fn try_from(value: &[P]) -> Result<Self, Self::Error> {
-        Ok(HDRInputList(
-            value
-                .par_iter()
-                .map(|value| -> Result<HDRInput, Self::Error> {
-                    HDRInput::try_from(value.as_ref())
-                })
-                .collect::<Result<Vec<HDRInput>, Self::Error>>()?,
-        ))
+        let inputs = read_each(
+            value,
+            |path| Some(path.as_ref()),
+            |path, _| HDRInput::try_from(path.as_ref()),
+        )?;
+
+        validate_channel_layouts(&inputs, |index| {
+            (index, Some(value[index].as_ref().to_path_buf()))
+        })?;
+
+        Ok(HDRInputList(inputs))
+    }
+}
+
+pub(crate) fn validate_max_dimension(max_dimension: u32) -> Result<(), Error> {
+    if max_dimension == 0 {
+        return Err(Error::InputError {
+            parameter_name: "max_dimension".to_string(),
+            message: "Maximum dimension must be greater than zero".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+pub(crate) fn validate_override_length(
+    parameter_name: &str,
+    length: usize,
+    expected: usize,
+) -> Result<(), Error> {
+    if length == expected {
+        return Ok(());
+    }
+
+    Err(Error::InputError {
+        parameter_name: parameter_name.to_string(),
+        message: format!("Expected {expected} values, one per path, got {length}"),
+    })
+}
+
+/// Read the exposure from EXIF data, rejecting values that are not a valid duration, e.g. a
+/// rational with a zero denominator.
+pub(crate) fn exposure_from_exif(exif: &exif::Exif) -> Result<Duration, Error> {
+    let exposure = get_exposures(exif)?;
+
+    Duration::try_from_secs_f32(exposure).map_err(|_| Error::InputError {
+        parameter_name: "exposure".to_string(),
+        message: format!("EXIF exposure time of {exposure} is not a valid duration"),
+    })
+}
+
+/// Read every item of a list in parallel, collecting the failures instead of stopping at the
+/// first one. `path` gives the path of an item, if it is read from a file.
+pub(crate) fn read_each_lenient<I: Sync, T: Send>(
+    items: &[I],
+    path: impl Fn(&I) -> Option<&Path> + Sync,
+    read: impl Fn(&I, usize) -> Result<T, Error> + Sync,
+) -> (Vec<T>, Vec<ReadFailure>) {
+    let results = items
+        .par_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            read(item, index).map_err(|error| ReadFailure {
+                index,
+                path: path(item).map(Path::to_path_buf),
+                error,
+            })
+        })
+        .collect::<Vec<Result<T, ReadFailure>>>();
+
+    let mut values = Vec::with_capacity(results.len());
+    let mut failures = Vec::new();
+
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(failure) => failures.push(failure),
+        }
+    }
+
+    (values, failures)
+}
+
+/// Same as [`read_each_lenient`], but fails with [`Error::ReadErrors`] listing every failure if
+/// any item cannot be read.
+pub(crate) fn read_each<I: Sync, T: Send>(
+    items: &[I],
+    path: impl Fn(&I) -> Option<&Path> + Sync,
+    read: impl Fn(&I, usize) -> Result<T, Error> + Sync,
+) -> Result<Vec<T>, Error> {
+    let (values, failures) = read_each_lenient(items, path, read);
+
+    if failures.is_empty() {
+        Ok(values)
+    } else {
+        Err(Error::ReadErrors(failures))
+    }
+}
+
+/// Fail with [`Error::ReadErrors`] naming every input whose channel layout differs from the one of
+/// the first input, e.g. a grayscale image in a stack of color images. The layout is the one
+/// after alpha handling, so 8 and 16-bit images, which are both normalised to `[0, 1]`, and RGBA
+/// images, whose alpha is kept apart from the color channels, merge with RGB images.
+/// `origin` gives the index and path of each input in the list of items it was read from.
+pub(crate) fn validate_channel_layouts(
+    inputs: &[HDRInput],
+    origin: impl Fn(usize) -> (usize, Option<PathBuf>),
+) -> Result<(), Error> {
+    let Some(reference) = inputs.first() else {
+        return Ok(());
+    };
+
+    let failures = inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, input)| {
+            channel_layout_mismatch(reference.get_buffer().dim().2, input.get_buffer().dim().2).map(
+                |error| {
+                    let (index, path) = origin(index);
+
+                    ReadFailure { index, path, error }
+                },
+            )
+        })
+        .collect::<Vec<ReadFailure>>();
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ReadErrors(failures))
+    }
+}
+
+/// Error describing a channel count that differs from the one of the reference frame, if it does.
+pub(crate) fn channel_layout_mismatch(reference: usize, channels: usize) -> Option<Error> {
+    let describe = |channels: usize| match channels {
+        1 => "a grayscale image".to_string(),
+        3 => "a color image".to_string(),
+        channels => format!("an image with {channels} channels"),
+    };
+
+    (channels != reference).then(|| Error::InputError {
+        parameter_name: "channels".to_string(),
+        message: format!(
+            "Image is {} while the first image is {}",
+            describe(channels),
+            describe(reference)
+        ),
+    })
+}
+
+/// Make sure an exposure and gain can be divided by to convert pixel values to radiance.
+fn validate_exposure_and_gain(exposure: Duration, gain: f32) -> Result<(), Error> {
+    if gain.is_infinite() || gain.is_nan() || gain <= 0. {
+        return Err(Error::InputError {
+            parameter_name: "gain".to_string(),
+            message: "Gain must be a valid positive and non-zero floating point number".to_string(),
+        });
+    }
+
+    if exposure.is_zero() {
+        return Err(Error::InputError {
+            parameter_name: "exposure".to_string(),
+            message: "Exposure must be a positive non-zero duration".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Mention the index of the offending image in input errors raised while building a list.
+pub(crate) fn with_index(index: usize, error: Error) -> Error {
+    match error {
+        Error::InputError {
+            parameter_name,
+            message,
+        } => Error::InputError {
+            parameter_name,
+            message: format!("Image at index {index}: {message}"),
+        },
+        error => error,
+    }
+}
+
+/// Index of the input with the median exposure. See [`HDRInputList::reference_index`].
+pub(crate) fn reference_index(inputs: &[HDRInput]) -> usize {
+    let mut indices = (0..inputs.len()).collect::<Vec<usize>>();
+    indices.sort_by(|a, b| {
+        inputs[*a]
+            .get_exposure()
+            .total_cmp(&inputs[*b].get_exposure())
+    });
+
+    indices.get(indices.len() / 2).copied().unwrap_or_default()
+}
+
+/// Multiply the color channels of `buffer` by the alpha channel of `image`, unless the image is
+/// fully opaque.
+///
+/// returns: The `(height, width, 1)` alpha channel, or `None` if the image is fully opaque
+fn premultiply_alpha(image: &DynamicImage, buffer: &mut Array3<f32>) -> Option<Array3<f32>> {
+    let rgba = image.to_rgba32f();
+
+    if rgba.pixels().all(|pixel| pixel.0[3] >= 1.) {
+        return None;
+    }
+
+    let (height, width, _) = buffer.dim();
+    #[allow(clippy::cast_possible_truncation)]
+    let alpha = Array3::from_shape_fn((height, width, 1), |(y, x, _)| {
+        rgba.get_pixel(x as u32, y as u32).0[3]
+    });
+
+    Zip::from(buffer.lanes_mut(Axis(2)))
+        .and(&alpha.index_axis(Axis(2), 0))
+        .par_for_each(|mut pixel, alpha| pixel *= *alpha);
+
+    Some(alpha)
+}
+
+/// Resize a `(height, width, channels)` buffer to the given dimensions with the given filter.
+#[allow(clippy::cast_possible_truncation)]
+fn resize_buffer(buffer: &Array3<f32>, width: u32, height: u32, filter: FilterType) -> Array3<f32> {
+    let (input_height, input_width, channels) = buffer.dim();
+    let (input_width, input_height) = (input_width as u32, input_height as u32);
+    let mut resized = Array3::<f32>::zeros((height as usize, width as usize, channels));
+
+    // Channels are resized one by one, since there is no image type for most channel counts.
+    for channel in 0..channels {
+        let data = buffer
+            .index_axis(Axis(2), channel)
+            .iter()
+            .copied()
+            .collect();
+        let image = ImageBuffer::<Luma<f32>, Vec<f32>>::from_raw(input_width, input_height, data)
+            .unwrap_or_else(|| unreachable!("Buffer length always matches its dimensions"));
+        let data = imageops::resize(&image, width, height, filter).into_raw();
+
+        resized
+            .index_axis_mut(Axis(2), channel)
+            .iter_mut()
+            .zip(data)
+            .for_each(|(value, data)| *value = data);
+    }
+
+    resized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    fn images(count: usize) -> Vec<DynamicImage> {
+        vec![DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, image::Rgb([128; 3]))); count]
+    }
+
+    fn seconds(exposures: &[f32]) -> Vec<Duration> {
+        exposures
+            .iter()
+            .map(|exposure| Duration::from_secs_f32(*exposure))
+            .collect()
+    }
+
+    fn input_error(error: Error) -> (String, String) {
+        match error {
+            Error::InputError {
+                parameter_name,
+                message,
+            } => (parameter_name, message),
+            error => panic!("Expected an input error, got {error:?}"),
+        }
+    }
+
+    #[test]
+    fn zero_exposure_is_rejected_with_the_image_index() {
+        let error = HDRInputList::with_images(&images(3), &seconds(&[0.5, 0., 2.]), &[1.; 3])
+            .err()
+            .expect("A zero exposure is invalid");
+        let (parameter_name, message) = input_error(error);
+
+        assert_eq!(parameter_name, "exposure");
+        assert!(message.starts_with("Image at index 1:"), "{message}");
+    }
+
+    #[test]
+    fn non_positive_and_non_finite_gains_are_rejected_with_the_image_index() {
+        for gain in [0., -1., f32::NAN, f32::INFINITY] {
+            let error =
+                HDRInputList::with_images(&images(3), &seconds(&[0.5, 1., 2.]), &[1., 1., gain])
+                    .err()
+                    .expect("The gain is invalid");
+            let (parameter_name, message) = input_error(error);
+
+            assert_eq!(parameter_name, "gain");
+            assert!(message.starts_with("Image at index 2:"), "{message}");
+        }
+    }
+
+    #[test]
+    fn merge_rejects_a_zero_exposure_with_the_image_index() {
+        let mut inputs = HDRInputList::with_images(&images(3), &seconds(&[0.5, 1., 2.]), &[1.; 3])
+            .expect("The stack is valid");
+        inputs.as_slice_mut()[1].exposure = 0.;
+
+        let error = crate::hdr_merge_images(&mut inputs).expect_err("A zero exposure is invalid");
+        let (parameter_name, message) = input_error(error);
+
+        assert_eq!(parameter_name, "exposures");
+        assert!(message.contains("index 1"), "{message}");
+    }
+
+    /// 8x8 RGB frame of a constant `0.25`, with a hot pixel of `1.0` in the green channel at
+    /// `(y, x)`.
+    fn frame_with_hot_pixel((y, x): (usize, usize), exposure: f32) -> HDRInput {
+        let mut buffer = Array3::from_elem((8, 8, 3), 0.25);
+        buffer[[y, x, 1]] = 1.;
+
+        HDRInput::with_buffer(buffer, Duration::from_secs_f32(exposure), 1.)
+            .expect("The frame is valid")
+    }
+
+    #[test]
+    fn hot_pixel_is_replaced_with_the_median_of_its_neighbours() {
+        for position in [(3, 4), (0, 0), (7, 3)] {
+            let mut input = frame_with_hot_pixel(position, 1.);
+
+            assert_eq!(input.correct_hot_pixels(0.1), 1);
+            assert_eq!(input.get_buffer(), Array3::from_elem((8, 8, 3), 0.25));
+        }
+    }
+
+    #[test]
+    fn values_within_the_threshold_are_kept() {
+        let mut input = frame_with_hot_pixel((3, 4), 1.);
+
+        assert_eq!(input.correct_hot_pixels(0.8), 0);
+        assert!((input.get_buffer()[[3, 4, 1]] - 1.).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn hot_pixel_is_removed_from_the_merge() {
+        let mut inputs = HDRInputList::from(vec![
+            frame_with_hot_pixel((3, 4), 1.),
+            frame_with_hot_pixel((3, 4), 2.),
+        ]);
+        let config = MergeConfig {
+            hot_pixel_threshold: Some(0.1),
+            ..MergeConfig::default()
+        };
+
+        let merged =
+            crate::hdr_merge_images_with_config(&mut inputs, &config).expect("The stack is valid");
+
+        // Each frame reads 0.25 after correction, so the radiances are 0.25 and 0.125.
+        let expected = (0.25 + 2. * 0.125) / 3.;
+        assert!(merged
+            .get_buffer()
+            .iter()
+            .all(|value| (value - expected).abs() < 1e-6));
+    }
+
+    /// Single row RGB gradient from black to white, at 16 bits per channel or rounded to 8.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn gradient(width: u32, sixteen_bit: bool) -> DynamicImage {
+        let value = |x: u32| x as f32 / (width - 1) as f32;
+
+        if sixteen_bit {
+            DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(width, 1, |x, _| {
+                image::Rgb([(value(x) * f32::from(u16::MAX)).round() as u16; 3])
+            }))
+        } else {
+            DynamicImage::ImageRgb8(RgbImage::from_fn(width, 1, |x, _| {
+                image::Rgb([(value(x) * f32::from(u8::MAX)).round() as u8; 3])
+            }))
+        }
+    }
+
+    /// Radiance of the first channel of a gradient after encoding and decoding it in the given
+    /// format, along with the color type it was decoded as.
+    fn gradient_radiance(
+        gradient: &DynamicImage,
+        format: image::ImageFormat,
+    ) -> (Vec<f32>, image::ColorType) {
+        let mut data = std::io::Cursor::new(Vec::new());
+        gradient
+            .write_to(&mut data, format)
+            .expect("The gradient can be encoded");
+        let image =
+            crate::io::read_image(data.get_ref(), Some(format)).expect("The gradient decodes");
+
+        let input = HDRInput::with_image(&image, Duration::from_millis(100), 1.)
+            .expect("The exposure and gain are valid");
+        let radiance = crate::poisson::calculate_radiance(&input, &MergeConfig::default())
+            .expect("The configuration applies");
+
+        (
+            radiance.index_axis(Axis(2), 0).iter().copied().collect(),
+            image.color(),
+        )
+    }
+
+    /// Number of distinct values and largest step between neighbours of a gradient.
+    fn levels_and_largest_step(radiance: &[f32]) -> (usize, f32) {
+        let mut levels = radiance.to_vec();
+        levels.dedup();
+        let step = radiance
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .fold(0., f32::max);
+
+        (levels.len(), step)
+    }
+
+    #[test]
+    fn sixteen_bit_gradients_keep_their_precision() {
+        for format in [image::ImageFormat::Png, image::ImageFormat::Tiff] {
+            let (radiance_16, color_16) = gradient_radiance(&gradient(1024, true), format);
+            let (radiance_8, color_8) = gradient_radiance(&gradient(1024, false), format);
+            assert_eq!(color_16, image::ColorType::Rgb16, "{format:?}");
+            assert_eq!(color_8, image::ColorType::Rgb8, "{format:?}");
+
+            // White is 1 in both, so the radiance spans the same range.
+            assert!((radiance_16[1023] - 10.).abs() < 1e-4, "{format:?}");
+            assert!((radiance_8[1023] - 10.).abs() < 1e-4, "{format:?}");
+
+            let (levels_16, step_16) = levels_and_largest_step(&radiance_16);
+            let (levels_8, step_8) = levels_and_largest_step(&radiance_8);
+            assert_eq!(levels_16, 1024, "{format:?}");
+            assert_eq!(levels_8, 256, "{format:?}");
+            assert!(step_16 * 3. < step_8, "{format:?}: {step_16} vs {step_8}");
+        }
+    }
+
+    #[test]
+    fn cached_exif_data_is_used_without_parsing_the_files() {
+        let directory = tempfile::tempdir().expect("The directory can be created");
+        let paths = [1, 2].map(|index| directory.path().join(format!("{index}.png")));
+        for path in &paths {
+            DynamicImage::new_rgb8(4, 4)
+                .save(path)
+                .expect("The file can be written");
+        }
+
+        assert!(
+            HDRInputList::with_overrides(&paths, None, None).is_err(),
+            "The files have no EXIF metadata"
+        );
+
+        let cached = [(10, 1.), (40, 4.)].map(|(milliseconds, gain)| ExifData {
+            exposure: Some(Duration::from_millis(milliseconds)),
+            gain: Some(gain),
+            ..ExifData::default()
+        });
+        let inputs =
+            HDRInputList::with_exif_data(&paths, &cached).expect("The cache has every value");
+
+        for (input, (exposure, gain)) in inputs.as_slice().iter().zip([(0.01, 1.), (0.04, 4.)]) {
+            assert!((input.get_exposure() - exposure).abs() < 1e-6);
+            assert!((input.get_gain() - gain).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mixed_bit_depths_and_opaque_alpha_have_the_same_channel_layout() {
+        let images = [
+            DynamicImage::new_rgb8(4, 4),
+            DynamicImage::new_rgb16(4, 4),
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                4,
+                4,
+                image::Rgba([64, 64, 64, 255]),
+            )),
+        ];
+
+        let inputs = HDRInputList::with_images(&images, &seconds(&[0.5, 1., 2.]), &[1.; 3])
+            .expect("Every image is a color image");
+
+        assert_eq!(inputs.len(), 3);
+    }
+
+    #[test]
+    fn grayscale_and_color_images_are_rejected() {
+        let images = [
+            DynamicImage::new_rgb8(4, 4),
+            DynamicImage::new_luma16(4, 4),
+            DynamicImage::new_rgba8(4, 4),
+        ];
+
+        let Err(Error::ReadErrors(failures)) =
+            HDRInputList::with_images(&images, &seconds(&[0.5, 1., 2.]), &[1.; 3])
+        else {
+            panic!("The grayscale image differs from the first one");
+        };
+
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].index, 1);
     }
 }
@@ -0,0 +1,399 @@
+//! Cheap inspection of a stack before merging it
+
+use crate::config::{ExposureModel, MergeConfig};
+use crate::error::ReadFailure;
+use crate::exif::{get_exif_data, ExifData};
+use crate::input::read_each;
+use crate::io::{read_file, read_image_dimensions};
+use crate::{validate_exposure_spread, validate_image_count, Error};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Information about a single file of a stack, see [`inspect_stack`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameInfo {
+    /// Path of the file
+    pub path: PathBuf,
+    /// Width of the image in pixels
+    pub width: u32,
+    /// Height of the image in pixels
+    pub height: u32,
+    /// Whether the file contains EXIF metadata that could be parsed
+    pub has_exif: bool,
+    /// Exposure time read from EXIF metadata, if present and valid
+    pub exposure: Option<Duration>,
+    /// Gain read from EXIF metadata, if present
+    pub gain: Option<f32>,
+    /// Aperture read from EXIF metadata, if present
+    pub aperture: Option<f32>,
+}
+
+impl FrameInfo {
+    /// Whether the file has everything needed to merge it without overrides.
+    #[must_use]
+    pub fn is_mergeable(&self) -> bool {
+        self.exposure.is_some() && self.gain.is_some_and(|gain| gain > 0.)
+    }
+}
+
+/// Information about a stack of files, see [`inspect_stack`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackInfo {
+    /// Information about each file, in the same order as the paths
+    pub frames: Vec<FrameInfo>,
+    /// Whether every image has the same width and height
+    pub consistent_dimensions: bool,
+}
+
+/// Given a set of file paths, read the dimensions, exposure and gain of each file without
+/// decoding the images, so that problems like mismatched sizes or missing EXIF metadata can be
+/// reported before running an expensive merge. Only the header of each image is decoded, except
+/// for RAW files, which are fully decoded to get the dimensions of the processed image.
+///
+/// Missing or invalid EXIF metadata is reported in [`FrameInfo`] rather than as an error.
+///
+/// # Errors
+/// - If any of the files cannot be read, or the dimensions of its image cannot be determined,
+///   listing every failure.
+pub fn inspect_stack<P: AsRef<Path> + Sync>(paths: &[P]) -> Result<StackInfo, Error> {
+    let frames = read_each(
+        paths,
+        |path| Some(path.as_ref()),
+        |path, _| inspect_frame(path.as_ref()),
+    )?;
+
+    let consistent_dimensions = frames
+        .windows(2)
+        .all(|frames| (frames[0].width, frames[0].height) == (frames[1].width, frames[1].height));
+
+    Ok(StackInfo {
+        frames,
+        consistent_dimensions,
+    })
+}
+
+fn inspect_frame(path: &Path) -> Result<FrameInfo, Error> {
+    let data = read_file(path)?;
+    let format = image::ImageFormat::from_path(path).ok();
+    let (width, height) = read_image_dimensions(&data, format)?;
+    let exif = get_exif_data(&data).ok();
+    let exif_data = exif.as_ref().map(ExifData::from).unwrap_or_default();
+
+    Ok(FrameInfo {
+        path: path.to_path_buf(),
+        width,
+        height,
+        has_exif: exif.is_some(),
+        exposure: exif_data.exposure,
+        gain: exif_data.gain,
+        aperture: exif_data.aperture,
+    })
+}
+
+/// Given a set of file paths, check that they can be merged with the given configuration
+/// without merging them, so that pipelines can reject a stack before committing resources to
+/// it. The files are read like in [`inspect_stack`], and the checks of a merge that don't need
+/// the pixels are run on the result: the number of images, their dimensions unless
+/// [`MergeConfig::resize_filter`] is set, the EXIF exposure, gain and, if the exposure model
+/// needs it, aperture of every image, and [`MergeConfig::min_exposure_ratio`].
+///
+/// Checks of the decoded pixels, like mixed grayscale and color images or
+/// [`MergeConfig::extreme_frames`], are not run, so merging a stack that passes can still fail.
+///
+/// # Errors
+/// - If the configuration is invalid
+/// - If fewer than two paths are provided
+/// - If any of the files cannot be read, or the dimensions of its image cannot be determined,
+///   listing every failure.
+/// - If any of the images lacks an exposure, gain or needed aperture in its EXIF metadata, or
+///   has different dimensions than the first image, listing every such image.
+/// - If the exposures are not spread enough, see [`MergeConfig::min_exposure_ratio`]
+pub fn validate_stack<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    config: &MergeConfig,
+) -> Result<(), Error> {
+    config.validate()?;
+    validate_image_count(paths.len())?;
+
+    let frames = inspect_stack(paths)?.frames;
+    let (width, height) = (frames[0].width, frames[0].height);
+
+    let mut range = None::<(f32, f32)>;
+    let mut failures = Vec::new();
+
+    for (index, frame) in frames.iter().enumerate() {
+        let failure = |parameter_name: &str, message: String| ReadFailure {
+            index,
+            path: Some(frame.path.clone()),
+            error: Error::InputError {
+                parameter_name: parameter_name.to_string(),
+                message,
+            },
+        };
+
+        let exposure = frame.exposure.map(|exposure| exposure.as_secs_f32());
+        let gain = frame.gain.filter(|gain| gain.is_finite() && *gain > 0.);
+        let aperture = match config.exposure_model {
+            ExposureModel::TimeAndGain => Some(1.),
+            ExposureModel::TimeGainAndAperture => frame.aperture,
+        };
+
+        let missing = [
+            ("exposures", "exposure time", exposure),
+            ("gains", "gain", gain),
+            ("aperture", "aperture", aperture),
+        ]
+        .into_iter()
+        .filter(|(_, _, value)| value.is_none())
+        .collect::<Vec<_>>();
+
+        // Report a single problem per image, so that every failure is a distinct image.
+        if config.resize_filter.is_none() && (frame.width, frame.height) != (width, height) {
+            failures.push(failure(
+                "paths",
+                format!(
+                    "Image is {}x{}, expected {width}x{height}",
+                    frame.width, frame.height
+                ),
+            ));
+        } else if let Some((parameter_name, _, _)) = missing.first() {
+            let names = missing
+                .iter()
+                .map(|(_, name, _)| *name)
+                .collect::<Vec<&str>>();
+
+            failures.push(failure(
+                parameter_name,
+                format!(
+                    "The EXIF metadata of the image has no valid {}",
+                    names.join(" or ")
+                ),
+            ));
+        }
+
+        if let (Some(exposure), Some(gain), Some(aperture)) = (exposure, gain, aperture) {
+            let factor = exposure * gain / (aperture * aperture);
+            range = Some(range.map_or((factor, factor), |(smallest, largest)| {
+                (factor.min(smallest), factor.max(largest))
+            }));
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(Error::ReadErrors(failures));
+    }
+
+    if let (Some(min_ratio), Some(range)) = (config.min_exposure_ratio, range) {
+        validate_exposure_spread(range, min_ratio)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{exposure_fields, field, jpeg};
+    use exif::{Field, Rational, Tag, Value};
+    use image::DynamicImage;
+    use tempfile::TempDir;
+
+    /// Exposure of `1 / denominator` seconds at ISO 100, and an f-number of `f_number / 10`
+    fn fields(denominator: u32, f_number: Option<u32>) -> Vec<Field> {
+        let mut fields = exposure_fields(1, denominator, 100);
+        if let Some(f_number) = f_number {
+            fields.push(field(
+                Tag::FNumber,
+                Value::Rational(vec![Rational::from((f_number, 10))]),
+            ));
+        }
+
+        fields
+    }
+
+    /// Write a gray JPEG of the given size with the fields as EXIF metadata for every frame.
+    fn stack(frames: &[((u32, u32), &[Field])]) -> (TempDir, Vec<PathBuf>) {
+        let directory = tempfile::tempdir().expect("The directory can be created");
+        let paths = frames
+            .iter()
+            .enumerate()
+            .map(|(index, ((width, height), fields))| {
+                let path = directory.path().join(format!("{index}.jpg"));
+                let image = DynamicImage::new_luma8(*width, *height);
+                std::fs::write(&path, jpeg(&image, fields)).expect("The file can be written");
+                path
+            })
+            .collect();
+
+        (directory, paths)
+    }
+
+    /// Index and parameter name of every failure of a stack
+    fn failures(error: Error) -> Vec<(usize, String)> {
+        let Error::ReadErrors(failures) = error else {
+            panic!("Expected the failures of the images, got {error:?}");
+        };
+
+        failures
+            .into_iter()
+            .map(|failure| match failure.error {
+                Error::InputError { parameter_name, .. } => (failure.index, parameter_name),
+                error => panic!("Expected an input error, got {error:?}"),
+            })
+            .collect()
+    }
+
+    fn aperture_config() -> MergeConfig {
+        MergeConfig {
+            exposure_model: ExposureModel::TimeGainAndAperture,
+            ..MergeConfig::default()
+        }
+    }
+
+    #[test]
+    fn bracketed_stack_is_valid() {
+        let (_directory, paths) = stack(&[
+            ((8, 8), &fields(100, None)),
+            ((8, 8), &fields(25, None)),
+            ((8, 8), &fields(5, None)),
+        ]);
+
+        validate_stack(&paths, &MergeConfig::default()).expect("The stack can be merged");
+    }
+
+    #[test]
+    fn fewer_than_two_paths_are_rejected() {
+        let (_directory, paths) = stack(&[((8, 8), &fields(100, None))]);
+
+        let error = validate_stack(&paths, &MergeConfig::default())
+            .expect_err("A single image cannot be merged");
+        assert!(
+            matches!(error, Error::InputError { ref parameter_name, .. } if parameter_name == "paths"),
+            "{error:?}"
+        );
+
+        let error = validate_stack::<PathBuf>(&[], &MergeConfig::default())
+            .expect_err("No images cannot be merged");
+        assert!(matches!(error, Error::NoImages), "{error:?}");
+    }
+
+    #[test]
+    fn mismatched_dimensions_are_reported_per_image() {
+        let (_directory, paths) = stack(&[
+            ((8, 8), &fields(100, None)),
+            ((16, 8), &fields(25, None)),
+            ((8, 8), &fields(5, None)),
+            ((8, 16), &fields(1, None)),
+        ]);
+
+        let error = validate_stack(&paths, &MergeConfig::default())
+            .expect_err("The images have different sizes");
+        assert_eq!(
+            failures(error),
+            [(1, "paths".to_string()), (3, "paths".to_string())]
+        );
+    }
+
+    #[test]
+    fn mismatched_dimensions_are_valid_when_resizing() {
+        let (_directory, paths) = stack(&[
+            ((8, 8), &fields(100, None)),
+            ((16, 8), &fields(25, None)),
+            ((8, 16), &fields(5, None)),
+        ]);
+        let config = MergeConfig {
+            resize_filter: Some(image::imageops::FilterType::Triangle),
+            ..MergeConfig::default()
+        };
+
+        validate_stack(&paths, &config).expect("The images are resized to the first one");
+    }
+
+    #[test]
+    fn missing_exposure_and_gain_are_reported_per_image() {
+        let no_exposure = [field(Tag::PhotographicSensitivity, Value::Short(vec![100]))];
+        let no_gain = [field(
+            Tag::ExposureTime,
+            Value::Rational(vec![Rational::from((1, 25))]),
+        )];
+        let (_directory, paths) = stack(&[
+            ((8, 8), &fields(100, None)),
+            ((8, 8), &no_exposure),
+            ((8, 8), &no_gain),
+            ((8, 8), &[]),
+        ]);
+
+        let error =
+            validate_stack(&paths, &MergeConfig::default()).expect_err("Metadata is missing");
+        assert_eq!(
+            failures(error),
+            [
+                (1, "exposures".to_string()),
+                (2, "gains".to_string()),
+                (3, "exposures".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn aperture_is_only_needed_by_the_aperture_exposure_model() {
+        let (_directory, paths) = stack(&[
+            ((8, 8), &fields(100, Some(28))),
+            ((8, 8), &fields(25, None)),
+            ((8, 8), &fields(5, Some(28))),
+        ]);
+
+        validate_stack(&paths, &MergeConfig::default())
+            .expect("The aperture is ignored by the default exposure model");
+
+        let error = validate_stack(&paths, &aperture_config())
+            .expect_err("The second image has no aperture");
+        assert_eq!(failures(error), [(1, "aperture".to_string())]);
+    }
+
+    #[test]
+    fn aperture_bracket_is_valid_with_the_aperture_exposure_model() {
+        let (_directory, paths) = stack(&[
+            ((8, 8), &fields(100, Some(14))),
+            ((8, 8), &fields(100, Some(28))),
+            ((8, 8), &fields(100, Some(56))),
+        ]);
+        let config = MergeConfig {
+            min_exposure_ratio: Some(4.),
+            ..aperture_config()
+        };
+
+        // The stack spans a factor of 16 through the aperture alone.
+        validate_stack(&paths, &config).expect("Every image has an aperture");
+        validate_stack(
+            &paths,
+            &MergeConfig {
+                min_exposure_ratio: Some(4.),
+                ..MergeConfig::default()
+            },
+        )
+        .expect_err("The exposures are identical when the aperture is ignored");
+    }
+
+    #[test]
+    fn exposures_below_the_minimum_ratio_are_rejected() {
+        let (_directory, paths) = stack(&[
+            ((8, 8), &fields(100, None)),
+            ((8, 8), &fields(80, None)),
+            ((8, 8), &fields(50, None)),
+        ]);
+        let config = |min_exposure_ratio| MergeConfig {
+            min_exposure_ratio: Some(min_exposure_ratio),
+            ..MergeConfig::default()
+        };
+
+        // The exposures span a factor of 2.
+        validate_stack(&paths, &config(1.5)).expect("The exposures are spread enough");
+
+        let error = validate_stack(&paths, &config(4.)).expect_err("The stack is not bracketed");
+        assert!(
+            matches!(error, Error::InputError { ref parameter_name, .. } if parameter_name == "exposures"),
+            "{error:?}"
+        );
+    }
+}
@@ -1,13 +1,187 @@
 //! Helper functions to read and decode images
 
+use crate::exif::{get_exif_data, get_orientation};
 use crate::Error;
-use image::DynamicImage;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageReader};
+use std::io::Cursor;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the RAW formats decoded with rawloader, in lowercase.
+#[cfg(feature = "read-raw-image")]
+const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "crw", "dcr", "dng", "erf", "iiq", "kdc", "mef", "mos", "mrw", "nef",
+    "nrw", "orf", "pef", "raf", "raw", "rw2", "rwl", "srf", "srw",
+];
+
+/// Files at least this large are memory mapped instead of being read into memory when the
+/// `mmap` feature is enabled. Smaller files are cheap to copy, and mapping them costs more than
+/// it saves.
+#[cfg(feature = "mmap")]
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Contents of a file, either read into memory or memory mapped. See [`read_file`]. A mapped
+/// file stays mapped until this is dropped, so it must outlive everything decoded from it.
+pub(crate) enum FileData {
+    Read(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Read(data) => data,
+            #[cfg(feature = "mmap")]
+            FileData::Mapped(map) => map,
+        }
+    }
+}
+
+/// Read the file at the given path. With the `mmap` feature, files of at least
+/// [`MMAP_THRESHOLD`] bytes are memory mapped instead, so that large files like TIFFs are not
+/// copied into memory up front, and only the parts that are used are ever loaded. Files that
+/// cannot be mapped, like pipes, are read normally.
+///
+/// The mapped file must not be modified while it is read, otherwise the decoded image may
+/// contain a mix of the old and new contents.
+///
+/// # Errors
+/// If file cannot be read
+pub(crate) fn read_file(path: &Path) -> Result<FileData, Error> {
+    #[cfg(feature = "mmap")]
+    {
+        use std::io::Read;
+
+        let mut file = std::fs::File::open(path)?;
+        if file.metadata()?.len() >= MMAP_THRESHOLD {
+            // SAFETY: The map is only ever read, and isn't unmapped before the `FileData`
+            // holding it is dropped. Concurrent modification of the file is documented above.
+            if let Ok(map) = unsafe { memmap2::Mmap::map(&file) } {
+                return Ok(FileData::Mapped(map));
+            }
+        }
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        Ok(FileData::Read(data))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    Ok(FileData::Read(std::fs::read(path)?))
+}
+
+/// List the image files of a directory, sorted by path so that the order doesn't depend on the
+/// filesystem. Files are recognised by their extension, ignoring case, and must be in a format
+/// the image crate can decode, or a RAW format with the `read-raw-image` feature. Other files,
+/// hidden files and subdirectories are skipped.
+///
+/// # Errors
+/// If the directory cannot be read
+pub(crate) fn image_paths_in_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+
+        if !hidden && entry.file_type()?.is_file() && is_image_path(&path) {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Whether the extension of a path is that of a format that can be decoded, see
+/// [`image_paths_in_dir`].
+fn is_image_path(path: &Path) -> bool {
+    if image::ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled()) {
+        return true;
+    }
+
+    #[cfg(feature = "read-raw-image")]
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        return RAW_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str());
+    }
+
+    false
+}
+
+/// Read the file at the given path and decode the image in it. See [`read_file`] and
+/// [`read_image`].
+///
+/// returns: The contents of the file along with the decoded image
+///
+/// # Errors
+/// If file cannot be read or image cannot be decoded
+pub(crate) fn read_image_file(path: &Path) -> Result<(FileData, DynamicImage), Error> {
+    let data = read_file(path)?;
+    let format = image::ImageFormat::from_path(path).ok();
+    let image = read_image(&data, format)?;
+
+    Ok((data, image))
+}
+
+/// Get the `(width, height)` of an encoded image once it is oriented, see [`read_image`]. Only
+/// the header is decoded when the format is supported by the image crate. Other files are fully
+/// decoded as RAW images.
+///
+/// # Errors
+/// If the dimensions cannot be read
+pub(crate) fn read_image_dimensions(
+    data: &[u8],
+    format: Option<image::ImageFormat>,
+) -> Result<(u32, u32), Error> {
+    let reader = match format {
+        Some(format) => ImageReader::with_format(Cursor::new(data), format),
+        None => ImageReader::new(Cursor::new(data)).with_guessed_format()?,
+    };
+
+    match reader.into_dimensions() {
+        Ok((width, height)) => match exif_orientation(data) {
+            Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH => Ok((height, width)),
+            _ => Ok((width, height)),
+        },
+        Err(err) => {
+            #[cfg(not(feature = "read-raw-image"))]
+            return Err(err.into());
+            #[cfg(feature = "read-raw-image")]
+            {
+                let _ = err;
+                let image = read_raw_image(data)?;
+                Ok((image.width(), image.height()))
+            }
+        }
+    }
+}
+
+/// Orientation of an encoded image from its EXIF metadata, see [`get_orientation`]. Images
+/// without a valid orientation are taken as they are.
+fn exif_orientation(data: &[u8]) -> Orientation {
+    get_exif_data(data)
+        .and_then(|exif| get_orientation(&exif))
+        .unwrap_or(Orientation::NoTransforms)
+}
 
 /// Given a path to a file, attempt to read the image.
 /// The function supports reading raw images. All
 /// formats and cameras supported by rawloader crate
 /// [rawloader](https://github.com/pedrocr/rawloader) are supported.
 ///
+/// The image is rotated and flipped upright according to its EXIF orientation, see
+/// [`get_orientation`]. RAW images are already oriented by their processing pipeline.
+///
 /// # Errors
 /// If image cannot be read
 pub(crate) fn read_image(
@@ -20,12 +194,18 @@ pub(crate) fn read_image(
     };
 
     match load_result {
-        Ok(image) => Ok(image),
-        Err(_err) => {
+        Ok(mut image) => {
+            image.apply_orientation(exif_orientation(data));
+            Ok(image)
+        }
+        Err(err) => {
             #[cfg(not(feature = "read-raw-image"))]
-            return Err(_err.into());
+            return Err(err.into());
             #[cfg(feature = "read-raw-image")]
-            Ok(read_raw_image(data)?)
+            {
+                let _ = err;
+                Ok(read_raw_image(data)?)
+            }
         }
     }
 }
@@ -62,3 +242,93 @@ pub(crate) fn read_raw_image(data: &[u8]) -> Result<DynamicImage, Error> {
         ))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{field, jpeg};
+    use exif::{Tag, Value};
+    use image::{GenericImageView, GrayImage, Luma};
+    use std::time::Duration;
+
+    /// JPEG of a frame whose first half along its width is black and the rest white, with the
+    /// given EXIF orientation.
+    fn half_black_frame(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        let image = GrayImage::from_fn(width, height, |x, _| {
+            Luma([if x < width / 2 { 0 } else { 255 }])
+        });
+
+        jpeg(
+            &DynamicImage::ImageLuma8(image),
+            &[field(Tag::Orientation, Value::Short(vec![orientation]))],
+        )
+    }
+
+    /// Whether the top half of an oriented image is black and the bottom half white.
+    fn is_black_on_top(image: &DynamicImage) -> bool {
+        let (width, height) = image.dimensions();
+        let value = |y| image.get_pixel(width / 2, y).0[0];
+
+        value(height / 4) < 32 && value(height * 3 / 4) > 223
+    }
+
+    #[test]
+    fn image_with_orientation_6_is_rotated_clockwise() {
+        // Rotating clockwise turns the left half into the top half.
+        let image = read_image(&half_black_frame(16, 8, 6), Some(image::ImageFormat::Jpeg))
+            .expect("The image decodes");
+
+        assert_eq!(image.dimensions(), (8, 16));
+        assert!(is_black_on_top(&image));
+    }
+
+    #[test]
+    fn dimensions_of_an_image_with_orientation_6_are_swapped() {
+        let data = half_black_frame(16, 8, 6);
+
+        assert_eq!(
+            read_image_dimensions(&data, Some(image::ImageFormat::Jpeg))
+                .expect("The header decodes"),
+            (8, 16)
+        );
+        assert_eq!(
+            read_image_dimensions(&data, None).expect("The format is guessed"),
+            (8, 16)
+        );
+    }
+
+    #[test]
+    fn upright_image_is_left_as_is() {
+        let data = half_black_frame(16, 8, 1);
+        let image = read_image(&data, None).expect("The image decodes");
+
+        assert_eq!(image.dimensions(), (16, 8));
+        assert_eq!(
+            read_image_dimensions(&data, None).expect("The header decodes"),
+            (16, 8)
+        );
+        assert!(image.get_pixel(4, 4).0[0] < 32);
+        assert!(image.get_pixel(12, 4).0[0] > 223);
+    }
+
+    #[test]
+    fn rotated_and_upright_frames_merge_consistently() {
+        let upright = GrayImage::from_fn(8, 16, |_, y| Luma([if y < 8 { 0 } else { 255 }]));
+        let upright = jpeg(
+            &DynamicImage::ImageLuma8(upright),
+            &[field(Tag::Orientation, Value::Short(vec![1]))],
+        );
+        let rotated = half_black_frame(16, 8, 6);
+
+        let merged = crate::hdr_merge_encoded_images(
+            &[&upright, &rotated],
+            &[Duration::from_millis(100), Duration::from_millis(200)],
+            &[1., 1.],
+        )
+        .expect("Both frames are 8x16 once oriented");
+
+        assert!(is_black_on_top(
+            &DynamicImage::from(merged).to_luma8().into()
+        ));
+    }
+}
@@ -1,37 +1,1270 @@
 //! An implementation of HDR Radiance Estimation using Poisson Photon Noise Estimator for creating HDR image from a set of images
+//!
+//! Reading and merging images is parallelised with rayon. Unless stated otherwise, functions run
+//! on rayon's global thread pool. Use [`hdr_merge_images_in_pool`], or call any function from
+//! within [`rayon::ThreadPool::install`], to run the work on a dedicated pool instead.
+//!
+//! The `parallel` feature, enabled by default, provides the rayon support. Without it, every
+//! function runs on the calling thread and no thread pool is ever started, e.g. for embedded
+//! targets. Results are identical with and without the feature. RAW decoding and some formats of
+//! the image crate still depend on rayon, only the merge itself doesn't use it.
+//!
+//! The crate builds for `wasm32-unknown-unknown`, where rayon runs everything on the calling
+//! thread. There is no filesystem in that environment, so use [`hdr_merge_encoded_images`] or
+//! [`hdr_merge_dynamic_images`] instead of the functions reading paths. Enable the `wasm` feature
+//! for JavaScript bindings in the `wasm` module.
+//!
+//! Enable the `tracing` feature to emit [`tracing`](https://docs.rs/tracing) spans around reading
+//! files, EXIF extraction, radiance computation and accumulation, along with the exposure and
+//! gain of every file at debug level. Without the feature, no instrumentation is compiled in.
+//!
+//! Enable the `async` feature for functions in the `asynchronous` module that merge from within
+//! a tokio runtime without blocking its worker threads.
+//!
+//! Enable the `mmap` feature to memory map large files instead of reading them into memory,
+//! which avoids copying inputs like 100 MB TIFFs before decoding them. Mapped files must not be
+//! modified while they are being read.
+//!
+//! Enable the `gpu` feature to accumulate the frames in a compute shader with
+//! [wgpu](https://docs.rs/wgpu) when the machine has a hardware adapter. Merges fall back to the
+//! CPU without one, and for configurations the shader doesn't implement, with the same result up
+//! to floating point rounding.
 #![allow(clippy::multiple_crate_versions)]
 
-use image::DynamicImage;
-use poisson::calculate_poisson_estimate;
+use poisson::{calculate_poisson_estimate, find_clipped_frames, Estimate};
 
+pub mod align;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod builder;
+pub mod calibration;
+pub mod color;
+pub mod config;
+pub mod debevec;
+pub mod denoise;
 pub mod error;
 pub mod exif;
+pub mod export;
 pub mod extensions;
+pub mod filename;
+pub mod frame;
+pub mod fusion;
+#[cfg(feature = "gpu")]
+mod gpu;
+pub mod gradient;
 pub mod input;
+pub mod inspect;
 mod io;
+pub mod output;
+mod parallel;
 mod poisson;
+pub mod sidecar;
+pub mod stream;
 pub mod stretch;
+#[cfg(test)]
+mod test_support;
+pub mod tonemap;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+use crate::align::Shift;
+use crate::config::{ExtremeFrameHandling, MergeConfig};
+use crate::error::ReadFailure;
+use crate::exif::ExifData;
 use crate::extensions::NDArrayBuffer;
-use crate::input::HDRInputList;
+use crate::filename::ExposurePattern;
+use crate::frame::Frame;
+use crate::input::{HDRInput, HDRInputList};
+use crate::output::{ClippedFrame, HDRImage, MergeReport};
+use crate::parallel::prelude::*;
+use crate::sidecar::Sidecar;
+use crate::tonemap::{BitDepth, ToneMapOperator};
 pub use error::Error;
+use image::DynamicImage;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Make sure the stack contains enough images for an HDR merge.
+fn validate_image_count(count: usize) -> Result<(), Error> {
+    match count {
+        0 => Err(Error::NoImages),
+        1 => Err(Error::InputError {
+            parameter_name: "paths".to_string(),
+            message: "Only 1 image was provided, at least two images are required".to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Make sure the stack can be merged, i.e. it has enough images, all of them share the same
+/// dimensions and have finite positive exposures and gains.
+fn validate_inputs(inputs: &HDRInputList) -> Result<(), Error> {
+    validate_image_count(inputs.len())?;
+
+    let shape = inputs.as_slice()[0].get_buffer().dim();
+
+    for (index, input) in inputs.as_slice().iter().enumerate() {
+        validate_input(index, input, shape)?;
+    }
+
+    Ok(())
+}
+
+/// Make sure the input at `index` has a finite positive exposure and gain, and the same
+/// `(height, width, channels)` shape as the first image of the stack.
+fn validate_input(
+    index: usize,
+    input: &HDRInput,
+    (height, width, channels): (usize, usize, usize),
+) -> Result<(), Error> {
+    let (input_height, input_width, input_channels) = input.get_buffer().dim();
+
+    for (parameter_name, value) in [
+        ("exposures", input.get_exposure()),
+        ("gains", input.get_gain()),
+    ] {
+        if !value.is_finite() || value <= 0. {
+            return Err(Error::InputError {
+                parameter_name: parameter_name.to_string(),
+                message: format!(
+                    "Image at index {index} has a value of {value}, expected a finite positive number"
+                ),
+            });
+        }
+    }
+
+    if (input_channels == 1) != (channels == 1) {
+        let color_type = |channels| if channels == 1 { "grayscale" } else { "color" };
+
+        return Err(Error::InputError {
+            parameter_name: "paths".to_string(),
+            message: format!(
+                "Image at index {index} is a {} image while the first image is a {} image, grayscale and color images cannot be merged together",
+                color_type(input_channels),
+                color_type(channels)
+            ),
+        });
+    }
+
+    if (input_height, input_width, input_channels) != (height, width, channels) {
+        return Err(Error::InputError {
+            parameter_name: "paths".to_string(),
+            message: format!(
+                "Image at index {index} is {input_width}x{input_height} with {input_channels} channel(s), expected {width}x{height} with {channels} channel(s)"
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Make sure the longest effective exposure of the stack is at least `min_ratio` times the
+/// shortest one, see [`MergeConfig::min_exposure_ratio`].
+fn validate_exposure_spread((shortest, longest): (f32, f32), min_ratio: f32) -> Result<(), Error> {
+    if longest < shortest * min_ratio {
+        return Err(Error::InputError {
+            parameter_name: "exposures".to_string(),
+            message: format!(
+                "Effective exposures range from {shortest} to {longest}, a ratio of {} which is below the minimum of {min_ratio}. The images were probably not bracketed",
+                longest / shortest
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Given a list of inputs, attempt to HDR merge the images
+/// and produce a single [`HDRImage`] holding the linear radiance estimate.
+/// The result can be converted into a [`image::DynamicImage`] using [`From`].
+///
+/// Stacks of grayscale images are merged on their single channel and produce a grayscale
+/// [`HDRImage`]. All other images are merged as RGB. Inputs created with
+/// [`HDRInput::with_buffer`] are merged on every one of their channels, e.g. for multispectral
+/// stacks.
+///
+/// # Errors
+/// - If image list is empty
+/// - If the stack mixes grayscale and color images.
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn hdr_merge_images(inputs: &mut HDRInputList) -> Result<HDRImage, Error> {
+    hdr_merge_images_with_config(inputs, &MergeConfig::default())
+}
+
+/// Same as [`hdr_merge_images`], but allows customising the merge with a [`MergeConfig`].
+///
+/// # Errors
+/// - If image list is empty
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+/// - If the exposures span less than [`MergeConfig::min_exposure_ratio`], when it is set
+/// - If the result is not finite and [`MergeConfig::non_finite`] asks for an error
+pub fn hdr_merge_images_with_config(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+) -> Result<HDRImage, Error> {
+    hdr_merge_images_with_progress(inputs, config, |_| {})
+}
+
+/// Same as [`hdr_merge_images_with_config`], but reports progress of the merge through the
+/// `progress` callback. The callback is called on the calling thread after each image has been
+/// accumulated into the result, with the fraction of the work done between `0.0` and `1.0`.
+///
+/// # Errors
+/// - If image list is empty
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+pub fn hdr_merge_images_with_progress(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+    progress: impl Fn(f32),
+) -> Result<HDRImage, Error> {
+    let estimate = merge(inputs, config, &progress)?;
+
+    Ok(HDRImage::with_alpha(
+        estimate.phi,
+        estimate.alpha,
+        config.premultiply_alpha,
+    ))
+}
+
+/// Same as [`hdr_merge_images_with_config`], but also returns a per-pixel confidence map.
+///
+/// The confidence of a pixel is the share of the total exposure weight that came from frames in
+/// which that pixel was neither saturated nor below the noise floor, in `[0, 1]`. A value of `1.0`
+/// means every frame measured the pixel reliably, while `0.0` means the merged value was derived
+/// entirely from clipped samples.
+///
+/// returns: The merged image along with the confidence map, in row major order with one value
+/// per pixel.
+///
+/// # Errors
+/// - If image list is empty
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+pub fn hdr_merge_images_with_confidence(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+) -> Result<(HDRImage, Vec<f32>), Error> {
+    let estimate = merge(inputs, config, &|_| {})?;
+
+    Ok((
+        HDRImage::with_alpha(estimate.phi, estimate.alpha, config.premultiply_alpha),
+        estimate.confidence.into_raw_vec_and_offset().0,
+    ))
+}
+
+/// Same as [`hdr_merge_images_with_config`], but also returns a [`MergeReport`] describing the
+/// reference frame, the exposure range and the share of saturated pixels of each frame, along
+/// with the time the merge took. A frame that is mostly saturated contributes little to the
+/// result, and often points at a misconfigured bracket. See [`MergeReport::recommends_fusion`]
+/// for stacks that are better suited to exposure fusion, which is also logged with the `tracing`
+/// feature.
+///
+/// # Errors
+/// - If image list is empty
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+pub fn hdr_merge_images_with_report(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+) -> Result<(HDRImage, MergeReport), Error> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let start = std::time::Instant::now();
+
+    let estimate = merge(inputs, config, &|_| {})?;
+    let image = HDRImage::with_alpha(estimate.phi, estimate.alpha, config.premultiply_alpha);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let duration = Some(start.elapsed());
+    #[cfg(target_arch = "wasm32")]
+    let duration = None;
+
+    let report = MergeReport {
+        reference_index: inputs.reference_index(),
+        exposure_range: inputs
+            .scaling_factor_range(config)?
+            .unwrap_or_else(|| unreachable!("The image count was validated")),
+        saturated_fractions: estimate.saturated_fractions,
+        clipped_frames: estimate.clipped_frames,
+        duration,
+    };
+
+    #[cfg(feature = "tracing")]
+    if report.recommends_fusion() {
+        tracing::info!("The stack has a narrow exposure range, exposure fusion may look better");
+    }
+
+    Ok((image, report))
+}
+
+/// Validate the configuration, resize the inputs if configured, validate them, sort them if
+/// configured, and find the frames with extreme clipping, dropping them if configured.
+fn prepare_inputs(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+) -> Result<Vec<ClippedFrame>, Error> {
+    config.validate()?;
+
+    if let Some(filter) = config.resize_filter {
+        inputs.resize_to_smallest(filter)?;
+    }
+
+    validate_inputs(inputs)?;
+
+    let range = inputs.scaling_factor_range(config)?;
+    if let (Some(min_ratio), Some(range)) = (config.min_exposure_ratio, range) {
+        validate_exposure_spread(range, min_ratio)?;
+    }
+
+    if let Some(threshold) = config.hot_pixel_threshold {
+        for input in inputs.as_slice_mut() {
+            input.correct_hot_pixels(threshold);
+        }
+    }
+
+    if config.sort_by_exposure {
+        inputs.sort_by_exposure();
+    }
+
+    let clipped_frames = find_clipped_frames(inputs.as_slice(), config)?;
+    if config.extreme_frames == ExtremeFrameHandling::Drop && !clipped_frames.is_empty() {
+        if inputs.len() - clipped_frames.len() < 2 {
+            return Err(Error::InputError {
+                parameter_name: "extreme_frames".to_string(),
+                message: format!(
+                    "{} of {} frames have more than {} of their pixels clipped, leaving fewer than two frames to merge",
+                    clipped_frames.len(),
+                    inputs.len(),
+                    config.max_clipped_fraction
+                ),
+            });
+        }
+
+        let indices = clipped_frames
+            .iter()
+            .map(|frame| frame.index)
+            .collect::<Vec<usize>>();
+        inputs.remove_indices(&indices);
+    }
+
+    Ok(clipped_frames)
+}
+
+/// Validate the configuration and inputs, and run the poisson estimate.
+fn merge(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+    progress: &dyn Fn(f32),
+) -> Result<Estimate, Error> {
+    let clipped_frames = prepare_inputs(inputs, config)?;
+
+    merge_prepared(inputs, config, clipped_frames, progress)
+}
+
+/// Run the poisson estimate on inputs that went through [`prepare_inputs`], which found the
+/// given clipped frames.
+fn merge_prepared(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+    clipped_frames: Vec<ClippedFrame>,
+    progress: &dyn Fn(f32),
+) -> Result<Estimate, Error> {
+    let mut estimate = calculate_poisson_estimate(inputs.as_slice_mut(), config, progress)?;
+    estimate.clipped_frames = clipped_frames;
+    config.non_finite.apply(&mut estimate.phi)?;
+
+    if let Some(strength) = config.denoise_strength {
+        denoise::denoise_buffer(estimate.phi.view_mut(), strength);
+    }
+
+    Ok(estimate)
+}
+
+/// Same as [`hdr_merge_images_with_config`], but runs all parallel work on the given thread pool
+/// instead of rayon's global thread pool. This allows capping the number of threads used, or
+/// isolating the merge from other work in the host application.
+///
+/// # Errors
+/// - If image list is empty
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+#[cfg(feature = "parallel")]
+pub fn hdr_merge_images_in_pool(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+    pool: &rayon::ThreadPool,
+) -> Result<HDRImage, Error> {
+    pool.install(|| hdr_merge_images_with_config(inputs, config))
+}
 
-/// Given a set of file paths, attempt to HDR merge the images
-/// and produce a single [`DynamicImage`] (from image-rs crate).
+/// Same as [`hdr_merge_images_with_config`], but first aligns the images with each other using
+/// Median Threshold Bitmaps (see [`align`]). This compensates for small translations between
+/// hand held exposures.
+///
+/// returns: The merged image along with the shift that was applied to each input, in the same
+/// order as the inputs (after sorting, if [`MergeConfig::sort_by_exposure`] is enabled).
 ///
 /// # Errors
 /// - If image list is empty
-/// - If supplied image is not an RGB image. Non RGB images include images with alpha channel, grayscale images, and images with other color encodings (like CMYK).
 /// - If images are of different dimensions.
-pub fn hdr_merge_images(inputs: &mut HDRInputList) -> Result<DynamicImage, Error> {
-    if inputs.len() < 2 {
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+pub fn hdr_merge_images_aligned(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+) -> Result<(HDRImage, Vec<Shift>), Error> {
+    merge_aligned(inputs, config, &|_| {})
+}
+
+/// Prepare the inputs like [`merge`], align them with each other in place and run the poisson
+/// estimate on them. The inputs are only prepared once, so that e.g. hot pixels are not
+/// corrected twice.
+fn merge_aligned(
+    inputs: &mut HDRInputList,
+    config: &MergeConfig,
+    progress: &dyn Fn(f32),
+) -> Result<(HDRImage, Vec<Shift>), Error> {
+    let clipped_frames = prepare_inputs(inputs, config)?;
+
+    let shifts = align::compute_shifts(inputs, align::DEFAULT_MAX_SHIFT_BITS);
+    align::apply_shifts(inputs, &shifts)?;
+
+    let estimate = merge_prepared(inputs, config, clipped_frames, progress)?;
+
+    Ok((
+        HDRImage::with_alpha(estimate.phi, estimate.alpha, config.premultiply_alpha),
+        shifts,
+    ))
+}
+
+/// Given a set of file paths, attempt to read the images along with their exposure and gain from
+/// EXIF metadata, and HDR merge them into a single [`HDRImage`].
+///
+/// This is a shorthand for building a [`HDRInputList`] from the paths and calling
+/// [`hdr_merge_images`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use image::DynamicImage;
+/// use image_hdr::hdr_merge_paths;
+/// use image_hdr::stretch::apply_histogram_stretch;
+///
+/// let paths = ["image1.tif", "image2.tif", "image3.tif"];
+/// let merged = hdr_merge_paths(&paths)?;
+/// let stretched = apply_histogram_stretch(&DynamicImage::from(merged))?;
+///
+/// stretched.to_rgba16().save("hdr_merged.tiff")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// # Errors
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If the images don't all have the same channel layout, e.g. an RGB JPEG and a grayscale PNG,
+///   listing every image that differs from the first one
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn hdr_merge_paths<P: AsRef<Path> + Sync>(paths: &[P]) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let mut inputs = HDRInputList::try_from(paths)?;
+
+    hdr_merge_images(&mut inputs)
+}
+
+/// Given a set of file paths, merge them like [`hdr_merge_paths`] and tone map the result into a
+/// displayable 8-bit sRGB image, ready to be saved as PNG or JPEG. This uses the default
+/// [`tonemap::ToneMapOperator`], the global Reinhard operator with a key of
+/// [`tonemap::DEFAULT_REINHARD_KEY`]. Use [`merge_to_image_with_operator`] to pick another
+/// operator.
+///
+/// # Errors
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If the images don't all have the same channel layout, e.g. an RGB JPEG and a grayscale PNG,
+///   listing every image that differs from the first one
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn merge_to_image<P: AsRef<Path> + Sync>(paths: &[P]) -> Result<DynamicImage, Error> {
+    merge_to_image_with_operator(paths, ToneMapOperator::default())
+}
+
+/// Same as [`merge_to_image`], but tone maps with the given operator and parameters. See
+/// [`tonemap::tone_map`].
+///
+/// # Errors
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If the images don't all have the same channel layout, e.g. an RGB JPEG and a grayscale PNG,
+///   listing every image that differs from the first one
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the parameters of the operator are invalid
+pub fn merge_to_image_with_operator<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    operator: ToneMapOperator,
+) -> Result<DynamicImage, Error> {
+    let hdr = hdr_merge_paths(paths)?;
+
+    tonemap::tone_map(&hdr, operator, BitDepth::Eight)
+}
+
+/// Same as [`merge_to_image`], but tone maps with the given [`tonemap::ToneMapper`], which can be
+/// one of the built-in operators like [`tonemap::Reinhard`], or a custom one.
+///
+/// # Errors
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If the images don't all have the same channel layout, e.g. an RGB JPEG and a grayscale PNG,
+///   listing every image that differs from the first one
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn merge_and_tonemap<P: AsRef<Path> + Sync, M: tonemap::ToneMapper + ?Sized>(
+    paths: &[P],
+    mapper: &M,
+) -> Result<DynamicImage, Error> {
+    let hdr = hdr_merge_paths(paths)?;
+
+    Ok(mapper.map(&hdr))
+}
+
+/// Same as [`hdr_merge_paths`], but downscales each frame right after reading it so that its
+/// longest side is at most `max_dimension` pixels, which makes for a fast low resolution preview
+/// of the full merge. Exposures and gains apply to whole frames, so the radiance math is
+/// unchanged and the radiance values are preserved, up to the averaging done by resampling.
+/// Frames that are already small enough are left untouched.
+///
+/// # Errors
+/// - If `max_dimension` is zero
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+///
+/// To preview a stack and then merge it in full without decoding it twice, read it into a
+/// [`HDRInputList`] and use [`HDRInputList::downscaled`] instead.
+pub fn hdr_merge_preview<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    max_dimension: u32,
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+    input::validate_max_dimension(max_dimension)?;
+
+    let inputs = input::read_each(
+        paths,
+        |path| Some(path.as_ref()),
+        |path, _| {
+            let mut input = HDRInput::new(path)?;
+            input.downscale(max_dimension)?;
+
+            Ok(input)
+        },
+    )?;
+
+    hdr_merge_images(&mut inputs.into())
+}
+
+/// Same as [`hdr_merge_paths`], but allows overriding the exposures and/or gains that would
+/// otherwise be read from EXIF metadata. This is useful for files with missing or wrong EXIF data.
+///
+/// # Errors
+/// - If overrides are provided but their length doesn't match the number of paths
+/// - If any of the images cannot be read, or doesn't contain EXIF metadata for a value that isn't
+///   overridden.
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn hdr_merge_paths_with_overrides<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    exposures: Option<&[Duration]>,
+    gains: Option<&[f32]>,
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let mut inputs = HDRInputList::with_overrides(paths, exposures, gains)?;
+
+    hdr_merge_images(&mut inputs)
+}
+
+/// Given a set of file paths along with the [`ExifData`] of each file, attempt to HDR merge the
+/// images into a single [`HDRImage`]. This allows inspecting and correcting the values detected
+/// with [`exif::read_exif_data`] before merging, or caching them across repeated merges of the
+/// same stack, see [`ExifData`]. See [`HDRInputList::with_exif_data`].
+///
+/// # Errors
+/// - If `exif_data` doesn't have one entry per path
+/// - If any of the images cannot be read, or doesn't contain EXIF metadata for a value that is
+///   `None`, listing every failure
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn hdr_merge_paths_with_exif_data<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    exif_data: &[ExifData],
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let mut inputs = HDRInputList::with_exif_data(paths, exif_data)?;
+
+    hdr_merge_images(&mut inputs)
+}
+
+/// Same as [`hdr_merge_paths`], but reads the exposure time of files without it in their EXIF
+/// metadata from their file name with the given pattern, e.g.
+/// [`filename::ExposurePattern::default`]. See [`HDRInputList::with_exposure_pattern`].
+///
+/// # Errors
+/// - If any of the images cannot be read, or has neither an exposure time in its EXIF metadata
+///   nor a file name matching the pattern, listing every failure
+/// - If the images don't all have the same channel layout, listing every image that differs from
+///   the first one
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn hdr_merge_paths_with_exposure_pattern<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    pattern: &ExposurePattern,
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let mut inputs = HDRInputList::with_exposure_pattern(paths, pattern)?;
+
+    hdr_merge_images(&mut inputs)
+}
+
+/// Same as [`hdr_merge_paths`], but takes the exposure and gain of every file from a
+/// [`Sidecar`] instead of EXIF metadata, matching its entries to the file names of the paths.
+///
+/// # Errors
+/// - If any of the paths has no entry in the sidecar, listing every such path
+/// - If any of the images cannot be read, listing every failure
+/// - If the images don't all have the same channel layout, listing every image that differs from
+///   the first one
+/// - If fewer than two paths are provided
+/// - If images are of different dimensions.
+pub fn hdr_merge_paths_with_sidecar<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    sidecar: &Sidecar,
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let (exposures, gains) = sidecar.exposures_and_gains(paths)?;
+    let mut inputs = HDRInputList::with_overrides(paths, Some(&exposures), Some(&gains))?;
+
+    hdr_merge_images(&mut inputs)
+}
+
+/// Merge the images of a directory with the given [`MergeConfig`], reading their exposure and
+/// gain from EXIF metadata like [`hdr_merge_paths`]. Only the files directly in the directory
+/// whose extension is that of a supported image format are merged, ignoring case, in the order
+/// of their paths. Other files, hidden files and subdirectories are skipped.
+///
+/// # Errors
+/// - If the directory cannot be read
+/// - If the directory holds fewer than two images
+/// - If any of the images cannot be read, or doesn't contain EXIF metadata for exposure and
+///   gain, listing every failure
+/// - If images are of different dimensions.
+/// - If the configuration is invalid
+pub fn merge_hdr_dir(dir: impl AsRef<Path>, config: &MergeConfig) -> Result<HDRImage, Error> {
+    let paths = io::image_paths_in_dir(dir.as_ref())?;
+
+    merge_stack(&paths, config)
+}
+
+/// Merge several stacks with the same [`MergeConfig`], one stack after another. Each stack is a
+/// set of file paths, read and merged like [`hdr_merge_paths`], and the merge of each stack uses
+/// the whole thread pool. Only one stack is held in memory at a time.
+///
+/// A failure only affects its own stack, so the result holds one entry per stack, in the same
+/// order. See [`merge_stacks_concurrently`] to merge several stacks at the same time.
+#[must_use]
+pub fn merge_stacks<P: AsRef<Path> + Sync>(
+    stacks: &[Vec<P>],
+    config: &MergeConfig,
+) -> Vec<Result<HDRImage, Error>> {
+    stacks
+        .iter()
+        .map(|paths| merge_stack(paths, config))
+        .collect()
+}
+
+/// Same as [`merge_stacks`], but merges up to `concurrent_stacks` stacks at the same time. Every
+/// stack still runs on the same rayon thread pool, so this doesn't start more threads. It only
+/// helps keep the pool busy when the individual stacks are too small to use every thread, e.g.
+/// while files are read and decoded.
+///
+/// The next stack is started as soon as one finishes, so a slow stack doesn't hold back the
+/// others, but no more than `concurrent_stacks` stacks are ever read or merged at once. Memory
+/// usage is bounded accordingly, which makes this suitable for merging hundreds of stacks on a
+/// server. The results are in the order of `stacks`, regardless of the order stacks finish in.
+///
+/// # Errors
+/// - If `concurrent_stacks` is zero
+pub fn merge_stacks_concurrently<P: AsRef<Path> + Sync>(
+    stacks: &[Vec<P>],
+    config: &MergeConfig,
+    concurrent_stacks: usize,
+) -> Result<Vec<Result<HDRImage, Error>>, Error> {
+    if concurrent_stacks == 0 {
         return Err(Error::InputError {
-            parameter_name: "paths".to_string(),
-            message: "At least two images must be provided".to_string(),
+            parameter_name: "concurrent_stacks".to_string(),
+            message: "At least one stack must be merged at a time".to_string(),
+        });
+    }
+
+    // Each worker merges one stack at a time, taking the next stack that hasn't been started.
+    let next = AtomicUsize::new(0);
+    let mut results = (0..concurrent_stacks.min(stacks.len()))
+        .into_par_iter()
+        .with_max_len(1)
+        .flat_map_iter(|_| {
+            std::iter::from_fn(|| {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+
+                stacks
+                    .get(index)
+                    .map(|paths| (index, merge_stack(paths, config)))
+            })
+        })
+        .collect::<Vec<(usize, Result<HDRImage, Error>)>>();
+
+    results.sort_by_key(|(index, _)| *index);
+
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+/// Read and merge a single stack of [`merge_stacks`].
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "info", skip_all, fields(frames = paths.len()))
+)]
+fn merge_stack<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    config: &MergeConfig,
+) -> Result<HDRImage, Error> {
+    validate_image_count(paths.len())?;
+
+    let mut inputs = HDRInputList::try_from(paths)?;
+
+    hdr_merge_images_with_config(&mut inputs, config)
+}
+
+/// Given a set of encoded images along with their exposures and gains, decode them in memory
+/// and HDR merge them into a single [`HDRImage`]. The format of each image is guessed from its
+/// content. This doesn't touch the filesystem or EXIF metadata, which makes it usable where
+/// there is no filesystem, e.g. in the browser.
+///
+/// # Errors
+/// - If any of the images cannot be decoded, listing every failure
+/// - If `images`, `exposures` and `gains` are not of the same length
+/// - If fewer than two images are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn hdr_merge_encoded_images(
+    images: &[&[u8]],
+    exposures: &[Duration],
+    gains: &[f32],
+) -> Result<HDRImage, Error> {
+    let images = input::read_each(images, |_| None, |data, _| io::read_image(data, None))?;
+
+    hdr_merge_dynamic_images(&images, exposures, gains)
+}
+
+/// Given a set of already decoded images along with their exposures and gains, HDR merge them
+/// into a single [`HDRImage`]. Unlike [`hdr_merge_paths`], this doesn't touch the filesystem
+/// or EXIF metadata.
+///
+/// # Errors
+/// - If `images`, `exposures` and `gains` are not of the same length
+/// - If any of the exposures or gains is invalid
+/// - If fewer than two images are provided
+/// - If images are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+pub fn hdr_merge_dynamic_images(
+    images: &[DynamicImage],
+    exposures: &[Duration],
+    gains: &[f32],
+) -> Result<HDRImage, Error> {
+    let mut inputs = HDRInputList::with_images(images, exposures, gains)?;
+
+    hdr_merge_images(&mut inputs)
+}
+
+/// Given frames decoded outside of this crate, see [`frame`], HDR merge them into a single
+/// [`HDRImage`] with the given configuration. Frames are taken from the iterator one after
+/// another, and the merge starts once the iterator is exhausted.
+///
+/// # Errors
+/// - If any of the frames fails or cannot be converted, listing every failure with the index of
+///   the frame
+/// - If fewer than two frames are provided
+/// - If frames are of different dimensions.
+/// - If an exposure or gain is not a finite positive number.
+/// - If the configuration is invalid
+pub fn hdr_merge_frames(
+    frames: impl IntoIterator<Item = Result<Frame, Error>>,
+    config: &MergeConfig,
+) -> Result<HDRImage, Error> {
+    let mut inputs = Vec::new();
+    let mut failures = Vec::new();
+
+    for (index, frame) in frames.into_iter().enumerate() {
+        match frame.and_then(HDRInput::try_from) {
+            Ok(input) => inputs.push(input),
+            Err(error) => failures.push(ReadFailure {
+                index,
+                path: None,
+                error,
+            }),
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(Error::ReadErrors(failures));
+    }
+
+    hdr_merge_images_with_config(&mut HDRInputList::from(inputs), config)
+}
+
+/// HDR merge already decoded RGB frames with the default [`MergeConfig`], returning the merged
+/// radiance. This is the lowest level entry point of the crate: it doesn't decode, read files or
+/// EXIF metadata, and only converts the frames to radiance and accumulates them, which makes it
+/// suitable for FFI and custom decoders. See [`hdr_merge_frames`] for frames with other channel
+/// counts or a custom configuration.
+///
+/// # Arguments
+///
+/// * `frames`: Normalised and linear pixel values of each frame, with `1.0` being the largest value
+///   the sensor can record, stored row-major with three interleaved RGB channels. Each slice must
+///   hold exactly `width * height * 3` values. The slices are copied, and left untouched.
+/// * `width`: Width of the frames in pixels
+/// * `height`: Height of the frames in pixels
+/// * `exposures`: Exposure time of each frame
+/// * `gains`: Gain of each frame
+///
+/// returns: `Result<Vec<f32>, Error>` with `width * height * 3` interleaved RGB radiance values,
+/// laid out like the frames
+///
+/// # Errors
+/// - If `frames`, `exposures` and `gains` are not of the same length
+/// - If any of the frames doesn't hold `width * height * 3` values, listing every such frame
+/// - If fewer than two frames are provided
+/// - If an exposure or gain is not a finite positive number.
+pub fn merge_radiance(
+    frames: &[&[f32]],
+    width: u32,
+    height: u32,
+    exposures: &[Duration],
+    gains: &[f32],
+) -> Result<Vec<f32>, Error> {
+    if exposures.len() != frames.len() || gains.len() != frames.len() {
+        return Err(Error::InputError {
+            parameter_name: "exposures".to_string(),
+            message: format!(
+                "Expected one exposure and gain per frame, got {} frames, {} exposures and {} gains",
+                frames.len(),
+                exposures.len(),
+                gains.len()
+            ),
+        });
+    }
+
+    let frames = frames
+        .iter()
+        .zip(exposures)
+        .zip(gains)
+        .map(|((pixels, exposure), gain)| {
+            Ok(Frame::rgb(pixels.to_vec(), width, height, *exposure, *gain))
+        });
+
+    hdr_merge_frames(frames, &MergeConfig::default()).map(HDRImage::into_vec)
+}
+
+/// Read, convert and accumulate the images at the given paths one at a time, so that only a
+/// single decoded frame is held in memory. See [`builder::MergeBuilder::low_memory`]. Files that
+/// cannot be read are skipped when `skip_unreadable` is set, and reported together otherwise.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "info", skip_all, fields(frames = paths.len()))
+)]
+fn merge_paths_low_memory<P: AsRef<Path>>(
+    paths: &[P],
+    exposures: Option<&[Duration]>,
+    gains: Option<&[f32]>,
+    ev_offsets: Option<&[f32]>,
+    config: &MergeConfig,
+    skip_unreadable: bool,
+    progress: &dyn Fn(f32),
+) -> Result<HDRImage, Error> {
+    let mut accumulator = stream::Accumulator::new(config)?;
+    validate_image_count(paths.len())?;
+
+    if let Some(exposures) = exposures {
+        input::validate_override_length("exposures", exposures.len(), paths.len())?;
+    }
+
+    if let Some(gains) = gains {
+        input::validate_override_length("gains", gains.len(), paths.len())?;
+    }
+
+    if let Some(ev_offsets) = ev_offsets {
+        input::validate_override_length("ev_offsets", ev_offsets.len(), paths.len())?;
+    }
+
+    let mut failures = Vec::new();
+
+    for (index, path) in paths.iter().enumerate() {
+        let input = HDRInput::with_overrides(
+            path,
+            exposures.map(|exposures| exposures[index]),
+            gains.map(|gains| gains[index]),
+        );
+
+        match input {
+            Ok(mut input) if failures.is_empty() || skip_unreadable => {
+                if let Some(ev_offsets) = ev_offsets {
+                    input
+                        .compensate_exposure(ev_offsets[index])
+                        .map_err(|error| input::with_index(index, error))?;
+                }
+
+                accumulator.push_at(index, input, Some(path.as_ref()))?;
+            }
+            // Keep reading after a failure to report every unreadable file, but stop accumulating.
+            Ok(_) => {}
+            Err(error) => {
+                let failure = ReadFailure {
+                    index,
+                    path: Some(path.as_ref().to_path_buf()),
+                    error,
+                };
+
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%failure, "Unable to read image");
+
+                failures.push(failure);
+            }
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        progress((index + 1) as f32 / paths.len() as f32);
+    }
+
+    if !failures.is_empty() && (!skip_unreadable || accumulator.len() < 2) {
+        return Err(Error::ReadErrors(failures));
+    }
+
+    accumulator.finalize()
+}
+
+/// Given a list of inputs, convert every frame to radiance on its own, without merging them.
+/// These are the per-frame values that the merge averages, which is useful to inspect the
+/// scaling of each frame, e.g. to verify the exposures and gains read from EXIF metadata. Only
+/// [`MergeConfig::coefficients`] and [`MergeConfig::vignetting`] affect the result. The inputs
+/// are left untouched.
+///
+/// returns: One radiance map per input, in the same order as the inputs.
+///
+/// # Errors
+/// - If the coefficients don't apply to the number of channels of the images
+/// - If the configuration is invalid
+pub fn compute_input_radiances(
+    inputs: &HDRInputList,
+    config: &MergeConfig,
+) -> Result<Vec<HDRImage>, Error> {
+    config.validate()?;
+
+    inputs
+        .as_slice()
+        .iter()
+        .map(|input| poisson::calculate_radiance(input, config).map(HDRImage::from_nd_array_buffer))
+        .collect()
+}
+
+/// Given a set of file paths, attempt to read the images along with their exposure and gain from
+/// EXIF metadata, and convert every frame to radiance without merging them. See
+/// [`compute_input_radiances`].
+///
+/// # Errors
+/// - If any of the images cannot be read or doesn't contain EXIF metadata for exposure and/or gain.
+/// - If the coefficients don't apply to the number of channels of the images
+pub fn compute_radiances<P: AsRef<Path> + Sync>(paths: &[P]) -> Result<Vec<HDRImage>, Error> {
+    let inputs = HDRInputList::try_from(paths)?;
+
+    compute_input_radiances(&inputs, &MergeConfig::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Noise;
+    use ndarray::Array3;
+
+    /// 8x8 RGB frame of a scene whose radiance varies across the frame, taken with the given
+    /// exposure in seconds and a gain of `1.0`. Pixel values are clipped at `1.0`.
+    #[allow(clippy::cast_precision_loss)]
+    fn frame(exposure: f32) -> HDRInput {
+        let buffer = Array3::from_shape_fn((8, 8, 3), |(y, x, channel)| {
+            let radiance = 0.05 + 0.2 * (y * 8 + x) as f32 / 64. + 0.05 * channel as f32;
+
+            (radiance * exposure).min(1.)
         });
+
+        HDRInput::with_buffer(buffer, Duration::from_secs_f32(exposure), 1.)
+            .expect("The frame is valid")
+    }
+
+    fn stack(exposures: &[f32]) -> HDRInputList {
+        HDRInputList::from(
+            exposures
+                .iter()
+                .map(|exposure| frame(*exposure))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn shuffled_inputs_merge_like_sorted_ones_when_sorting() {
+        let config = MergeConfig {
+            sort_by_exposure: true,
+            ..MergeConfig::default()
+        };
+
+        let sorted = hdr_merge_images_with_config(&mut stack(&[0.5, 1., 2., 4.]), &config)
+            .expect("The stack is valid");
+        let mut shuffled = stack(&[2., 0.5, 4., 1.]);
+        let merged =
+            hdr_merge_images_with_config(&mut shuffled, &config).expect("The stack is valid");
+
+        assert_eq!(merged.get_buffer(), sorted.get_buffer());
+
+        let exposures = shuffled
+            .as_slice()
+            .iter()
+            .map(HDRInput::get_exposure)
+            .collect::<Vec<f32>>();
+        assert_eq!(exposures, [0.5, 1., 2., 4.]);
+    }
+
+    #[test]
+    fn reference_frame_has_the_median_exposure() {
+        assert_eq!(stack(&[2., 0.5, 1.]).reference_index(), 2);
+        // The longer of the two middle exposures for an even number of frames
+        assert_eq!(stack(&[2., 0.5, 4., 1.]).reference_index(), 0);
+    }
+
+    /// 16x16 RGB frame of noise, whose values are spread enough for the hot pixel correction to
+    /// replace different values when it runs a second time.
+    fn noise_frame() -> HDRInput {
+        let mut noise = Noise::new(0x2545_f491);
+        let buffer = Array3::from_shape_simple_fn((16, 16, 3), || 0.1 + 0.8 * noise.next());
+
+        HDRInput::with_buffer(buffer, Duration::from_secs(1), 1.).expect("The frame is valid")
+    }
+
+    #[test]
+    fn aligned_merge_prepares_the_inputs_once() {
+        let threshold = 0.3;
+        let config = MergeConfig {
+            hot_pixel_threshold: Some(threshold),
+            ..MergeConfig::default()
+        };
+
+        // Correcting twice must differ from correcting once for the test to be meaningful.
+        let mut frame = noise_frame();
+        frame.correct_hot_pixels(threshold);
+        assert!(frame.correct_hot_pixels(threshold) > 0);
+
+        let mut inputs = HDRInputList::from(vec![noise_frame(), noise_frame()]);
+        let (aligned, shifts) =
+            hdr_merge_images_aligned(&mut inputs, &config).expect("The stack is valid");
+        assert!(shifts.iter().all(|shift| *shift == Shift::default()));
+
+        let mut inputs = HDRInputList::from(vec![noise_frame(), noise_frame()]);
+        let merged =
+            hdr_merge_images_with_config(&mut inputs, &config).expect("The stack is valid");
+
+        assert_eq!(aligned.get_buffer(), merged.get_buffer());
+    }
+
+    /// 8x8 RGB frame of a scene with a constant radiance of `4.0` relative to an f/1 aperture,
+    /// taken with an exposure of 0.1s and a gain of `1.0` at the given f-number.
+    fn frame_at_aperture(aperture: f32) -> HDRInput {
+        let value = 4. * 0.1 / (aperture * aperture);
+
+        HDRInput::with_buffer(
+            Array3::from_elem((8, 8, 3), value),
+            Duration::from_millis(100),
+            1.,
+        )
+        .and_then(|input| input.with_aperture(aperture))
+        .expect("The frame is valid")
+    }
+
+    fn aperture_bracket() -> HDRInputList {
+        HDRInputList::from(
+            [1.4, 2., 2.8]
+                .into_iter()
+                .map(frame_at_aperture)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn aperture_bracket_merges_to_a_constant_radiance() {
+        let config = MergeConfig {
+            exposure_model: config::ExposureModel::TimeGainAndAperture,
+            ..MergeConfig::default()
+        };
+        let merged = hdr_merge_images_with_config(&mut aperture_bracket(), &config)
+            .expect("Every frame has an aperture");
+
+        for value in merged.get_buffer() {
+            assert!((value - 4.).abs() < 4. * 1e-5, "{value}");
+        }
+    }
+
+    #[test]
+    fn aperture_bracket_is_inconsistent_without_the_aperture_in_the_exposure() {
+        let merged = hdr_merge_images_with_config(&mut aperture_bracket(), &MergeConfig::default())
+            .expect("The stack is valid");
+
+        // The frames read as radiances of about 2, 1 and 0.5 when the aperture is ignored.
+        let value = merged.get_buffer()[[0, 0, 0]];
+        assert!(value > 0.5 && value < 2., "{value}");
+    }
+
+    #[test]
+    fn aperture_exposure_model_needs_the_aperture_of_every_frame() {
+        let mut inputs = aperture_bracket();
+        inputs.as_slice_mut()[1] = frame(0.1);
+        let config = MergeConfig {
+            exposure_model: config::ExposureModel::TimeGainAndAperture,
+            ..MergeConfig::default()
+        };
+
+        let error = hdr_merge_images_with_config(&mut inputs, &config)
+            .expect_err("The second frame has no aperture");
+
+        let Error::ReadErrors(failures) = error else {
+            panic!("Expected the failure of the frame, got {error:?}");
+        };
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].index, 1);
+        assert!(matches!(
+            &failures[0].error,
+            Error::InputError { parameter_name, .. } if parameter_name == "aperture"
+        ));
+    }
+
+    /// Frames of a dark, flat scene with a radiance of `0.05`, taken with exposures of 1s, 4s
+    /// and 16s at unity gain. Each pixel counts photons with Poisson noise, `photon_scale` being
+    /// the normalised value of a single photon, and adds Gaussian read noise.
+    #[allow(clippy::cast_possible_truncation)]
+    fn poisson_noised_stack(photon_scale: f32, read_noise: f32) -> HDRInputList {
+        use rand::SeedableRng;
+        use rand_distr::{Distribution, Normal, Poisson};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(94);
+        let read_noise = Normal::new(0., f64::from(read_noise)).expect("The deviation is valid");
+
+        HDRInputList::from(
+            [1_f32, 4., 16.]
+                .into_iter()
+                .map(|exposure| {
+                    let photons = Poisson::new(f64::from(0.05 * exposure / photon_scale))
+                        .expect("The mean is positive");
+                    let buffer = Array3::from_shape_simple_fn((32, 32, 1), || {
+                        let value = photons.sample(&mut rng) * f64::from(photon_scale)
+                            + read_noise.sample(&mut rng);
+
+                        value as f32
+                    });
+
+                    HDRInput::with_buffer(buffer, Duration::from_secs_f32(exposure), 1.)
+                        .expect("The frame is valid")
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn variance<'a>(values: impl ExactSizeIterator<Item = &'a f32> + Clone) -> f32 {
+        let count = values.len() as f32;
+        let mean = values.clone().sum::<f32>() / count;
+
+        values.map(|value| (value - mean).powi(2)).sum::<f32>() / count
+    }
+
+    #[test]
+    fn noise_model_merges_shadows_with_less_variance_than_the_default_weights() {
+        // Read noise dominates the photon noise of the shortest exposures.
+        let (photon_scale, read_noise) = (1e-4, 0.02);
+        let inputs = poisson_noised_stack(photon_scale, read_noise);
+        let default = MergeConfig {
+            // Every sample of the dark scene counts, rather than only the ones above the floor.
+            noise_floor: 0.,
+            ..MergeConfig::default()
+        };
+        let config = MergeConfig {
+            noise_model: config::NoiseModel::new(read_noise, photon_scale)
+                .expect("The noise model is valid"),
+            ..default.clone()
+        };
+
+        let merged =
+            hdr_merge_images_with_config(&mut inputs.clone(), &config).expect("The stack is valid");
+        let weighted_by_exposure = hdr_merge_images_with_config(&mut inputs.clone(), &default)
+            .expect("The stack is valid");
+
+        let noise_model_variance = variance(merged.get_buffer().iter());
+        let default_variance = variance(weighted_by_exposure.get_buffer().iter());
+        assert!(
+            noise_model_variance < default_variance / 1.3,
+            "{noise_model_variance} vs {default_variance}"
+        );
+
+        // Both estimate the same radiance.
+        for image in [&merged, &weighted_by_exposure] {
+            let mean = image.get_buffer().mean().expect("The frames have pixels");
+            assert!((mean - 0.05).abs() < 0.05 * 0.05, "{mean}");
+        }
     }
 
-    let phi = calculate_poisson_estimate(inputs.as_slice_mut());
+    #[test]
+    fn report_indices_refer_to_the_merged_list_except_for_dropped_frames() {
+        let config = MergeConfig {
+            sort_by_exposure: true,
+            extreme_frames: ExtremeFrameHandling::Drop,
+            ..MergeConfig::default()
+        };
+        // The 10ms frame is below the noise floor everywhere, and comes first once sorted.
+        let mut inputs = stack(&[2., 0.01, 4., 1.]);
+
+        let (_, report) =
+            hdr_merge_images_with_report(&mut inputs, &config).expect("Three frames are left");
 
-    Ok(DynamicImage::from_nd_array_buffer(phi))
+        assert_eq!(report.clipped_frames.len(), 1);
+        assert_eq!(report.clipped_frames[0].index, 0);
+
+        let exposures = inputs
+            .as_slice()
+            .iter()
+            .map(HDRInput::get_exposure)
+            .collect::<Vec<f32>>();
+        assert_eq!(exposures, [1., 2., 4.]);
+        assert_eq!(report.reference_index, 1);
+        assert_eq!(report.saturated_fractions.len(), 3);
+        assert_eq!(report.exposure_range, (1., 4.));
+    }
 }
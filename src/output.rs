@@ -0,0 +1,583 @@
+//! Output type produced by the HDR merge
+
+use crate::extensions::NDArrayBuffer;
+use crate::parallel::prelude::*;
+use crate::tonemap::luminance;
+use crate::Error;
+use image::{DynamicImage, ImageBuffer, Rgba};
+use ndarray::{s, Array2, Array3, ArrayViewMut3, Axis, Zip};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Magic bytes at the start of files written with [`HDRImage::write_raw`].
+const RAW_MAGIC: &[u8] = b"IHDRRAW\0";
+
+/// Version of the format written by [`HDRImage::write_raw`], incremented whenever the layout of
+/// the file changes.
+const RAW_VERSION: u32 = 1;
+
+/// Number of `u32` fields in the header of [`HDRImage::write_raw`] after the magic bytes.
+const RAW_HEADER_FIELDS: usize = 5;
+
+/// Linear radiance estimate produced by merging a set of [`crate::input::HDRInput`].
+///
+/// Pixel data is stored row-major with interleaved channels, i.e. the value for channel `c` of
+/// the pixel at `(x, y)` lives at index `(y * width + x) * channels + c`. When the image has an
+/// alpha channel, see [`HDRImage::has_alpha`], it is the last channel.
+#[derive(Clone, Debug)]
+pub struct HDRImage {
+    buffer: Array3<f32>,
+    has_alpha: bool,
+}
+
+impl HDRImage {
+    /// Get width of the image in pixels
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn get_width(&self) -> u32 {
+        self.buffer.dim().1 as u32
+    }
+
+    /// Get height of the image in pixels
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn get_height(&self) -> u32 {
+        self.buffer.dim().0 as u32
+    }
+
+    /// Get number of channels per pixel. This is `3` for RGB images, `1` for grayscale images and
+    /// the number of channels of the stack for inputs created with
+    /// [`crate::input::HDRInput::with_buffer`], plus one if the image has an alpha channel.
+    #[must_use]
+    pub fn get_channels(&self) -> usize {
+        self.buffer.dim().2
+    }
+
+    /// Whether the last channel of the image is the alpha channel combined from the stack, see
+    /// [`crate::config::MergeConfig::alpha`]. Alpha ranges from `0.0` for fully transparent
+    /// pixels to `1.0` for opaque ones, and is left untouched by [`HDRImage::normalize`] and
+    /// [`HDRImage::normalize_percentile`]. Tone mapping drops it.
+    #[must_use]
+    pub fn has_alpha(&self) -> bool {
+        self.has_alpha
+    }
+
+    /// Get underlying radiance data as a `(height, width, channels)` nd-array
+    #[must_use]
+    pub fn get_buffer(&self) -> &Array3<f32> {
+        &self.buffer
+    }
+
+    /// Get a mutable view into the underlying radiance data
+    #[must_use]
+    pub fn get_buffer_mut(&mut self) -> ArrayViewMut3<'_, f32> {
+        self.buffer.view_mut()
+    }
+
+    /// Get radiance data as a flat slice of interleaved channel values.
+    #[must_use]
+    pub fn as_slice(&self) -> &[f32] {
+        self.buffer
+            .as_slice()
+            .unwrap_or_else(|| unreachable!("HDRImage buffer is always in standard layout"))
+    }
+
+    /// Get radiance data as a flat vec of interleaved channel values.
+    #[must_use]
+    pub fn into_vec(self) -> Vec<f32> {
+        self.buffer.into_raw_vec_and_offset().0
+    }
+
+    /// Write the image to a file in a minimal binary format, for caching merge results between
+    /// sessions without the cost of encoding a full image format. The file holds a header, with
+    /// the magic bytes `IHDRRAW\0`, the format version, the width, height and channel count and a
+    /// flag for the alpha channel as little-endian `u32`s, followed by the radiance as
+    /// little-endian `f32`s in the layout of [`HDRImage::as_slice`]. Use
+    /// [`HDRImage::read_raw`] to read it back bit for bit, including non-finite values.
+    ///
+    /// The format is meant for scratch data written and read by this crate, not for exchanging
+    /// images with other software, see [`crate::export`] for that.
+    ///
+    /// # Errors
+    ///
+    /// - If the file cannot be created or written to
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn write_raw(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(RAW_MAGIC)?;
+        for value in [
+            RAW_VERSION,
+            self.get_width(),
+            self.get_height(),
+            self.get_channels() as u32,
+            u32::from(self.has_alpha),
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        for value in self.as_slice() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Read an image written with [`HDRImage::write_raw`].
+    ///
+    /// # Errors
+    ///
+    /// - If the file cannot be read
+    /// - If the file is not in the format of [`HDRImage::write_raw`], was written by an
+    ///   incompatible version, or is truncated
+    pub fn read_raw(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let data = std::fs::read(path)?;
+        let invalid = |message: String| Error::InputError {
+            parameter_name: "path".to_string(),
+            message,
+        };
+
+        let Some(header) = data.strip_prefix(RAW_MAGIC) else {
+            return Err(invalid("File is not a raw HDR image".to_string()));
+        };
+        if header.len() < RAW_HEADER_FIELDS * 4 {
+            return Err(invalid("Raw HDR image header is truncated".to_string()));
+        }
+
+        let (header, values) = header.split_at(RAW_HEADER_FIELDS * 4);
+        let field = |index: usize| {
+            u32::from_le_bytes(
+                header[index * 4..index * 4 + 4]
+                    .try_into()
+                    .unwrap_or_else(|_| unreachable!("Header fields are 4 bytes long")),
+            )
+        };
+
+        let version = field(0);
+        if version != RAW_VERSION {
+            return Err(invalid(format!(
+                "Raw HDR image has version {version}, only version {RAW_VERSION} is supported"
+            )));
+        }
+
+        let (width, height, channels) = (field(1) as usize, field(2) as usize, field(3) as usize);
+        let has_alpha = field(4) != 0;
+        let expected = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .and_then(|length| length.checked_mul(4));
+        if expected != Some(values.len()) || (has_alpha && channels < 2) {
+            return Err(invalid(format!(
+                "Raw HDR image of {width}x{height} with {channels} channel(s) doesn't match its {} bytes of data",
+                values.len()
+            )));
+        }
+
+        let values = values
+            .chunks_exact(4)
+            .map(|bytes| {
+                f32::from_le_bytes(
+                    bytes
+                        .try_into()
+                        .unwrap_or_else(|_| unreachable!("Chunks are 4 bytes long")),
+                )
+            })
+            .collect::<Vec<f32>>();
+
+        Ok(Self {
+            buffer: Array3::from_shape_vec((height, width, channels), values)
+                .unwrap_or_else(|_| unreachable!("The length was validated")),
+            has_alpha,
+        })
+    }
+
+    /// Count the pixels with at least one infinite or NaN channel value. Tone mapping and most
+    /// image formats don't handle such values, see [`crate::config::MergeConfig::non_finite`].
+    #[must_use]
+    pub fn count_non_finite(&self) -> usize {
+        count_non_finite(&self.buffer)
+    }
+
+    /// Build a histogram of the log luminance of the image, e.g. to draw it in a tone mapping user
+    /// interface or to pick an exposure. Radiance spans many orders of magnitude, so the `bins`
+    /// equally wide bins span the natural log of luminance, from the darkest to the brightest
+    /// pixel with positive luminance. Luminance uses Rec. 709 primaries, and is the value itself
+    /// for grayscale images.
+    ///
+    /// Pixels with non-finite luminance are skipped. Pixels with zero or negative luminance are
+    /// counted in [`LuminanceHistogram::black_count`] instead of a bin.
+    ///
+    /// # Errors
+    ///
+    /// - If `bins` is zero
+    pub fn luminance_histogram(&self, bins: usize) -> Result<LuminanceHistogram, Error> {
+        if bins == 0 {
+            return Err(Error::InputError {
+                parameter_name: "bins".to_string(),
+                message: "Histogram needs at least one bin".to_string(),
+            });
+        }
+
+        Ok(LuminanceHistogram::new(&self.buffer, bins))
+    }
+
+    /// Scale the image so that its maximum finite value maps to `1.0`. Non-finite values are
+    /// ignored while looking for the maximum. Images without any positive finite value are left
+    /// untouched.
+    ///
+    /// returns: The scale factor that was applied. Dividing by it restores the original values.
+    pub fn normalize(&mut self) -> f32 {
+        let max = self
+            .color_channels_mut()
+            .iter()
+            .copied()
+            .filter(|value| value.is_finite())
+            .fold(0_f32, f32::max);
+
+        self.scale_by_reference(max)
+    }
+
+    /// Scale the image so that the value at the given percentile maps to `1.0`, and clip values
+    /// above it to `1.0`. This prevents a handful of very bright pixels from darkening the whole
+    /// image. Non-finite values are ignored while computing the percentile. Images without any
+    /// positive finite value are left untouched.
+    ///
+    /// Because of the clipping, the transform is only invertible for values at or below the
+    /// percentile.
+    ///
+    /// # Arguments
+    ///
+    /// * `percentile`: Percentile, between `0.0` (exclusive) and `100.0` (inclusive), e.g. `99.0`
+    ///
+    /// returns: `Result<f32, Error>` with the scale factor that was applied. Dividing by it
+    /// restores the original values that were not clipped.
+    ///
+    /// # Errors
+    ///
+    /// - If percentile is not in `(0, 100]`
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_sign_loss)]
+    pub fn normalize_percentile(&mut self, percentile: f32) -> Result<f32, Error> {
+        if percentile.is_nan() || percentile <= 0. || percentile > 100. {
+            return Err(Error::InputError {
+                parameter_name: "percentile".to_string(),
+                message: format!("Percentile must be in (0, 100], got {percentile}"),
+            });
+        }
+
+        let mut values = self
+            .color_channels_mut()
+            .iter()
+            .copied()
+            .filter(|value| value.is_finite())
+            .collect::<Vec<f32>>();
+
+        if values.is_empty() {
+            return Ok(1.);
+        }
+
+        let index = ((percentile / 100. * (values.len() - 1) as f32).round() as usize)
+            .min(values.len() - 1);
+        let reference = *values.select_nth_unstable_by(index, f32::total_cmp).1;
+
+        if reference <= 0. {
+            return Ok(1.);
+        }
+
+        let scale = self.scale_by_reference(reference);
+        self.color_channels_mut()
+            .par_mapv_inplace(|value| if value > 1. { 1. } else { value });
+
+        Ok(scale)
+    }
+
+    /// Scale the image so that `reference` maps to `1.0`, if it is positive.
+    fn scale_by_reference(&mut self, reference: f32) -> f32 {
+        if reference <= 0. {
+            return 1.;
+        }
+
+        let scale = 1. / reference;
+        self.color_channels_mut()
+            .par_mapv_inplace(|value| value * scale);
+
+        scale
+    }
+
+    /// Get a mutable view into every channel but the alpha channel.
+    pub(crate) fn color_channels_mut(&mut self) -> ArrayViewMut3<'_, f32> {
+        let channels = self.buffer.dim().2 - usize::from(self.has_alpha);
+
+        self.buffer.slice_mut(s![.., .., ..channels])
+    }
+
+    /// Create an image from merged radiance, appending `alpha` as its last channel if set.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: Merged radiance, with straight alpha
+    /// * `alpha`: Combined `(height, width)` alpha of the stack
+    /// * `premultiply`: Whether to multiply the radiance by alpha
+    pub(crate) fn with_alpha(
+        buffer: Array3<f32>,
+        alpha: Option<Array2<f32>>,
+        premultiply: bool,
+    ) -> Self {
+        let Some(alpha) = alpha else {
+            return Self::from_nd_array_buffer(buffer);
+        };
+
+        let (height, width, channels) = buffer.dim();
+        let mut combined = Array3::<f32>::zeros((height, width, channels + 1));
+        Zip::from(combined.lanes_mut(Axis(2)))
+            .and(buffer.lanes(Axis(2)))
+            .and(&alpha)
+            .par_for_each(|mut pixel, radiance, alpha| {
+                let scale = if premultiply { *alpha } else { 1. };
+
+                pixel
+                    .slice_mut(s![..channels])
+                    .zip_mut_with(&radiance, |value, radiance| *value = radiance * scale);
+                pixel[channels] = *alpha;
+            });
+
+        Self {
+            buffer: combined,
+            has_alpha: true,
+        }
+    }
+}
+
+/// Summary of a merge, returned by [`crate::hdr_merge_images_with_report`] along with the merged
+/// image.
+///
+/// Indices and per-frame values refer to the list of inputs as it is after the merge, not to the
+/// order the caller built it in. The list is sorted in place if
+/// [`crate::config::MergeConfig::sort_by_exposure`] is set, and frames with extreme clipping are
+/// removed from it if [`crate::config::MergeConfig::extreme_frames`] is
+/// [`crate::config::ExtremeFrameHandling::Drop`]. The only exception is the index of each of the
+/// [`MergeReport::clipped_frames`], which refers to the list before any frame was removed, since
+/// dropped frames are no longer in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergeReport {
+    /// Index of the reference frame used for deghosting and alignment in the merged list, see
+    /// [`crate::input::HDRInputList::reference_index`]
+    pub reference_index: usize,
+    /// Shortest and longest effective exposure of the merged frames, i.e. `exposure * gain` by
+    /// default, see [`crate::config::MergeConfig::exposure_model`]
+    pub exposure_range: (f32, f32),
+    /// Share of the pixels of each merged frame, between `0.0` and `1.0`, with at least one
+    /// channel at or above its saturation threshold, see
+    /// [`crate::config::MergeConfig::channel_saturation_thresholds`]. Only the crop region is
+    /// counted when [`crate::config::MergeConfig::crop`] is set.
+    pub saturated_fractions: Vec<f32>,
+    /// Frames with more than [`crate::config::MergeConfig::max_clipped_fraction`] of their
+    /// pixels clipped, by increasing index. They were left out of the merge if
+    /// [`crate::config::MergeConfig::extreme_frames`] is
+    /// [`crate::config::ExtremeFrameHandling::Drop`], in which case the merged list is the list
+    /// before the merge, sorted if configured, without the frames at these indices.
+    pub clipped_frames: Vec<ClippedFrame>,
+    /// Time spent merging, from validating the inputs to the finished image, or `None` on
+    /// platforms without a clock, e.g. `wasm32-unknown-unknown`
+    pub duration: Option<Duration>,
+}
+
+/// Frame of a stack with extreme clipping, see [`MergeReport::clipped_frames`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClippedFrame {
+    /// Index of the frame in the list after sorting, if
+    /// [`crate::config::MergeConfig::sort_by_exposure`] is set, and before any frame was dropped.
+    /// Unlike the other indices of [`MergeReport`], this doesn't refer to the merged list when
+    /// frames were dropped.
+    pub index: usize,
+    /// Share of the pixels of the frame with every channel at or above its saturation threshold
+    pub saturated_fraction: f32,
+    /// Share of the pixels of the frame with every channel below the noise floor
+    pub black_fraction: f32,
+}
+
+/// Largest ratio between the longest and shortest effective exposure of a stack for which
+/// [`MergeReport::recommends_fusion`] recommends exposure fusion, i.e. one stop.
+const FUSION_MAX_EXPOSURE_RATIO: f32 = 2.;
+
+/// Largest share of saturated pixels of any frame for which [`MergeReport::recommends_fusion`]
+/// recommends exposure fusion.
+const FUSION_MAX_SATURATED_FRACTION: f32 = 0.01;
+
+impl MergeReport {
+    /// Whether the stack looks like it was bracketed for exposure fusion rather than to capture a
+    /// wide dynamic range, i.e. its exposures lie within one stop of each other and barely any
+    /// pixel of any frame is saturated. Such a stack holds little more dynamic range than any
+    /// single frame, and [`crate::fusion`] usually gives a better looking result than merging and
+    /// tone mapping it.
+    ///
+    /// This is only a hint, the merged image is valid either way.
+    #[must_use]
+    pub fn recommends_fusion(&self) -> bool {
+        let (shortest, longest) = self.exposure_range;
+
+        longest <= shortest * FUSION_MAX_EXPOSURE_RATIO
+            && self
+                .saturated_fractions
+                .iter()
+                .all(|fraction| *fraction <= FUSION_MAX_SATURATED_FRACTION)
+    }
+}
+
+/// Histogram of the natural log of the luminance of an [`HDRImage`], see
+/// [`HDRImage::luminance_histogram`]. Pixels with non-finite luminance are skipped, and pixels
+/// without positive luminance cannot be placed on a log scale, so they are counted separately.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LuminanceHistogram {
+    /// Number of pixels in each bin, from darkest to brightest. Every bin spans the same range of
+    /// log luminance.
+    pub counts: Vec<u32>,
+    /// Natural log of the smallest positive luminance, the lower edge of the first bin. `0.0` if
+    /// no pixel has a positive luminance.
+    pub min_log_luminance: f32,
+    /// Natural log of the largest finite luminance, the upper edge of the last bin. `0.0` if no
+    /// pixel has a positive luminance.
+    pub max_log_luminance: f32,
+    /// Number of pixels with zero or negative luminance
+    pub black_count: u32,
+}
+
+impl LuminanceHistogram {
+    /// Bin the log luminance of every pixel of `buffer` into `bins` equally wide bins spanning
+    /// the range of the image. `bins` must not be zero.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn new(buffer: &Array3<f32>, bins: usize) -> Self {
+        let log_luminances = buffer
+            .lanes(Axis(2))
+            .into_iter()
+            .map(luminance)
+            .filter(|luminance| luminance.is_finite())
+            .map(|luminance| (luminance > 0.).then(|| luminance.ln()))
+            .collect::<Vec<Option<f32>>>();
+
+        let (min, max) = log_luminances
+            .iter()
+            .flatten()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+                (min.min(*value), max.max(*value))
+            });
+        let (min, max) = if min > max { (0., 0.) } else { (min, max) };
+        let bin_width = (max - min) / bins as f32;
+
+        let mut counts = vec![0_u32; bins];
+        let mut black_count = 0_u32;
+
+        for bin in log_luminances
+            .par_iter()
+            .map(|value| {
+                value.map(|value| {
+                    let bin = if bin_width > 0. {
+                        ((value - min) / bin_width) as usize
+                    } else {
+                        0
+                    };
+                    bin.min(bins - 1)
+                })
+            })
+            .collect::<Vec<Option<usize>>>()
+        {
+            match bin {
+                Some(bin) => counts[bin] += 1,
+                None => black_count += 1,
+            }
+        }
+
+        Self {
+            counts,
+            min_log_luminance: min,
+            max_log_luminance: max,
+            black_count,
+        }
+    }
+
+    /// Range of log luminance covered by each bin.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn bin_width(&self) -> f32 {
+        (self.max_log_luminance - self.min_log_luminance) / self.counts.len().max(1) as f32
+    }
+
+    /// Luminance below which `percentile` percent of the pixels fall, interpolated linearly in
+    /// log space within the bin it falls in.
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn percentile(&self, percentile: f32) -> f32 {
+        let total =
+            self.black_count as f32 + self.counts.iter().map(|count| *count as f32).sum::<f32>();
+        let rank = percentile / 100. * total;
+        let mut below = self.black_count as f32;
+        if rank <= below {
+            return 0.;
+        }
+
+        let bin_width =
+            (self.max_log_luminance - self.min_log_luminance) / self.counts.len() as f32;
+        for (bin, count) in self.counts.iter().enumerate() {
+            let count = *count as f32;
+            if count > 0. && below + count >= rank {
+                let fraction = (rank - below) / count;
+                return (self.min_log_luminance + (bin as f32 + fraction) * bin_width).exp();
+            }
+
+            below += count;
+        }
+
+        self.max_log_luminance.exp()
+    }
+}
+
+impl NDArrayBuffer for HDRImage {
+    fn to_nd_array_buffer(&self) -> Array3<f32> {
+        self.buffer.clone()
+    }
+
+    fn from_nd_array_buffer(buffer: Array3<f32>) -> Self {
+        let buffer = if buffer.is_standard_layout() {
+            buffer
+        } else {
+            buffer.as_standard_layout().into_owned()
+        };
+
+        Self {
+            buffer,
+            has_alpha: false,
+        }
+    }
+}
+
+/// Images with an alpha channel and three color channels produce an RGBA image. Otherwise the
+/// alpha channel is dropped, see [`NDArrayBuffer::from_nd_array_buffer`] for [`DynamicImage`].
+impl From<HDRImage> for DynamicImage {
+    #[allow(clippy::cast_possible_truncation)]
+    fn from(value: HDRImage) -> Self {
+        let (height, width, channels) = value.buffer.dim();
+
+        if !value.has_alpha || channels != 4 {
+            return DynamicImage::from_nd_array_buffer(value.buffer);
+        }
+
+        DynamicImage::ImageRgba32F(ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+            Rgba([0, 1, 2, 3].map(|channel| value.buffer[[y as usize, x as usize, channel]]))
+        }))
+    }
+}
+
+/// Count the pixels of a `(height, width, channels)` buffer with at least one non-finite value.
+pub(crate) fn count_non_finite(buffer: &Array3<f32>) -> usize {
+    buffer
+        .lanes(Axis(2))
+        .into_iter()
+        .filter(|pixel| pixel.iter().any(|value| !value.is_finite()))
+        .count()
+}
@@ -0,0 +1,162 @@
+//! Parallel iteration, run on rayon with the `parallel` feature and on the calling thread without
+//! it.
+//!
+//! Modules import [`prelude`] instead of `rayon::prelude`. With the feature it is rayon's prelude,
+//! and the `par_` methods of ndarray are its own. Without the feature, the prelude provides the
+//! same methods on top of sequential iterators, so the same code builds without rayon and its
+//! thread pool, e.g. for embedded or WASM targets.
+//!
+//! Only the methods whose sequential counterpart produces the same result are provided. Reductions
+//! of floating point values must not depend on how the work is split, which rayon leaves
+//! unspecified, so they compute partial results over a fixed partition of the input, e.g. one per
+//! row, and combine them sequentially in order. Results are then identical with and without the
+//! feature.
+
+#[cfg(feature = "parallel")]
+pub(crate) mod prelude {
+    pub(crate) use rayon::prelude::*;
+}
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) mod prelude {
+    use ndarray::{Array, ArrayBase, DataMut, Dimension, NdProducer, Zip};
+
+    /// Sequential counterpart of `rayon::iter::IntoParallelIterator`
+    pub(crate) trait IntoParallelIterator {
+        type Iter: Iterator;
+
+        fn into_par_iter(self) -> Self::Iter;
+    }
+
+    impl<I: IntoIterator> IntoParallelIterator for I {
+        type Iter = I::IntoIter;
+
+        fn into_par_iter(self) -> Self::Iter {
+            self.into_iter()
+        }
+    }
+
+    /// Sequential counterpart of `rayon::iter::IntoParallelRefIterator`
+    pub(crate) trait IntoParallelRefIterator<'data> {
+        type Iter: Iterator;
+
+        fn par_iter(&'data self) -> Self::Iter;
+    }
+
+    impl<'data, I: 'data + ?Sized> IntoParallelRefIterator<'data> for I
+    where
+        &'data I: IntoIterator,
+    {
+        type Iter = <&'data I as IntoIterator>::IntoIter;
+
+        fn par_iter(&'data self) -> Self::Iter {
+            self.into_iter()
+        }
+    }
+
+    /// Sequential counterpart of `rayon::iter::IntoParallelRefMutIterator`
+    pub(crate) trait IntoParallelRefMutIterator<'data> {
+        type Iter: Iterator;
+
+        fn par_iter_mut(&'data mut self) -> Self::Iter;
+    }
+
+    impl<'data, I: 'data + ?Sized> IntoParallelRefMutIterator<'data> for I
+    where
+        &'data mut I: IntoIterator,
+    {
+        type Iter = <&'data mut I as IntoIterator>::IntoIter;
+
+        fn par_iter_mut(&'data mut self) -> Self::Iter {
+            self.into_iter()
+        }
+    }
+
+    /// Methods of `rayon::iter::ParallelIterator` and `rayon::iter::IndexedParallelIterator`
+    /// that sequential iterators don't have
+    pub(crate) trait ParallelIterator: Iterator + Sized {
+        /// Work is never split, so the limit has no effect.
+        fn with_max_len(self, _max: usize) -> Self {
+            self
+        }
+
+        fn flat_map_iter<U: IntoIterator, F: FnMut(Self::Item) -> U>(
+            self,
+            function: F,
+        ) -> std::iter::FlatMap<Self, U, F> {
+            self.flat_map(function)
+        }
+    }
+
+    impl<I: Iterator> ParallelIterator for I {}
+
+    /// Sequential counterpart of the `par_mapv_inplace` method of ndarray
+    pub(crate) trait ParallelArray<A> {
+        fn par_mapv_inplace(&mut self, function: impl FnMut(A) -> A);
+    }
+
+    impl<A: Clone, S: DataMut<Elem = A>, D: Dimension> ParallelArray<A> for ArrayBase<S, D> {
+        fn par_mapv_inplace(&mut self, function: impl FnMut(A) -> A) {
+            self.mapv_inplace(function);
+        }
+    }
+
+    /// Sequential counterparts of the `par_` methods of ndarray's [`Zip`], one trait per number
+    /// of producers. Every number of producers ndarray supports is provided, whether it is
+    /// currently used or not.
+    macro_rules! parallel_zip {
+        ($trait:ident, $($producer:ident),+) => {
+            #[allow(dead_code)]
+            pub(crate) trait $trait<$($producer: NdProducer),+> {
+                fn par_for_each(self, function: impl FnMut($($producer::Item),+));
+
+                fn par_fold<T>(
+                    self,
+                    identity: impl Fn() -> T,
+                    fold: impl FnMut(T, $($producer::Item),+) -> T,
+                    reduce: impl Fn(T, T) -> T,
+                ) -> T;
+            }
+
+            impl<D: Dimension, $($producer: NdProducer<Dim = D>),+> $trait<$($producer),+>
+                for Zip<($($producer,)+), D>
+            {
+                fn par_for_each(self, function: impl FnMut($($producer::Item),+)) {
+                    self.for_each(function);
+                }
+
+                fn par_fold<T>(
+                    self,
+                    identity: impl Fn() -> T,
+                    fold: impl FnMut(T, $($producer::Item),+) -> T,
+                    _reduce: impl Fn(T, T) -> T,
+                ) -> T {
+                    self.fold(identity(), fold)
+                }
+            }
+        };
+    }
+
+    parallel_zip!(ParallelZip1, P1);
+    parallel_zip!(ParallelZip2, P1, P2);
+    parallel_zip!(ParallelZip3, P1, P2, P3);
+    parallel_zip!(ParallelZip4, P1, P2, P3, P4);
+    parallel_zip!(ParallelZip5, P1, P2, P3, P4, P5);
+    parallel_zip!(ParallelZip6, P1, P2, P3, P4, P5, P6);
+
+    /// Sequential counterpart of the `par_map_collect` method of ndarray's [`Zip`]
+    pub(crate) trait ParallelMapCollect<P1: NdProducer, P2: NdProducer> {
+        fn par_map_collect<R>(
+            self,
+            function: impl FnMut(P1::Item, P2::Item) -> R,
+        ) -> Array<R, P1::Dim>;
+    }
+
+    impl<D: Dimension, P1: NdProducer<Dim = D>, P2: NdProducer<Dim = D>> ParallelMapCollect<P1, P2>
+        for Zip<(P1, P2), D>
+    {
+        fn par_map_collect<R>(self, function: impl FnMut(P1::Item, P2::Item) -> R) -> Array<R, D> {
+            self.map_collect(function)
+        }
+    }
+}
@@ -1,14 +1,476 @@
 //! An implementation of HDR merging via "Poisson Photon Noise Estimator" as introduced in
 //! [Noise-Aware Merging of High Dynamic Range Image Stacks without Camera Calibration](https://www.cl.cam.ac.uk/research/rainbow/projects/noise-aware-merging/2020-ppne-mle.pdf)
 
-use crate::input::HDRInput;
-use ndarray::array;
+use crate::config::{AlphaMode, MergeConfig, VignettingCorrection};
+use crate::input::{reference_index, HDRInput};
+use crate::output::ClippedFrame;
+#[cfg(not(feature = "parallel"))]
+use crate::parallel::prelude::*;
+use crate::tonemap::luminance;
+use crate::Error;
 use ndarray::prelude::*;
-use rayon::prelude::*;
+use ndarray::Zip;
 
-const RED_COEFFICIENT: f32 = 1.;
-const GREEN_COEFFICIENT: f32 = 1.;
-const BLUE_COEFFICIENT: f32 = 1.;
+/// Relative weight of saturated samples and samples below the noise floor. This is small enough for
+/// well exposed samples to dominate, while keeping the estimate defined for pixels that are clipped
+/// in every frame.
+pub(crate) const CLIPPED_SAMPLE_WEIGHT: f32 = 1e-4;
+
+/// Result of the poisson estimate.
+pub(crate) struct Estimate {
+    /// Merged radiance of the stack
+    pub(crate) phi: Array3<f32>,
+    /// Per-pixel share of the total exposure coming from samples that were neither saturated nor
+    /// below the noise floor.
+    pub(crate) confidence: Array2<f32>,
+    /// Combined alpha of the stack, unless [`MergeConfig::alpha`] is [`AlphaMode::Discard`]
+    pub(crate) alpha: Option<Array2<f32>>,
+    /// Share of the pixels of each frame with at least one saturated channel, in the order the
+    /// frames were added
+    pub(crate) saturated_fractions: Vec<f32>,
+    /// Frames with extreme clipping, see [`MergeConfig::max_clipped_fraction`]. The estimate
+    /// itself doesn't detect them, so this is empty until they are filled in by the caller.
+    pub(crate) clipped_frames: Vec<ClippedFrame>,
+}
+
+/// Running state of the poisson estimate, to which frames are added one at a time. The sums are
+/// updated in place, so adding a frame doesn't allocate a new buffer.
+pub(crate) struct Accumulator {
+    phi: Array3<f32>,
+    total_weights: Array3<f32>,
+    /// Sum of the exposures of the frames in which each pixel was well exposed
+    well_exposed: Array2<f32>,
+    total_exposure: f32,
+    /// Combined alpha of the frames added so far, unless [`MergeConfig::alpha`] is
+    /// [`AlphaMode::Discard`]. Holds the sum of alpha for [`AlphaMode::Mean`].
+    alpha: Option<Array2<f32>>,
+    frames: usize,
+    saturated_fractions: Vec<f32>,
+    /// Shape of the frames before cropping, which the vignetting correction is relative to
+    frame_shape: (usize, usize, usize),
+    config: MergeConfig,
+}
+
+impl Accumulator {
+    /// Create an empty accumulator for frames of the given `(height, width, channels)` shape. When
+    /// [`MergeConfig::crop`] is set, the estimate has the size of the crop region instead.
+    pub(crate) fn new(frame_shape: (usize, usize, usize), config: &MergeConfig) -> Self {
+        let shape = match config.crop {
+            Some((_, _, width, height)) => (height as usize, width as usize, frame_shape.2),
+            None => frame_shape,
+        };
+
+        Self {
+            phi: Array3::zeros(shape),
+            total_weights: Array3::zeros(shape),
+            well_exposed: Array2::zeros((shape.0, shape.1)),
+            total_exposure: 0.,
+            alpha: match config.alpha {
+                AlphaMode::Discard => None,
+                AlphaMode::Min => Some(Array2::ones((shape.0, shape.1))),
+                AlphaMode::Mean => Some(Array2::zeros((shape.0, shape.1))),
+            },
+            frames: 0,
+            saturated_fractions: Vec::new(),
+            frame_shape,
+            config: config.clone(),
+        }
+    }
+
+    /// Crop the frame if [`MergeConfig::crop`] is set, convert it to radiance in place and
+    /// accumulate it into the estimate. Every frame
+    /// contributes exactly once to each channel with weight `exposure`, reduced by read noise
+    /// (see [`crate::config::NoiseModel`]), scaled by [`MergeConfig::weight_function`] and reduced
+    /// for clipped samples. Pixel values are linearized according to
+    /// [`MergeConfig::input_encoding`] and calibrated with [`MergeConfig::calibration`] as they
+    /// are converted. Vignetting is corrected after
+    /// clipped samples are detected. The pixels of the frame are processed in parallel.
+    ///
+    /// Unless [`MergeConfig::alpha`] is [`AlphaMode::Discard`], the premultiplied pixels are
+    /// divided by their alpha before they are converted, and the weights are multiplied by it.
+    /// Samples with an alpha of zero don't contribute at all, and are set to zero. The weights are
+    /// also multiplied by the weight map of the frame, if any, and samples with a weight of zero
+    /// are handled like transparent ones.
+    ///
+    /// # Errors
+    /// - If the coefficients or saturation thresholds don't apply to the number of channels of the
+    ///   frame
+    /// - If the calibration frames don't have the dimensions of the uncropped frame.
+    /// - If the crop region doesn't lie within the frame.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(exposure = input.get_exposure(), gain = input.get_gain())
+        )
+    )]
+    pub(crate) fn add(&mut self, input: &mut HDRInput) -> Result<(), Error> {
+        let origin = match self.config.crop {
+            Some((x, y, width, height)) => {
+                input.crop(x, y, width, height)?;
+                (y as usize, x as usize)
+            }
+            None => (0, 0),
+        };
+
+        let noise_floor = self.config.noise_floor;
+        let thresholds = self
+            .config
+            .saturation_thresholds(input.get_buffer().dim().2)?;
+        let is_saturated = |pixel: ArrayView1<f32>| {
+            pixel
+                .iter()
+                .zip(&thresholds)
+                .any(|(value, threshold)| value >= threshold)
+        };
+
+        let exposure = input.get_exposure();
+        let scale = RadianceScale::new(input, &self.config)?;
+        let noise_model = self.config.noise_model;
+        let read_variance = noise_model.read_noise * noise_model.read_noise;
+        let photon_scale = noise_model.photon_scale * input.get_gain();
+        let weight_function = self.config.weight_function;
+        let encoding = self.config.input_encoding;
+        let vignetting = Vignetting::new(self.config.vignetting, self.frame_shape, origin);
+        let calibration = &self.config.calibration;
+        calibration.validate_shape(self.frame_shape)?;
+        let combine_alpha = self.config.alpha != AlphaMode::Discard;
+        let (input_buffer, input_alpha, weight_map) = input.get_buffer_mut_and_masks();
+
+        let saturated = Zip::from(input_buffer.lanes(Axis(2))).par_fold(
+            || 0_usize,
+            |count, pixel| count + usize::from(is_saturated(pixel)),
+            |count, other| count + other,
+        );
+        #[allow(clippy::cast_precision_loss)]
+        self.saturated_fractions
+            .push(saturated as f32 / (input_buffer.len() / input_buffer.dim().2) as f32);
+
+        let shape = (input_buffer.dim().0, input_buffer.dim().1);
+        let alpha = pixel_mask(input_alpha.filter(|_| combine_alpha), shape);
+        let weight_map = pixel_mask(weight_map, shape);
+
+        Zip::indexed(self.phi.lanes_mut(Axis(2)))
+            .and(self.total_weights.lanes_mut(Axis(2)))
+            .and(&mut self.well_exposed)
+            .and(input_buffer.lanes_mut(Axis(2)))
+            .and(&alpha)
+            .par_for_each(
+                |(y, x), mut phi, mut total_weights, well_exposed, mut radiance, alpha| {
+                    let mask = weight_map[[y, x]];
+                    if *alpha <= 0. || mask <= 0. {
+                        radiance.fill(0.);
+                        return;
+                    }
+                    if *alpha < 1. {
+                        radiance /= *alpha;
+                    }
+
+                    let correction = vignetting.factor(y, x);
+                    let mask = alpha * mask;
+                    let brightest = radiance.fold(0_f32, |max, value| max.max(*value));
+                    if brightest >= noise_floor && !is_saturated(radiance.view()) {
+                        *well_exposed += exposure;
+                    }
+
+                    Zip::indexed(&mut phi)
+                        .and(&mut total_weights)
+                        .and(&mut radiance)
+                        .and(&thresholds)
+                        .for_each(|channel, phi, total_weight, radiance, threshold| {
+                            let weight = if read_variance > 0. {
+                                exposure
+                                    / (1.
+                                        + read_variance
+                                            / (photon_scale * radiance.max(f32::MIN_POSITIVE)))
+                            } else {
+                                exposure
+                            } * weight_function.weight(*radiance);
+                            let weight = if *radiance >= noise_floor && *radiance < *threshold {
+                                weight * mask
+                            } else {
+                                weight * mask * CLIPPED_SAMPLE_WEIGHT
+                            };
+
+                            let linear = calibration.apply(
+                                encoding.linearize(*radiance),
+                                (y + origin.0, x + origin.1, channel),
+                            );
+
+                            *radiance = scale.apply(linear * correction, channel);
+                            *phi += *radiance * weight;
+                            *total_weight += weight;
+                        });
+                },
+            );
+
+        self.combine_alpha(alpha);
+        self.total_exposure += exposure;
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Combine the alpha of a frame into the merged alpha, according to [`MergeConfig::alpha`].
+    fn combine_alpha(&mut self, alpha: ArrayView2<f32>) {
+        match (&mut self.alpha, self.config.alpha) {
+            (Some(merged), AlphaMode::Min) => Zip::from(merged)
+                .and(&alpha)
+                .par_for_each(|merged, alpha| *merged = merged.min(*alpha)),
+            (Some(merged), _) => {
+                Zip::from(merged)
+                    .and(&alpha)
+                    .par_for_each(|merged, alpha| *merged += alpha);
+            }
+            (None, _) => {}
+        }
+    }
+
+    /// Normalise the accumulated sums into the final estimate.
+    pub(crate) fn finish(self) -> Estimate {
+        let Self {
+            mut phi,
+            total_weights,
+            mut well_exposed,
+            total_exposure,
+            mut alpha,
+            frames,
+            saturated_fractions,
+            config,
+            ..
+        } = self;
+
+        // Only pixels that are transparent in every frame have no weight at all.
+        Zip::from(&mut phi)
+            .and(&total_weights)
+            .par_for_each(|phi, total_weight| {
+                *phi = if *total_weight > 0. {
+                    *phi / total_weight
+                } else {
+                    0.
+                };
+            });
+
+        well_exposed.par_mapv_inplace(|value| value / total_exposure);
+
+        if let (Some(alpha), AlphaMode::Mean) = (&mut alpha, config.alpha) {
+            #[allow(clippy::cast_precision_loss)]
+            let frames = frames as f32;
+            alpha.par_mapv_inplace(|value| value / frames);
+        }
+
+        Estimate {
+            phi,
+            confidence: well_exposed,
+            alpha,
+            saturated_fractions,
+            clipped_frames: Vec::new(),
+        }
+    }
+}
+
+/// View a `(height, width, 1)` mask of a frame, e.g. its alpha, as a `(height, width)` one, or
+/// a mask of ones if the frame has none.
+fn pixel_mask(mask: Option<&Array3<f32>>, shape: (usize, usize)) -> ArrayView2<'_, f32> {
+    match mask {
+        Some(mask) => mask.index_axis(Axis(2), 0),
+        None => ArrayView2::from_shape(shape.strides((0, 0)), &[1.])
+            .unwrap_or_else(|_| unreachable!("A single value broadcasts to any shape")),
+    }
+}
+
+/// Convert a linear pixel value to radiance, i.e. `value / (exposure * gain * coefficient)`.
+/// This is the core of the conversion of every frame, see [`RadianceScale`].
+pub(crate) fn scale_radiance(value: f32, exposure: f32, gain: f32, coefficient: f32) -> f32 {
+    value / (exposure * gain * coefficient)
+}
+
+/// Exposure, gain and per-channel coefficients a frame is converted to radiance with, see
+/// [`scale_radiance`].
+pub(crate) struct RadianceScale {
+    /// Exposure to light, see [`HDRInput::light_exposure`]
+    exposure: f32,
+    gain: f32,
+    /// Coefficient of each channel, raised so that `exposure * gain * coefficient` is at least
+    /// [`MergeConfig::min_scaling_factor`] if set
+    coefficients: Array1<f32>,
+}
+
+impl RadianceScale {
+    /// # Errors
+    /// - If the coefficients don't apply to the number of channels of the frame.
+    /// - If the exposure model includes the aperture, but the aperture of the frame is unknown.
+    pub(crate) fn new(input: &HDRInput, config: &MergeConfig) -> Result<Self, Error> {
+        let exposure = input.light_exposure(config)?;
+        let gain = input.get_gain();
+        let min_scaling_factor = config.min_scaling_factor.unwrap_or(0.);
+
+        let coefficients = config
+            .coefficients
+            .for_channels(input.get_buffer().dim().2)?
+            .into_iter()
+            .map(|coefficient| {
+                if exposure * gain * coefficient < min_scaling_factor {
+                    min_scaling_factor / (exposure * gain)
+                } else {
+                    coefficient
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            exposure,
+            gain,
+            coefficients,
+        })
+    }
+
+    /// Convert a linear value of the given channel to radiance.
+    fn apply(&self, value: f32, channel: usize) -> f32 {
+        scale_radiance(value, self.exposure, self.gain, self.coefficients[channel])
+    }
+
+    /// `exposure * gain * coefficient` of the given channel, which its linear values are divided
+    /// by.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn denominator(&self, channel: usize) -> f32 {
+        self.exposure * self.gain * self.coefficients[channel]
+    }
+}
+
+/// Vignetting correction for frames of a given size, mapping pixel positions to the factor they
+/// are multiplied by. Positions are relative to `origin` in the frame, so that cropped frames are
+/// corrected like the full frame.
+struct Vignetting {
+    correction: VignettingCorrection,
+    center: (f32, f32),
+    inverse_radius_squared: f32,
+}
+
+impl Vignetting {
+    #[allow(clippy::cast_precision_loss)]
+    fn new(
+        correction: VignettingCorrection,
+        (height, width, _): (usize, usize, usize),
+        (origin_y, origin_x): (usize, usize),
+    ) -> Self {
+        let (height, width) = (height as f32, width as f32);
+
+        Self {
+            correction,
+            center: (height / 2. - origin_y as f32, width / 2. - origin_x as f32),
+            inverse_radius_squared: 4. / (height * height + width * width),
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn factor(&self, y: usize, x: usize) -> f32 {
+        if self.correction.is_identity() {
+            return 1.;
+        }
+
+        let dy = y as f32 + 0.5 - self.center.0;
+        let dx = x as f32 + 0.5 - self.center.1;
+
+        self.correction
+            .factor((dy * dy + dx * dx) * self.inverse_radius_squared)
+    }
+}
+
+/// Convert a frame to radiance on its own, without merging it with the rest of the stack. This
+/// is the same conversion the estimate applies to each frame before accumulating it.
+///
+/// # Errors
+/// - If the coefficients don't apply to the number of channels of the frame.
+/// - If the calibration frames don't have the dimensions of the frame.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(exposure = input.get_exposure(), gain = input.get_gain())
+    )
+)]
+pub(crate) fn calculate_radiance(
+    input: &HDRInput,
+    config: &MergeConfig,
+) -> Result<Array3<f32>, Error> {
+    let scale = RadianceScale::new(input, config)?;
+    let vignetting = Vignetting::new(config.vignetting, input.get_buffer().dim(), (0, 0));
+    let encoding = config.input_encoding;
+    let calibration = &config.calibration;
+    calibration.validate_shape(input.get_buffer().dim())?;
+    let mut radiance = input.get_buffer().mapv(|value| encoding.linearize(value));
+
+    if !calibration.is_empty() {
+        Zip::indexed(&mut radiance)
+            .par_for_each(|index, value| *value = calibration.apply(*value, index));
+    }
+
+    Zip::indexed(&mut radiance).par_for_each(|(y, x, channel), value| {
+        *value = scale.apply(*value * vignetting.factor(y, x), channel);
+    });
+
+    Ok(radiance)
+}
+
+/// Find the frames with more than [`MergeConfig::max_clipped_fraction`] of their pixels
+/// saturated in every channel or below the noise floor in every channel.
+///
+/// # Errors
+/// If the saturation thresholds don't apply to the number of channels of a frame.
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn find_clipped_frames(
+    inputs: &[HDRInput],
+    config: &MergeConfig,
+) -> Result<Vec<ClippedFrame>, Error> {
+    let mut clipped_frames = Vec::new();
+
+    for (index, input) in inputs.iter().enumerate() {
+        let buffer = input.get_buffer();
+        let thresholds = config.saturation_thresholds(buffer.dim().2)?;
+        let (saturated, black) = Zip::from(buffer.lanes(Axis(2))).par_fold(
+            || (0_usize, 0_usize),
+            |(saturated, black), pixel| {
+                let is_saturated = pixel
+                    .iter()
+                    .zip(&thresholds)
+                    .all(|(value, threshold)| value >= threshold);
+                let is_black = pixel.iter().all(|value| *value < config.noise_floor);
+
+                (
+                    saturated + usize::from(is_saturated),
+                    black + usize::from(is_black),
+                )
+            },
+            |(saturated, black), (other_saturated, other_black)| {
+                (saturated + other_saturated, black + other_black)
+            },
+        );
+
+        let pixels = (buffer.len() / buffer.dim().2).max(1) as f32;
+        let frame = ClippedFrame {
+            index,
+            saturated_fraction: saturated as f32 / pixels,
+            black_fraction: black as f32 / pixels,
+        };
+
+        if frame.saturated_fraction + frame.black_fraction > config.max_clipped_fraction {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                index,
+                saturated_fraction = frame.saturated_fraction,
+                black_fraction = frame.black_fraction,
+                "Frame is mostly clipped, the stack may be mis-bracketed"
+            );
+
+            clipped_frames.push(frame);
+        }
+    }
+
+    Ok(clipped_frames)
+}
 
 /// Calculate the poisson estimate for an image.
 /// Given a set of image paths, this returns a
@@ -20,50 +482,462 @@ const BLUE_COEFFICIENT: f32 = 1.;
 ///
 /// specifically the section about "Poisson Photon Noise Estimator"
 ///
+/// With the `gpu` feature, the frames are accumulated on the GPU when it supports the merge, see
+/// [`crate::gpu`], and on the CPU otherwise.
+///
 /// # Errors
-/// If supplied image is not an RGB image. Non RGB images
-/// include images with alpha channel, grayscale images,
-/// and images with other color encodings (like CMYK).
-pub(crate) fn calculate_poisson_estimate(inputs: &mut [HDRInput]) -> Array3<f32> {
-    inputs.par_iter_mut().for_each(|input| {
-        let scaling_factor = input.get_exposure() * input.get_gain();
-        let input_buffer = input.get_buffer_mut();
-
-        if let (_, _, 1) = input_buffer.dim() {
-            *input_buffer /= scaling_factor;
-        } else if let (_, _, 3) = input_buffer.dim() {
-            *input_buffer /= &array![[[
-                scaling_factor * RED_COEFFICIENT,
-                scaling_factor * GREEN_COEFFICIENT,
-                scaling_factor * BLUE_COEFFICIENT
-            ]]];
-        } else {
-            panic!("Unexpected scaling matrix encountered.")
+/// - If no images are provided, see [`Error::NoImages`].
+/// - If the coefficients don't apply to the number of channels of the images.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "info", skip_all, fields(frames = inputs.len()))
+)]
+pub(crate) fn calculate_poisson_estimate(
+    inputs: &mut [HDRInput],
+    config: &MergeConfig,
+    progress: &dyn Fn(f32),
+) -> Result<Estimate, Error> {
+    #[cfg(feature = "tracing")]
+    {
+        let exposures = inputs
+            .iter()
+            .map(|input| input.get_exposure() * input.get_gain());
+        let shortest = exposures.clone().fold(f32::INFINITY, f32::min);
+        let longest = exposures.fold(0., f32::max);
+
+        tracing::info!(shortest, longest, "Merging exposures");
+    }
+
+    let shape = inputs.first().ok_or(Error::NoImages)?.get_buffer().dim();
+
+    #[cfg(feature = "gpu")]
+    if let Some(estimate) = crate::gpu::calculate_estimate(inputs, config, progress)? {
+        return Ok(estimate);
+    }
+
+    let mut estimate = accumulate(inputs, shape, config, progress)?;
+
+    if config.deghost {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("deghost").entered();
+
+        deghost(inputs, &mut estimate.phi, config.deghost_threshold);
+    }
+
+    Ok(estimate)
+}
+
+/// Accumulate the frames of the given `(height, width, channels)` shape on the CPU, converting
+/// them to radiance in place.
+///
+/// # Errors
+/// If a frame can't be accumulated, see [`Accumulator::add`].
+pub(crate) fn accumulate(
+    inputs: &mut [HDRInput],
+    shape: (usize, usize, usize),
+    config: &MergeConfig,
+    progress: &dyn Fn(f32),
+) -> Result<Estimate, Error> {
+    let mut accumulator = Accumulator::new(shape, config);
+    let count = inputs.len();
+
+    // The accumulators start from zeros rather than from the first frame, so no frame is counted
+    // twice. Frames are accumulated one after another so that progress can be reported in order.
+    for (index, input) in inputs.iter_mut().enumerate() {
+        accumulator.add(input)?;
+
+        #[allow(clippy::cast_precision_loss)]
+        progress((index + 1) as f32 / count as f32);
+    }
+
+    Ok(accumulator.finish())
+}
+
+/// Replace pixels whose luminance varies more than `threshold` (relative standard deviation)
+/// across the scaled inputs with the radiance of the reference frame.
+#[allow(clippy::cast_precision_loss)]
+fn deghost(inputs: &[HDRInput], phi: &mut Array3<f32>, threshold: f32) {
+    let reference = inputs[reference_index(inputs)].get_buffer();
+    let count = inputs.len() as f32;
+
+    Zip::indexed(phi.lanes_mut(Axis(2))).par_for_each(|(y, x), mut pixel| {
+        let (sum, sum_squares) = inputs
+            .iter()
+            .map(|input| luminance(input.get_buffer().slice(s![y, x, ..])))
+            .fold((0., 0.), |(sum, sum_squares), value| {
+                (sum + value, sum_squares + value * value)
+            });
+
+        let mean = sum / count;
+        let deviation = (sum_squares / count - mean * mean).max(0.).sqrt();
+
+        if mean > 0. && deviation / mean > threshold {
+            pixel.assign(&reference.slice(s![y, x, ..]));
         }
     });
+}
 
-    let shape = inputs
-        .first()
-        .unwrap_or_else(|| panic!("Expected at least 1 input image"))
-        .get_buffer()
-        .dim();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+    use std::time::Duration;
 
-    let sum_exposures: f32 = inputs.iter().map(HDRInput::get_exposure).sum();
+    /// Frame of 4x4 RGB pixels that all hold `value`, taken with the given exposure in seconds
+    /// and a gain of `1.0`.
+    fn constant_frame(value: f32, exposure: f32) -> HDRInput {
+        let image = image::Rgb32FImage::from_pixel(4, 4, image::Rgb([value; 3]));
+        HDRInput::with_image(
+            &DynamicImage::ImageRgb32F(image),
+            Duration::from_secs_f32(exposure),
+            1.,
+        )
+        .expect("The frame is valid")
+    }
 
-    let normalized_radiances = inputs
-        .par_iter()
-        .map(|input| {
-            let mut radiance = input.get_buffer().clone();
-            let exposure = input.get_exposure();
+    fn estimate(inputs: &mut [HDRInput]) -> Estimate {
+        estimate_with_config(inputs, &MergeConfig::default())
+    }
 
-            radiance *= exposure / sum_exposures;
+    fn estimate_with_config(inputs: &mut [HDRInput], config: &MergeConfig) -> Estimate {
+        calculate_poisson_estimate(inputs, config, &|_| {}).expect("The stack is valid")
+    }
 
-            radiance
-        })
-        .reduce(
-            || Array3::<f32>::zeros(shape),
-            |acc, radiance| acc + radiance,
+    fn assert_all_close(phi: &Array3<f32>, expected: f32) {
+        for value in phi {
+            assert!(
+                (value - expected).abs() <= expected * 1e-6,
+                "Expected {expected}, got {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn constant_radiance_is_recovered() {
+        let mut inputs = [
+            constant_frame(0.125, 0.5),
+            constant_frame(0.25, 1.),
+            constant_frame(0.5, 2.),
+        ];
+
+        assert_all_close(&estimate(&mut inputs).phi, 0.25);
+    }
+
+    #[test]
+    fn every_frame_contributes_once_with_its_exposure() {
+        // Radiances of 0.4, 0.3 and 0.2, weighted by exposures of 1, 2 and 4. Counting the first
+        // frame twice would give (2 * 0.4 + 2 * 0.3 + 4 * 0.2) / 8 instead.
+        let mut inputs = [
+            constant_frame(0.4, 1.),
+            constant_frame(0.6, 2.),
+            constant_frame(0.8, 4.),
+        ];
+
+        assert_all_close(&estimate(&mut inputs).phi, (0.4 + 0.6 + 0.8) / 7.);
+    }
+
+    #[test]
+    fn result_does_not_depend_on_the_frame_order() {
+        let frames = || {
+            [
+                constant_frame(0.4, 1.),
+                constant_frame(0.6, 2.),
+                constant_frame(0.8, 4.),
+            ]
+        };
+
+        let mut forward = frames();
+        let mut reversed = frames();
+        reversed.reverse();
+
+        assert_all_close(
+            &estimate(&mut reversed).phi,
+            estimate(&mut forward).phi[[0, 0, 0]],
         );
+    }
+
+    /// Stack of constant radiance `0.25`, where a bright subject moved through the 2x2 pixels at
+    /// `(2, 2)` of the shortest exposure only.
+    fn stack_with_ghost() -> [HDRInput; 3] {
+        let mut frames = [
+            constant_frame(0.125, 0.5),
+            constant_frame(0.25, 1.),
+            constant_frame(0.5, 2.),
+        ];
+        frames[0]
+            .get_buffer_mut()
+            .slice_mut(s![2..4, 2..4, ..])
+            .fill(0.9);
+
+        frames
+    }
+
+    #[test]
+    fn ghost_is_blended_without_deghosting() {
+        let phi = estimate(&mut stack_with_ghost()).phi;
+
+        // The subject has a radiance of 1.8 in the shortest exposure, with a weight of 0.5.
+        let blended = (0.5 * 1.8 + 1. * 0.25 + 2. * 0.25) / 3.5;
+        assert!((phi[[2, 2, 0]] - blended).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ghost_is_taken_from_the_reference_frame_with_deghosting() {
+        let config = MergeConfig {
+            deghost: true,
+            ..MergeConfig::default()
+        };
+
+        assert_all_close(
+            &estimate_with_config(&mut stack_with_ghost(), &config).phi,
+            0.25,
+        );
+    }
+
+    /// Highlight with a radiance of `2.0`, which is only measured by the shortest exposure and
+    /// clipped at `1.0` in the others.
+    fn stack_with_clipped_highlight() -> [HDRInput; 3] {
+        [
+            constant_frame(0.5, 0.25),
+            constant_frame(1., 1.),
+            constant_frame(1., 4.),
+        ]
+    }
+
+    #[test]
+    fn clipped_highlight_is_recovered_from_the_shortest_exposure() {
+        let phi = estimate(&mut stack_with_clipped_highlight()).phi;
+
+        assert!((phi[[0, 0, 0]] - 2.).abs() < 2. * 0.01);
+    }
+
+    #[test]
+    fn clipped_highlight_corrupts_the_result_without_saturation_weighting() {
+        let config = MergeConfig {
+            saturation_threshold: 1.5,
+            ..MergeConfig::default()
+        };
+        let phi = estimate_with_config(&mut stack_with_clipped_highlight(), &config).phi;
 
-    normalized_radiances
+        assert!(phi[[0, 0, 0]] < 0.5);
+    }
+
+    #[test]
+    fn samples_below_the_noise_floor_barely_contribute() {
+        // The shortest exposure reads 0.01 instead of 0.0025 because of noise.
+        let mut inputs = [constant_frame(0.01, 0.25), constant_frame(0.04, 4.)];
+        let phi = estimate(&mut inputs).phi;
+
+        assert!((phi[[0, 0, 0]] - 0.01).abs() < 0.01 * 0.001);
+    }
+
+    /// Reference implementation of the default estimate, which computes every pixel on its own
+    /// from freshly allocated radiance maps, but in the same order as the accumulator.
+    fn reference_estimate(inputs: &[HDRInput], config: &MergeConfig) -> Array3<f32> {
+        let radiances = inputs
+            .iter()
+            .map(|input| calculate_radiance(input, config).expect("The frame is valid"))
+            .collect::<Vec<Array3<f32>>>();
+
+        Array3::from_shape_fn(inputs[0].get_buffer().dim(), |index| {
+            let (phi, total_weight) = inputs.iter().zip(&radiances).fold(
+                (0_f32, 0_f32),
+                |(phi, total_weight), (input, radiance)| {
+                    let value = input.get_buffer()[index];
+                    let weight =
+                        if value >= config.noise_floor && value < config.saturation_threshold {
+                            input.get_exposure()
+                        } else {
+                            input.get_exposure() * CLIPPED_SAMPLE_WEIGHT
+                        };
+
+                    (phi + radiance[index] * weight, total_weight + weight)
+                },
+            );
+
+            phi / total_weight
+        })
+    }
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn in_place_accumulation_matches_the_reference_estimate() {
+        let frames = || {
+            [0.25_f32, 1., 4.].map(|exposure| {
+                let image = image::Rgb32FImage::from_fn(6, 6, |x, y| {
+                    image::Rgb([0., 1., 2.].map(|channel: f32| {
+                        (((y * 6 + x) as f32 / 36. + 0.1 * channel) * exposure / 2.).min(1.)
+                    }))
+                });
+
+                HDRInput::with_image(
+                    &DynamicImage::ImageRgb32F(image),
+                    Duration::from_secs_f32(exposure),
+                    1.,
+                )
+                .expect("The frame is valid")
+            })
+        };
+
+        let config = MergeConfig::default();
+        let expected = reference_estimate(&frames(), &config);
+
+        assert_eq!(estimate(&mut frames()).phi, expected);
+    }
+
+    /// Frame of 4x4 pixels with the given RGB values, taken with the given exposure in seconds and
+    /// a gain of `1.0`.
+    fn rgb_frame(pixel: [f32; 3], exposure: f32) -> HDRInput {
+        let buffer = Array3::from_shape_fn((4, 4, 3), |(_, _, channel)| pixel[channel]);
+
+        HDRInput::with_buffer(buffer, Duration::from_secs_f32(exposure), 1.)
+            .expect("The frame is valid")
+    }
+
+    /// Red highlight with a radiance of `1.6` on a sensor whose red channel clips at `0.9`, so
+    /// it is only measured by the shortest exposure. Green and blue have a radiance of `0.2`.
+    fn stack_with_early_red_clipping() -> [HDRInput; 3] {
+        [
+            rgb_frame([0.4, 0.05, 0.05], 0.25),
+            rgb_frame([0.9, 0.2, 0.2], 1.),
+            rgb_frame([0.9, 0.8, 0.8], 4.),
+        ]
+    }
+
+    #[test]
+    fn channel_clipping_before_the_saturation_threshold_corrupts_the_result() {
+        let phi = estimate(&mut stack_with_early_red_clipping()).phi;
+
+        assert!(phi[[0, 0, 0]] < 0.5);
+    }
+
+    #[test]
+    fn channel_saturation_thresholds_recover_the_channel_that_clips_first() {
+        let config = MergeConfig {
+            channel_saturation_thresholds: Some(vec![0.9, 0.98, 0.98]),
+            ..MergeConfig::default()
+        };
+        let phi = estimate_with_config(&mut stack_with_early_red_clipping(), &config).phi;
+
+        assert!((phi[[0, 0, 0]] - 1.6).abs() < 1.6 * 0.01);
+        assert!((phi[[0, 0, 1]] - 0.2).abs() < 1e-6);
+        assert!((phi[[0, 0, 2]] - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn channel_saturation_thresholds_must_match_the_channels() {
+        let config = MergeConfig {
+            channel_saturation_thresholds: Some(vec![0.9, 0.98]),
+            ..MergeConfig::default()
+        };
+        let error =
+            calculate_poisson_estimate(&mut stack_with_early_red_clipping(), &config, &|_| {})
+                .err()
+                .expect("Two thresholds don't apply to RGB frames");
+
+        assert!(matches!(
+            error,
+            Error::InputError { parameter_name, .. } if parameter_name == "channel_saturation_thresholds"
+        ));
+    }
+
+    /// Stack of a dark pixel with a radiance of `10`, lost in noise in an exposure of 1ms, and a
+    /// frame whose exposure was misreported as 1µs, where the noise reads as `0.05`.
+    fn stack_with_misreported_exposure() -> [HDRInput; 2] {
+        [constant_frame(0.05, 1e-6), constant_frame(0.01, 1e-3)]
+    }
+
+    #[test]
+    fn tiny_exposure_turns_noise_into_runaway_radiance() {
+        let phi = estimate(&mut stack_with_misreported_exposure()).phi;
+
+        assert!(phi[[0, 0, 0]] > 1e4, "{}", phi[[0, 0, 0]]);
+    }
+
+    #[test]
+    fn min_scaling_factor_prevents_runaway_radiance() {
+        let config = MergeConfig {
+            min_scaling_factor: Some(1e-3),
+            ..MergeConfig::default()
+        };
+        let phi = estimate_with_config(&mut stack_with_misreported_exposure(), &config).phi;
+
+        assert!(phi[[0, 0, 0]] < 100., "{}", phi[[0, 0, 0]]);
+    }
+
+    #[test]
+    fn min_scaling_factor_only_applies_to_frames_below_it() {
+        let config = MergeConfig {
+            min_scaling_factor: Some(1e-3),
+            ..MergeConfig::default()
+        };
+
+        let short = calculate_radiance(&constant_frame(0.05, 1e-6), &config)
+            .expect("The configuration applies");
+        assert_all_close(&short, 0.05 / 1e-3);
+
+        let long = calculate_radiance(&constant_frame(0.05, 0.5), &config)
+            .expect("The configuration applies");
+        assert_all_close(&long, 0.05 / 0.5);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn radiance_is_monotonic_in_the_pixel_value(
+            value in 0_f32..=1.,
+            other in 0_f32..=1.,
+            exposure in 1e-4_f32..10.,
+            gain in 0.1_f32..100.,
+            coefficient in 0.1_f32..10.,
+        ) {
+            let (low, high) = (value.min(other), value.max(other));
+
+            proptest::prop_assert!(
+                scale_radiance(low, exposure, gain, coefficient)
+                    <= scale_radiance(high, exposure, gain, coefficient)
+            );
+        }
+
+        #[test]
+        fn doubling_the_exposure_halves_the_radiance(
+            value in 0_f32..=1.,
+            exposure in 1e-4_f32..10.,
+            gain in 0.1_f32..100.,
+            coefficient in 0.1_f32..10.,
+        ) {
+            let radiance = scale_radiance(value, exposure, gain, coefficient);
+            let doubled = scale_radiance(value, 2. * exposure, gain, coefficient);
+
+            proptest::prop_assert!((2. * doubled - radiance).abs() <= radiance * 1e-6);
+        }
+
+        #[test]
+        fn doubling_the_exposure_of_a_frame_halves_its_radiance(
+            value in 0_f32..=1.,
+            exposure in 1e-3_f32..10.,
+            gain in 0.1_f32..100.,
+        ) {
+            let config = MergeConfig::default();
+            let frame = |exposure| {
+                HDRInput::with_buffer(
+                    Array3::from_elem((1, 1, 3), value),
+                    Duration::from_secs_f32(exposure),
+                    gain,
+                )
+                .expect("The frame is valid")
+            };
+            let scale = RadianceScale::new(&frame(exposure), &config)
+                .expect("The coefficients apply");
+            let doubled = RadianceScale::new(&frame(2. * exposure), &config)
+                .expect("The coefficients apply");
+
+            for channel in 0..3 {
+                let radiance = scale.apply(value, channel);
+                // Durations are rounded to nanoseconds.
+                proptest::prop_assert!(
+                    (2. * doubled.apply(value, channel) - radiance).abs() <= radiance * 1e-5
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,195 @@
+//! Sidecar files listing the exposure and gain of every frame of a stack, for stacks without any
+//! metadata.
+//!
+//! A sidecar is a text file with one `filename exposure gain` line per frame, separated by
+//! whitespace, e.g.
+//!
+//! ```text
+//! # Bracketed stack of the hallway
+//! hallway_1.jpg 1/250 1
+//! hallway_2.jpg 1/60 1
+//! hallway_3.jpg 0.25 2
+//! ```
+//!
+//! The exposure time is in seconds, written as a decimal number or as a fraction like `1/250`. The
+//! gain is optional and defaults to `1.0`. Empty lines and lines starting with `#` are ignored.
+//! File names cannot contain whitespace, and are matched against the file name of each path of
+//! the stack, so a sidecar holding bare file names keeps working wherever the stack is moved.
+
+use crate::error::ReadFailure;
+use crate::Error;
+use std::path::Path;
+use std::time::Duration;
+
+/// Exposure and gain of a single frame of a [`Sidecar`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SidecarEntry {
+    /// File name of the frame, as written in the sidecar
+    pub file_name: String,
+    /// Exposure time
+    pub exposure: Duration,
+    /// Gain
+    pub gain: f32,
+}
+
+/// Exposures and gains of the frames of a stack, parsed from a sidecar file. See the
+/// [module documentation](self) for the format.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sidecar {
+    entries: Vec<SidecarEntry>,
+}
+
+impl Sidecar {
+    /// Parse a sidecar from its text. See the [module documentation](self) for the format.
+    ///
+    /// # Errors
+    ///
+    /// - If a line doesn't hold a file name followed by one or two values
+    /// - If an exposure time is not a positive duration
+    /// - If a gain is not a finite, positive and non-zero number
+    /// - If a file name appears more than once
+    pub fn from_text(text: &str) -> Result<Self, Error> {
+        let error = |number: usize, message: String| Error::InputError {
+            parameter_name: "sidecar".to_string(),
+            message: format!("Line {number}: {message}"),
+        };
+
+        let mut entries = Vec::<SidecarEntry>::new();
+
+        for (number, line) in text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let (file_name, exposure, gain) = match fields[..] {
+                [file_name, exposure] => (file_name, exposure, None),
+                [file_name, exposure, gain] => (file_name, exposure, Some(gain)),
+                _ => {
+                    return Err(error(
+                        number,
+                        format!("Expected a file name, an exposure and a gain, got \"{line}\""),
+                    ))
+                }
+            };
+
+            let exposure = parse_exposure(exposure).ok_or_else(|| {
+                error(
+                    number,
+                    format!("Exposure must be a positive number of seconds, got \"{exposure}\""),
+                )
+            })?;
+
+            let gain = match gain {
+                Some(gain) => gain
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|gain| gain.is_finite() && *gain > 0.)
+                    .ok_or_else(|| {
+                        error(
+                            number,
+                            format!("Gain must be a valid positive and non-zero floating point number, got \"{gain}\""),
+                        )
+                    })?,
+                None => 1.,
+            };
+
+            if entries.iter().any(|entry| entry.file_name == file_name) {
+                return Err(error(
+                    number,
+                    format!("File name {file_name} appears more than once"),
+                ));
+            }
+
+            entries.push(SidecarEntry {
+                file_name: file_name.to_string(),
+                exposure,
+                gain,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Read a sidecar from a file, see [`Sidecar::from_text`].
+    ///
+    /// # Errors
+    ///
+    /// - If the file cannot be read
+    /// - If the file doesn't hold a valid sidecar
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_text(&std::fs::read_to_string(path)?)
+    }
+
+    /// Get the entries of the sidecar, in the order of its lines
+    #[must_use]
+    pub fn entries(&self) -> &[SidecarEntry] {
+        &self.entries
+    }
+
+    /// Find the entry of the given path, matching its file name.
+    #[must_use]
+    pub fn entry(&self, path: impl AsRef<Path>) -> Option<&SidecarEntry> {
+        let file_name = path.as_ref().file_name()?;
+
+        self.entries
+            .iter()
+            .find(|entry| Path::new(&entry.file_name).file_name() == Some(file_name))
+    }
+
+    /// Look up the exposure and gain of every path, in the order of the paths. Entries that don't
+    /// match any path are ignored. The result can be passed to
+    /// [`crate::hdr_merge_paths_with_overrides`].
+    ///
+    /// returns: `Result<(Vec<Duration>, Vec<f32>), Error>` with the exposures and gains
+    ///
+    /// # Errors
+    ///
+    /// - If any of the paths has no entry, listing every such path
+    pub fn exposures_and_gains<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+    ) -> Result<(Vec<Duration>, Vec<f32>), Error> {
+        let mut exposures = Vec::with_capacity(paths.len());
+        let mut gains = Vec::with_capacity(paths.len());
+        let mut failures = Vec::new();
+
+        for (index, path) in paths.iter().enumerate() {
+            match self.entry(path) {
+                Some(entry) => {
+                    exposures.push(entry.exposure);
+                    gains.push(entry.gain);
+                }
+                None => failures.push(ReadFailure {
+                    index,
+                    path: Some(path.as_ref().to_path_buf()),
+                    error: Error::InputError {
+                        parameter_name: "sidecar".to_string(),
+                        message: "The sidecar has no entry for this file".to_string(),
+                    },
+                }),
+            }
+        }
+
+        if failures.is_empty() {
+            Ok((exposures, gains))
+        } else {
+            Err(Error::ReadErrors(failures))
+        }
+    }
+}
+
+/// Parse an exposure time in seconds written as a decimal number or as a `/` separated fraction.
+fn parse_exposure(text: &str) -> Option<Duration> {
+    let seconds = match text.split_once('/') {
+        Some((numerator, denominator)) => {
+            numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?
+        }
+        None => text.parse::<f64>().ok()?,
+    };
+
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|exposure| !exposure.is_zero())
+}
@@ -0,0 +1,235 @@
+//! Streaming merge, to which frames are added one at a time from the caller's own loop, e.g. for
+//! live capture or memory constrained pipelines.
+//!
+//! An [`Accumulator`] weights each frame like [`crate::hdr_merge_images_with_config`], but only
+//! keeps the running sums of the estimate in memory, so each frame can be dropped as soon as it
+//! has been pushed. Steps that need every frame at once, i.e. deghosting, sorting by exposure,
+//! resizing and dropping frames with extreme clipping, are not supported. This is what [`crate::builder::MergeBuilder::low_memory`] uses to merge
+//! files.
+
+use crate::config::{ExtremeFrameHandling, MergeConfig};
+use crate::error::ReadFailure;
+use crate::frame::Frame;
+use crate::input::{self, HDRInput};
+use crate::output::HDRImage;
+use crate::poisson::{self, Estimate};
+use crate::{denoise, validate_exposure_spread, validate_image_count, validate_input, Error};
+use std::path::Path;
+
+/// Running HDR merge of frames pushed one at a time. See the [module documentation](self).
+///
+/// Pushing a frame takes `&mut self` and processes its pixels in parallel on rayon's global
+/// thread pool, so frames are added one after another. To push frames from several threads,
+/// share the accumulator behind a [`std::sync::Mutex`]. The merged radiance doesn't depend on
+/// the order the frames are pushed in, up to floating point rounding.
+pub struct Accumulator {
+    config: MergeConfig,
+    /// Running estimate, along with the shape of the first frame which every other frame must
+    /// match
+    estimate: Option<(poisson::Accumulator, (usize, usize, usize))>,
+    frames: usize,
+    exposure_range: (f32, f32),
+}
+
+impl Accumulator {
+    /// Create an empty accumulator merging with the given configuration.
+    ///
+    /// # Errors
+    /// - If the configuration is invalid
+    /// - If the configuration enables deghosting, sorting by exposure, resizing or
+    ///   [`ExtremeFrameHandling::Drop`], which need every frame at once
+    pub fn new(config: &MergeConfig) -> Result<Self, Error> {
+        config.validate()?;
+
+        if config.deghost
+            || config.sort_by_exposure
+            || config.resize_filter.is_some()
+            || config.extreme_frames == ExtremeFrameHandling::Drop
+        {
+            return Err(Error::InputError {
+                parameter_name: "config".to_string(),
+                message: "Deghosting, sorting by exposure, resizing and dropping clipped frames need every frame at once and are not supported when streaming frames".to_string(),
+            });
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            estimate: None,
+            frames: 0,
+            exposure_range: (f32::INFINITY, 0.),
+        })
+    }
+
+    /// Number of frames pushed so far
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    /// Whether no frame has been pushed yet
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// Add a frame to the merge. The first frame sets the dimensions and channel layout that every
+    /// other frame must have. Frames that fail are not added, and the accumulator can still be
+    /// used.
+    ///
+    /// # Errors
+    /// - If the frame has different dimensions than the first frame
+    /// - If the frame is grayscale while the first frame is a color image, or vice versa
+    /// - If an exposure or gain is not a finite positive number.
+    /// - If the coefficients or saturation thresholds don't apply to the number of channels of the
+    ///   frame
+    /// - If the crop region doesn't lie within the frame.
+    pub fn push(&mut self, input: HDRInput) -> Result<(), Error> {
+        self.push_at(self.frames, input, None)
+    }
+
+    /// Convert a [`Frame`] with [`HDRInput::try_from`] and add it to the merge, see
+    /// [`Accumulator::push`].
+    ///
+    /// # Errors
+    /// - If the frame cannot be converted
+    /// - If the frame cannot be added, see [`Accumulator::push`]
+    pub fn push_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        self.push(HDRInput::try_from(frame)?)
+    }
+
+    /// Add the frame at `index` of the stack, read from `path` if it was read from a file.
+    pub(crate) fn push_at(
+        &mut self,
+        index: usize,
+        mut input: HDRInput,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        // The first frame only becomes the reference once it has been added, so that a frame
+        // that is rejected doesn't decide the shape of the frames that follow.
+        let mut first = None;
+        let (estimate, shape) = if let Some((estimate, shape)) = &mut self.estimate {
+            (estimate, *shape)
+        } else {
+            let shape = input.get_buffer().dim();
+
+            (
+                first.insert(poisson::Accumulator::new(shape, &self.config)),
+                shape,
+            )
+        };
+
+        if let Some(error) = input::channel_layout_mismatch(shape.2, input.get_buffer().dim().2) {
+            return Err(Error::ReadErrors(vec![ReadFailure {
+                index,
+                path: path.map(Path::to_path_buf),
+                error,
+            }]));
+        }
+
+        validate_input(index, &input, shape)?;
+
+        if let Some(threshold) = self.config.hot_pixel_threshold {
+            input.correct_hot_pixels(threshold);
+        }
+
+        let exposure = input.scaling_factor(&self.config)?;
+        estimate.add(&mut input)?;
+
+        if let Some(estimate) = first {
+            self.estimate = Some((estimate, shape));
+        }
+
+        self.exposure_range = (
+            self.exposure_range.0.min(exposure),
+            self.exposure_range.1.max(exposure),
+        );
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Finish the merge, normalising the accumulated sums into the merged image. This applies
+    /// [`MergeConfig::non_finite`], [`MergeConfig::denoise_strength`] and
+    /// [`MergeConfig::alpha`] like [`crate::hdr_merge_images_with_config`].
+    ///
+    /// # Errors
+    /// - If fewer than two frames were pushed
+    /// - If the exposures are not spread enough, see [`MergeConfig::min_exposure_ratio`]
+    /// - If the result is not finite and [`MergeConfig::non_finite`] asks for an error
+    pub fn finalize(self) -> Result<HDRImage, Error> {
+        validate_image_count(self.frames)?;
+
+        if let Some(min_ratio) = self.config.min_exposure_ratio {
+            validate_exposure_spread(self.exposure_range, min_ratio)?;
+        }
+
+        let Some((estimate, _)) = self.estimate else {
+            unreachable!("The image count was validated")
+        };
+
+        let Estimate { mut phi, alpha, .. } = estimate.finish();
+        self.config.non_finite.apply(&mut phi)?;
+
+        if let Some(strength) = self.config.denoise_strength {
+            denoise::denoise_buffer(phi.view_mut(), strength);
+        }
+
+        Ok(HDRImage::with_alpha(
+            phi,
+            alpha,
+            self.config.premultiply_alpha,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array3;
+    use std::time::Duration;
+
+    fn frame(size: usize, exposure: f32) -> HDRInput {
+        HDRInput::with_buffer(
+            Array3::from_elem((size, size, 3), 0.25 * exposure),
+            Duration::from_secs_f32(exposure),
+            1.,
+        )
+        .expect("The frame is valid")
+    }
+
+    #[test]
+    fn rejected_first_frame_does_not_become_the_reference() {
+        let config = MergeConfig {
+            crop: Some((0, 0, 4, 4)),
+            ..MergeConfig::default()
+        };
+        let mut accumulator = Accumulator::new(&config).expect("The configuration is valid");
+
+        assert!(
+            accumulator.push(frame(2, 1.)).is_err(),
+            "The crop region doesn't fit in the frame"
+        );
+        assert!(accumulator.is_empty());
+
+        accumulator.push(frame(8, 1.)).expect("The frame is valid");
+        accumulator
+            .push(frame(8, 2.))
+            .expect("The frame matches the first one");
+
+        let merged = accumulator.finalize().expect("Two frames were pushed");
+        assert_eq!(merged.get_buffer().dim(), (4, 4, 3));
+    }
+
+    #[test]
+    fn dropping_clipped_frames_is_rejected() {
+        let config = MergeConfig {
+            extreme_frames: ExtremeFrameHandling::Drop,
+            ..MergeConfig::default()
+        };
+
+        assert!(matches!(
+            Accumulator::new(&config),
+            Err(Error::InputError { parameter_name, .. }) if parameter_name == "config"
+        ));
+    }
+}
@@ -1,9 +1,9 @@
 //! Apply basic histogram stretch to a linear image to make it viewable.
 
 use crate::extensions::NDArrayBuffer;
+use crate::parallel::prelude::*;
 use crate::Error;
 use image::DynamicImage;
-use rayon::prelude::*;
 
 fn scale_pixel(pixel: f32, min: f32, max: f32) -> f32 {
     (pixel - min) * (1. / (max - min))
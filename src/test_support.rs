@@ -0,0 +1,86 @@
+//! Helpers shared by the unit tests of several modules, to build EXIF metadata and encoded images
+//! in memory.
+
+use exif::{Exif, Field, In, Rational, Tag, Value};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageEncoder};
+
+/// Field of the primary image with the given tag and value
+pub(crate) fn field(tag: Tag, value: Value) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value,
+    }
+}
+
+/// `ExposureTime` of `numerator / denominator` seconds and a `PhotographicSensitivity` of `iso`
+pub(crate) fn exposure_fields(numerator: u32, denominator: u32, iso: u16) -> Vec<Field> {
+    vec![
+        field(
+            Tag::ExposureTime,
+            Value::Rational(vec![Rational::from((numerator, denominator))]),
+        ),
+        field(Tag::PhotographicSensitivity, Value::Short(vec![iso])),
+    ]
+}
+
+/// Encode the fields into an EXIF blob, as embedded into image files.
+pub(crate) fn exif_blob(fields: &[Field]) -> Vec<u8> {
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    writer
+        .write(&mut buffer, false)
+        .expect("The fields can be encoded");
+
+    buffer.into_inner()
+}
+
+/// Encode the fields and parse them back, like the metadata read from a file.
+pub(crate) fn exif(fields: &[Field]) -> Exif {
+    exif::Reader::new()
+        .read_raw(exif_blob(fields))
+        .expect("The blob can be parsed")
+}
+
+/// Encode the image as a JPEG with the fields embedded as EXIF metadata.
+pub(crate) fn jpeg(image: &DynamicImage, fields: &[Field]) -> Vec<u8> {
+    let mut data = Vec::new();
+    encode(image, JpegEncoder::new_with_quality(&mut data, 100), fields);
+
+    data
+}
+
+fn encode(image: &DynamicImage, mut encoder: impl ImageEncoder, fields: &[Field]) {
+    if !fields.is_empty() {
+        encoder
+            .set_exif_metadata(exif_blob(fields))
+            .expect("The encoder supports EXIF metadata");
+    }
+
+    image
+        .write_with_encoder(encoder)
+        .expect("The image can be encoded");
+}
+
+/// Deterministic source of uniformly distributed noise, so that tests with noisy frames always
+/// see the same values.
+pub(crate) struct Noise(u32);
+
+impl Noise {
+    pub(crate) fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    /// Next value, uniformly distributed in `[0, 1)`
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -0,0 +1,1067 @@
+//! Tone mapping operators to compress linear HDR radiance into a displayable range.
+
+use crate::color::encode_srgb;
+use crate::output::{HDRImage, LuminanceHistogram};
+use crate::parallel::prelude::*;
+use crate::Error;
+use image::{DynamicImage, ImageBuffer, Luma, Rgb};
+use ndarray::{Array2, Array3, ArrayView1, Axis, Zip};
+
+/// Default key value used by [`tone_map_reinhard`]. This maps the log-average luminance of the
+/// scene to middle grey.
+pub const DEFAULT_REINHARD_KEY: f32 = 0.18;
+
+/// Small offset to avoid taking the logarithm of zero for black pixels.
+const LUMINANCE_DELTA: f64 = 1e-6;
+
+/// Default contrast of the base layer used by [`tone_map_durand`], i.e. the ratio between the
+/// brightest and darkest large scale features of the result.
+pub const DEFAULT_DURAND_CONTRAST: f32 = 5.;
+
+/// Default range sigma used by [`tone_map_durand`], in log10 luminance units.
+pub const DEFAULT_DURAND_SIGMA_RANGE: f32 = 0.4;
+
+/// Default spatial sigma used by [`tone_map_durand`], relative to the larger dimension of the
+/// image.
+pub const DEFAULT_DURAND_SIGMA_SPATIAL_RATIO: f32 = 0.02;
+
+/// Default exposure used by [`tone_map_aces_filmic`], leaving the log-average luminance of the
+/// scene at middle grey.
+pub const DEFAULT_ACES_EXPOSURE: f32 = 1.;
+
+/// Default bias used by [`tone_map_drago`], controlling how fast the log base of the adaptive
+/// logarithmic curve grows with luminance.
+pub const DEFAULT_DRAGO_BIAS: f32 = 0.85;
+
+/// Default percentile of luminance mapped to black by [`tone_map_auto`].
+pub const DEFAULT_AUTO_BLACK_PERCENTILE: f32 = 1.;
+
+/// Default percentile of luminance mapped to white by [`tone_map_auto`].
+pub const DEFAULT_AUTO_WHITE_PERCENTILE: f32 = 99.;
+
+/// Number of log luminance bins used to find percentiles.
+const PERCENTILE_BINS: usize = 4096;
+
+/// Number of empty cells around the bilateral grid, so that blurring and interpolating never
+/// reads outside of it.
+const GRID_PADDING: usize = 2;
+
+/// Number of rows accumulated into each partial bilateral grid. The partial grids are summed in
+/// order, so the result doesn't depend on how the work is split between threads.
+const GRID_CHUNK_ROWS: usize = 64;
+
+/// Relative luminance of a pixel with Rec. 709 primaries. Grayscale pixels are returned as is.
+pub(crate) fn luminance(pixel: ArrayView1<f32>) -> f32 {
+    if pixel.len() < 3 {
+        pixel[0]
+    } else {
+        0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+    }
+}
+
+/// Geometric mean of the luminance of all pixels in the buffer.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+fn log_average_luminance(buffer: &Array3<f32>) -> f32 {
+    let (height, width, _) = buffer.dim();
+
+    let sum: f64 = buffer
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .map(|row| {
+            row.outer_iter()
+                .map(|pixel| (LUMINANCE_DELTA + f64::from(luminance(pixel).max(0.))).ln())
+                .sum::<f64>()
+        })
+        .collect::<Vec<f64>>()
+        .into_iter()
+        .sum();
+
+    (sum / (height * width).max(1) as f64).exp() as f32
+}
+
+/// Number of bits per channel of a tone mapped image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    /// 8 bits per channel, as used by most displays and formats like JPEG.
+    #[default]
+    Eight,
+    /// 16 bits per channel, for formats like PNG and TIFF. This avoids banding when the result is
+    /// edited further.
+    Sixteen,
+}
+
+/// A tone mapping operator along with its parameters, see [`tone_map`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// See [`tone_map_reinhard_with_key`].
+    Reinhard {
+        /// Target brightness of the log-average luminance
+        key: f32,
+    },
+    /// See [`tone_map_reinhard_luminance_with_key`].
+    ReinhardLuminance {
+        /// Target brightness of the log-average luminance
+        key: f32,
+    },
+    /// See [`tone_map_aces_filmic_with_exposure`].
+    AcesFilmic {
+        /// Multiplier applied to the radiance after its log-average luminance is scaled to
+        /// middle grey
+        exposure: f32,
+    },
+    /// See [`tone_map_drago_with_bias`].
+    Drago {
+        /// Shape of the curve
+        bias: f32,
+    },
+    /// See [`tone_map_auto_with_percentiles`].
+    Auto {
+        /// Percentile of luminance mapped to black
+        black_percentile: f32,
+        /// Percentile of luminance mapped to white
+        white_percentile: f32,
+    },
+    /// See [`tone_map_durand_with_parameters`].
+    Durand {
+        /// Ratio between the brightest and darkest parts of the base layer
+        contrast: f32,
+        /// Spatial extent of the bilateral filter, in pixels. `None` uses
+        /// [`DEFAULT_DURAND_SIGMA_SPATIAL_RATIO`] times the larger dimension of the image.
+        sigma_spatial: Option<f32>,
+        /// Edge threshold of the bilateral filter, in log10 luminance units
+        sigma_range: f32,
+    },
+}
+
+impl Default for ToneMapOperator {
+    fn default() -> Self {
+        Self::Reinhard {
+            key: DEFAULT_REINHARD_KEY,
+        }
+    }
+}
+
+/// An operator turning an [`HDRImage`] into a displayable image, see
+/// [`crate::merge_and_tonemap`]. The built-in global operators are provided as [`Reinhard`],
+/// [`Drago`] and [`AcesFilmic`], and custom operators can be written by implementing this trait
+/// for types of their own, e.g. to tone map with parameters that depend on the image.
+pub trait ToneMapper {
+    /// Tone map the merged HDR image into a displayable image.
+    fn map(&self, hdr: &HDRImage) -> DynamicImage;
+}
+
+/// The global Reinhard operator as a [`ToneMapper`], see [`tone_map_reinhard_with_key`]. The
+/// default uses [`DEFAULT_REINHARD_KEY`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reinhard {
+    key: f32,
+}
+
+impl Reinhard {
+    /// Create new [`Reinhard`] operator
+    ///
+    /// # Errors
+    ///
+    /// - If `key` is not a finite positive number
+    pub fn new(key: f32) -> Result<Self, Error> {
+        validate_key(key)?;
+
+        Ok(Self { key })
+    }
+
+    /// Get the target brightness of the log-average luminance
+    #[must_use]
+    pub fn key(&self) -> f32 {
+        self.key
+    }
+}
+
+impl Default for Reinhard {
+    fn default() -> Self {
+        Self {
+            key: DEFAULT_REINHARD_KEY,
+        }
+    }
+}
+
+impl ToneMapper for Reinhard {
+    fn map(&self, hdr: &HDRImage) -> DynamicImage {
+        tone_map_reinhard_with_key(hdr, self.key)
+    }
+}
+
+/// The Drago adaptive logarithmic operator as a [`ToneMapper`], see [`tone_map_drago_with_bias`].
+/// The default uses [`DEFAULT_DRAGO_BIAS`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Drago {
+    bias: f32,
+}
+
+impl Drago {
+    /// Create new [`Drago`] operator
+    ///
+    /// # Errors
+    ///
+    /// - If `bias` is not a number greater than `0.0` and at most `1.0`
+    pub fn new(bias: f32) -> Result<Self, Error> {
+        validate_bias(bias)?;
+
+        Ok(Self { bias })
+    }
+
+    /// Get the shape of the curve
+    #[must_use]
+    pub fn bias(&self) -> f32 {
+        self.bias
+    }
+}
+
+impl Default for Drago {
+    fn default() -> Self {
+        Self {
+            bias: DEFAULT_DRAGO_BIAS,
+        }
+    }
+}
+
+impl ToneMapper for Drago {
+    fn map(&self, hdr: &HDRImage) -> DynamicImage {
+        to_dynamic_image(&drago(hdr, self.bias))
+    }
+}
+
+/// The ACES filmic curve as a [`ToneMapper`], see [`tone_map_aces_filmic_with_exposure`]. The
+/// default uses [`DEFAULT_ACES_EXPOSURE`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AcesFilmic {
+    exposure: f32,
+}
+
+impl AcesFilmic {
+    /// Create new [`AcesFilmic`] operator
+    ///
+    /// # Errors
+    ///
+    /// - If `exposure` is not a finite positive number
+    pub fn new(exposure: f32) -> Result<Self, Error> {
+        validate_exposure(exposure)?;
+
+        Ok(Self { exposure })
+    }
+
+    /// Get the multiplier applied to the scaled radiance
+    #[must_use]
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+}
+
+impl Default for AcesFilmic {
+    fn default() -> Self {
+        Self {
+            exposure: DEFAULT_ACES_EXPOSURE,
+        }
+    }
+}
+
+impl ToneMapper for AcesFilmic {
+    fn map(&self, hdr: &HDRImage) -> DynamicImage {
+        to_dynamic_image(&aces_filmic(hdr, self.exposure))
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn quantize_u8(value: f32) -> u8 {
+    (value.clamp(0., 1.) * f32::from(u8::MAX)).round() as u8
+}
+
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn quantize_u16(value: f32) -> u16 {
+    (value.clamp(0., 1.) * f32::from(u16::MAX)).round() as u16
+}
+
+/// Convert a buffer of linear values in `[0, 1]` into an sRGB encoded 8-bit image. Single channel
+/// buffers produce a grayscale image, everything else produces an RGB image.
+fn to_dynamic_image(buffer: &Array3<f32>) -> DynamicImage {
+    quantize_to_dynamic_image(buffer, encode_srgb, BitDepth::Eight)
+}
+
+/// Convert a buffer of values in `[0, 1]` into an image of the given bit depth, applying
+/// `transfer` to each value before quantization. Values are clamped to `[0, 1]`, scaled to the
+/// largest value of the bit depth and rounded to the nearest integer. Buffers with fewer than
+/// three channels produce a grayscale image of their first channel, everything else produces an
+/// RGB image of the first three channels.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn quantize_to_dynamic_image(
+    buffer: &Array3<f32>,
+    transfer: impl Fn(f32) -> f32,
+    bit_depth: BitDepth,
+) -> DynamicImage {
+    let (height, width, channels) = buffer.dim();
+    let value =
+        |y: u32, x: u32, channel: usize| transfer(buffer[[y as usize, x as usize, channel]]);
+    let (width, height) = (width as u32, height as u32);
+
+    match (bit_depth, channels) {
+        (BitDepth::Eight, channels) if channels < 3 => {
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| {
+                Luma([quantize_u8(value(y, x, 0))])
+            }))
+        }
+        (BitDepth::Eight, _) => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+                Rgb([0, 1, 2].map(|channel| quantize_u8(value(y, x, channel))))
+            }))
+        }
+        (BitDepth::Sixteen, channels) if channels < 3 => {
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| {
+                Luma([quantize_u16(value(y, x, 0))])
+            }))
+        }
+        (BitDepth::Sixteen, _) => {
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(width, height, |x, y| {
+                Rgb([0, 1, 2].map(|channel| quantize_u16(value(y, x, channel))))
+            }))
+        }
+    }
+}
+
+/// Tone map an HDR image with the given operator and produce an sRGB encoded image of the given
+/// bit depth.
+///
+/// Every operator produces linear values in `[0, 1]`, which are sRGB encoded, scaled to `255` or
+/// `65535` and rounded to the nearest integer. A 16-bit result keeps the precision of the tone
+/// mapping, which is worth it when saving to PNG or TIFF. The other tone mapping functions of this
+/// module produce the same result as an 8-bit image.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `operator`: The tone mapping operator and its parameters
+/// * `bit_depth`: Number of bits per channel of the result
+///
+/// returns: `Result<DynamicImage, Error>`
+///
+/// # Errors
+///
+/// - If a Reinhard `key` is not a finite positive number
+/// - If the parameters of the operator are invalid, see [`tone_map_aces_filmic_with_exposure`],
+///   [`tone_map_drago_with_bias`], [`tone_map_auto_with_percentiles`] and
+///   [`tone_map_durand_with_parameters`]
+pub fn tone_map(
+    hdr: &HDRImage,
+    operator: ToneMapOperator,
+    bit_depth: BitDepth,
+) -> Result<DynamicImage, Error> {
+    let buffer = match operator {
+        ToneMapOperator::Reinhard { key } => {
+            validate_key(key)?;
+            reinhard(hdr, key)
+        }
+        ToneMapOperator::ReinhardLuminance { key } => {
+            validate_key(key)?;
+            reinhard_luminance(hdr, key)
+        }
+        ToneMapOperator::AcesFilmic { exposure } => {
+            validate_exposure(exposure)?;
+            aces_filmic(hdr, exposure)
+        }
+        ToneMapOperator::Drago { bias } => {
+            validate_bias(bias)?;
+            drago(hdr, bias)
+        }
+        ToneMapOperator::Auto {
+            black_percentile,
+            white_percentile,
+        } => {
+            validate_percentiles(black_percentile, white_percentile)?;
+            auto(hdr, black_percentile, white_percentile)
+        }
+        ToneMapOperator::Durand {
+            contrast,
+            sigma_spatial,
+            sigma_range,
+        } => {
+            let sigma_spatial = sigma_spatial.unwrap_or_else(|| default_sigma_spatial(hdr));
+            validate_durand_parameters(contrast, sigma_spatial, sigma_range)?;
+            durand(hdr, contrast, sigma_spatial, sigma_range)
+        }
+    };
+
+    Ok(quantize_to_dynamic_image(&buffer, encode_srgb, bit_depth))
+}
+
+fn validate_key(key: f32) -> Result<(), Error> {
+    if !key.is_finite() || key <= 0. {
+        return Err(Error::InputError {
+            parameter_name: "key".to_string(),
+            message: format!("Key must be a finite positive number, got {key}"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Tone map an HDR image with the global Reinhard operator using [`DEFAULT_REINHARD_KEY`].
+///
+/// See [`tone_map_reinhard_with_key`] for details.
+#[must_use]
+pub fn tone_map_reinhard(hdr: &HDRImage) -> DynamicImage {
+    tone_map_reinhard_with_key(hdr, DEFAULT_REINHARD_KEY)
+}
+
+/// Tone map an HDR image with the global Reinhard operator `L_out = L / (1 + L)`.
+///
+/// The image is first scaled so that its log-average luminance maps to `key`, after which the
+/// operator is applied to each channel independently. Per-channel compression desaturates bright
+/// colors slightly, but never pushes any channel out of range.
+///
+/// The result is an sRGB encoded 8-bit image, ready to be saved or displayed. RGB input produces
+/// an RGB image and grayscale input produces a grayscale image.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `key`: Target brightness of the log-average luminance. Must be positive. Higher values
+///   produce brighter results.
+///
+/// returns: `DynamicImage`
+#[must_use]
+pub fn tone_map_reinhard_with_key(hdr: &HDRImage, key: f32) -> DynamicImage {
+    to_dynamic_image(&reinhard(hdr, key))
+}
+
+/// Per-channel Reinhard operator, producing linear values in `[0, 1]`.
+fn reinhard(hdr: &HDRImage, key: f32) -> Array3<f32> {
+    let mut buffer = hdr.get_buffer().clone();
+    let scale = key / log_average_luminance(&buffer);
+
+    buffer.par_mapv_inplace(|value| {
+        let scaled = value.max(0.) * scale;
+
+        scaled / (1. + scaled)
+    });
+
+    buffer
+}
+
+/// Tone map an HDR image with the global Reinhard operator applied to luminance, using
+/// [`DEFAULT_REINHARD_KEY`].
+///
+/// See [`tone_map_reinhard_luminance_with_key`] for details.
+#[must_use]
+pub fn tone_map_reinhard_luminance(hdr: &HDRImage) -> DynamicImage {
+    tone_map_reinhard_luminance_with_key(hdr, DEFAULT_REINHARD_KEY)
+}
+
+/// Tone map an HDR image with the global Reinhard operator `L_out = L / (1 + L)` applied to the
+/// luminance of each pixel.
+///
+/// The luminance `Y = 0.2126 R + 0.7152 G + 0.0722 B` is scaled so that its log-average maps to
+/// `key` and then compressed, after which every channel is multiplied by `Y_out / Y_in`. This
+/// preserves the ratios between channels, and therefore hue, unlike
+/// [`tone_map_reinhard_with_key`]. Very bright saturated colors may have individual channels
+/// clipped to white. Black pixels stay black.
+///
+/// The result is an sRGB encoded 8-bit image, ready to be saved or displayed. RGB input produces
+/// an RGB image and grayscale input produces a grayscale image.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `key`: Target brightness of the log-average luminance. Must be positive. Higher values
+///   produce brighter results.
+///
+/// returns: `DynamicImage`
+#[must_use]
+pub fn tone_map_reinhard_luminance_with_key(hdr: &HDRImage, key: f32) -> DynamicImage {
+    to_dynamic_image(&reinhard_luminance(hdr, key))
+}
+
+/// Luminance based Reinhard operator, producing linear values in `[0, 1]`.
+fn reinhard_luminance(hdr: &HDRImage, key: f32) -> Array3<f32> {
+    let mut buffer = hdr.get_buffer().clone();
+    let scale = key / log_average_luminance(&buffer);
+
+    Zip::from(buffer.lanes_mut(Axis(2))).par_for_each(|mut pixel| {
+        let luminance_in = luminance(pixel.view()).max(0.) * scale;
+        if luminance_in <= 0. {
+            pixel.fill(0.);
+            return;
+        }
+
+        let luminance_out = luminance_in / (1. + luminance_in);
+        let ratio = luminance_out / luminance_in * scale;
+
+        pixel.mapv_inplace(|value| value.max(0.) * ratio);
+    });
+
+    buffer
+}
+
+/// Tone map an HDR image with the ACES filmic curve using [`DEFAULT_ACES_EXPOSURE`].
+///
+/// See [`tone_map_aces_filmic_with_exposure`] for details.
+#[must_use]
+pub fn tone_map_aces_filmic(hdr: &HDRImage) -> DynamicImage {
+    to_dynamic_image(&aces_filmic(hdr, DEFAULT_ACES_EXPOSURE))
+}
+
+/// Tone map an HDR image with the approximation of the ACES reference rendering and output
+/// transforms from
+/// [ACES Filmic Tone Mapping Curve](https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/)
+/// by Narkowicz:
+///
+/// `L_out = L * (2.51 * L + 0.03) / (L * (2.43 * L + 0.59) + 0.14)`
+///
+/// The image is first scaled so that its log-average luminance maps to middle grey, i.e.
+/// [`DEFAULT_REINHARD_KEY`], and then by `exposure`, after which the curve is applied to each
+/// channel independently. Its toe deepens shadows, and its shoulder rolls highlights off
+/// smoothly and desaturates them towards white the way film does, which suits photographic
+/// content better than the Reinhard operators. The curve reaches white for a scaled radiance of
+/// about `7.2`, brighter values are clipped.
+///
+/// The result is an sRGB encoded 8-bit image, ready to be saved or displayed. RGB input produces
+/// an RGB image and grayscale input produces a grayscale image.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `exposure`: Multiplier applied to the scaled radiance. Must be positive. `1.0` keeps the
+///   log-average luminance at middle grey, and `2.0` brightens the result by one stop.
+///
+/// returns: `Result<DynamicImage, Error>`
+///
+/// # Errors
+///
+/// - If `exposure` is not a finite positive number
+pub fn tone_map_aces_filmic_with_exposure(
+    hdr: &HDRImage,
+    exposure: f32,
+) -> Result<DynamicImage, Error> {
+    validate_exposure(exposure)?;
+
+    Ok(to_dynamic_image(&aces_filmic(hdr, exposure)))
+}
+
+fn validate_exposure(exposure: f32) -> Result<(), Error> {
+    if !exposure.is_finite() || exposure <= 0. {
+        return Err(Error::InputError {
+            parameter_name: "exposure".to_string(),
+            message: format!("Exposure must be a finite positive number, got {exposure}"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Per-channel ACES filmic curve with an already validated exposure, producing linear values in
+/// `[0, 1]`.
+fn aces_filmic(hdr: &HDRImage, exposure: f32) -> Array3<f32> {
+    let mut buffer = hdr.get_buffer().clone();
+    let scale = exposure * DEFAULT_REINHARD_KEY / log_average_luminance(&buffer);
+
+    buffer.par_mapv_inplace(|value| {
+        let scaled = value.max(0.) * scale;
+
+        (scaled * (2.51 * scaled + 0.03) / (scaled * (2.43 * scaled + 0.59) + 0.14)).clamp(0., 1.)
+    });
+
+    buffer
+}
+
+/// Tone map an HDR image with the Drago adaptive logarithmic operator using
+/// [`DEFAULT_DRAGO_BIAS`].
+///
+/// See [`tone_map_drago_with_bias`] for details.
+#[must_use]
+pub fn tone_map_drago(hdr: &HDRImage) -> DynamicImage {
+    to_dynamic_image(&drago(hdr, DEFAULT_DRAGO_BIAS))
+}
+
+/// Tone map an HDR image with the global operator from
+/// [Adaptive Logarithmic Mapping For Displaying High Contrast Scenes](https://resources.mpi-inf.mpg.de/tmo/logmap/logmap.pdf)
+/// by Drago et al.
+///
+/// Luminance is first divided by its log-average, and then compressed with a logarithm whose
+/// base varies smoothly from `2` for the darkest pixels to `10` for the brightest one:
+///
+/// `L_out = ln(L + 1) / (log10(L_max + 1) * ln(2 + 8 * (L / L_max)^(ln(bias) / ln(0.5))))`
+///
+/// The brightest pixel maps to white, and colors are restored by multiplying every channel by
+/// `L_out / L_in`, like [`tone_map_reinhard_luminance_with_key`]. This handles scenes with a very
+/// high dynamic range better than the Reinhard operators. Pixels without positive luminance map
+/// to black.
+///
+/// The result is an sRGB encoded 8-bit image, ready to be saved or displayed. RGB input produces
+/// an RGB image and grayscale input produces a grayscale image.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `bias`: Shape of the curve, between `0.0` and `1.0`. Lower values compress highlights less
+///   and produce darker results, higher values produce brighter results with less contrast.
+///   Values around `0.85` work for most scenes.
+///
+/// returns: `Result<DynamicImage, Error>`
+///
+/// # Errors
+///
+/// - If `bias` is not a number greater than `0.0` and at most `1.0`
+pub fn tone_map_drago_with_bias(hdr: &HDRImage, bias: f32) -> Result<DynamicImage, Error> {
+    validate_bias(bias)?;
+
+    Ok(to_dynamic_image(&drago(hdr, bias)))
+}
+
+fn validate_bias(bias: f32) -> Result<(), Error> {
+    if bias.is_nan() || bias <= 0. || bias > 1. {
+        return Err(Error::InputError {
+            parameter_name: "bias".to_string(),
+            message: format!("Bias must be greater than 0 and at most 1, got {bias}"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Drago operator with an already validated bias, producing linear values in `[0, 1]`.
+fn drago(hdr: &HDRImage, bias: f32) -> Array3<f32> {
+    let mut buffer = hdr.get_buffer().clone();
+    let scale = 1. / log_average_luminance(&buffer);
+
+    let max_luminance = buffer
+        .lanes(Axis(2))
+        .into_iter()
+        .map(|pixel| luminance(pixel) * scale)
+        .filter(|luminance| luminance.is_finite())
+        .fold(0_f32, f32::max);
+
+    if max_luminance <= 0. {
+        buffer.fill(0.);
+        return buffer;
+    }
+
+    let exponent = bias.ln() / 0.5_f32.ln();
+    let normalization = 1. / (max_luminance + 1.).log10();
+
+    Zip::from(buffer.lanes_mut(Axis(2))).par_for_each(|mut pixel| {
+        let luminance_in = luminance(pixel.view()) * scale;
+        if luminance_in.is_nan() || luminance_in <= 0. {
+            pixel.fill(0.);
+            return;
+        }
+
+        let base = 2. + 8. * (luminance_in / max_luminance).min(1.).powf(exponent);
+        let luminance_out = normalization * luminance_in.ln_1p() / base.ln();
+        let ratio = luminance_out / luminance_in * scale;
+
+        pixel.mapv_inplace(|value| value.max(0.) * ratio);
+    });
+
+    buffer
+}
+
+/// Tone map an HDR image with automatic black and white points at
+/// [`DEFAULT_AUTO_BLACK_PERCENTILE`] and [`DEFAULT_AUTO_WHITE_PERCENTILE`].
+///
+/// See [`tone_map_auto_with_percentiles`] for details.
+#[must_use]
+pub fn tone_map_auto(hdr: &HDRImage) -> DynamicImage {
+    to_dynamic_image(&auto(
+        hdr,
+        DEFAULT_AUTO_BLACK_PERCENTILE,
+        DEFAULT_AUTO_WHITE_PERCENTILE,
+    ))
+}
+
+/// Tone map an HDR image with black and white points taken from percentiles of its luminance.
+///
+/// The luminance at `black_percentile` is subtracted from every pixel, and the remainder is
+/// compressed with the extended Reinhard operator `L_out = L (1 + L / L_white²) / (1 + L)`, after
+/// scaling the image so that its log-average luminance maps to [`DEFAULT_REINHARD_KEY`]. The white
+/// point `L_white` is the luminance at `white_percentile`, which therefore maps to white. A few
+/// hot pixels or deep shadows thus don't dictate the tonal range, they are clipped instead. Colors
+/// are restored by multiplying every channel by `L_out / L_in`, like
+/// [`tone_map_reinhard_luminance_with_key`].
+///
+/// Percentiles are read from a histogram of log luminance with a fixed number of bins, which
+/// avoids sorting every pixel. Pixels with non-finite luminance are ignored. If both percentiles
+/// have the same luminance, e.g. for a uniform image, this falls back to
+/// [`tone_map_reinhard_luminance`].
+///
+/// The result is an sRGB encoded 8-bit image, ready to be saved or displayed. RGB input produces
+/// an RGB image and grayscale input produces a grayscale image.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `black_percentile`: Percentage of pixels, between `0.0` and `100.0`, darker than the black
+///   point.
+/// * `white_percentile`: Percentage of pixels, between `0.0` and `100.0`, darker than the white
+///   point. Must be greater than `black_percentile`.
+///
+/// returns: `Result<DynamicImage, Error>`
+///
+/// # Errors
+///
+/// - If a percentile is not between `0.0` and `100.0`
+/// - If `white_percentile` is not greater than `black_percentile`
+pub fn tone_map_auto_with_percentiles(
+    hdr: &HDRImage,
+    black_percentile: f32,
+    white_percentile: f32,
+) -> Result<DynamicImage, Error> {
+    validate_percentiles(black_percentile, white_percentile)?;
+
+    Ok(to_dynamic_image(&auto(
+        hdr,
+        black_percentile,
+        white_percentile,
+    )))
+}
+
+fn validate_percentiles(black_percentile: f32, white_percentile: f32) -> Result<(), Error> {
+    for (parameter_name, value) in [
+        ("black_percentile", black_percentile),
+        ("white_percentile", white_percentile),
+    ] {
+        if !(0. ..=100.).contains(&value) {
+            return Err(Error::InputError {
+                parameter_name: parameter_name.to_string(),
+                message: format!("Percentile must be between 0 and 100, got {value}"),
+            });
+        }
+    }
+
+    if white_percentile <= black_percentile {
+        return Err(Error::InputError {
+            parameter_name: "white_percentile".to_string(),
+            message: format!(
+                "White percentile must be greater than the black percentile {black_percentile}, got {white_percentile}"
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Automatic black and white point operator with already validated percentiles, producing linear
+/// values in `[0, 1]`.
+fn auto(hdr: &HDRImage, black_percentile: f32, white_percentile: f32) -> Array3<f32> {
+    let mut buffer = hdr.get_buffer().clone();
+    let histogram = LuminanceHistogram::new(&buffer, PERCENTILE_BINS);
+    let black = histogram.percentile(black_percentile);
+    let white = histogram.percentile(white_percentile);
+    let scale = DEFAULT_REINHARD_KEY / log_average_luminance(&buffer);
+
+    let white_point = (white - black) * scale;
+    if !white_point.is_finite() || white_point <= 0. {
+        // Every pixel between the percentiles has the same luminance, so there is no range to
+        // map to black and white.
+        return reinhard_luminance(hdr, DEFAULT_REINHARD_KEY);
+    }
+
+    let inverse_white_squared = 1. / (white_point * white_point);
+
+    Zip::from(buffer.lanes_mut(Axis(2))).par_for_each(|mut pixel| {
+        let luminance_in = luminance(pixel.view());
+        let scaled = (luminance_in - black) * scale;
+        if luminance_in.is_nan() || luminance_in <= 0. || scaled.is_nan() || scaled <= 0. {
+            pixel.fill(0.);
+            return;
+        }
+
+        let luminance_out =
+            (scaled * (1. + scaled * inverse_white_squared) / (1. + scaled)).min(1.);
+        let ratio = luminance_out / luminance_in;
+
+        pixel.mapv_inplace(|value| value.max(0.) * ratio);
+    });
+
+    buffer
+}
+
+/// Tone map an HDR image with the Durand and Dorsey local operator using
+/// [`DEFAULT_DURAND_CONTRAST`], [`DEFAULT_DURAND_SIGMA_RANGE`] and a spatial sigma of
+/// [`DEFAULT_DURAND_SIGMA_SPATIAL_RATIO`] times the larger dimension of the image.
+///
+/// See [`tone_map_durand_with_parameters`] for details.
+#[must_use]
+pub fn tone_map_durand(hdr: &HDRImage) -> DynamicImage {
+    to_dynamic_image(&durand(
+        hdr,
+        DEFAULT_DURAND_CONTRAST,
+        default_sigma_spatial(hdr),
+        DEFAULT_DURAND_SIGMA_RANGE,
+    ))
+}
+
+/// Spatial sigma of [`DEFAULT_DURAND_SIGMA_SPATIAL_RATIO`] times the larger dimension of the image.
+#[allow(clippy::cast_precision_loss)]
+fn default_sigma_spatial(hdr: &HDRImage) -> f32 {
+    (hdr.get_width().max(hdr.get_height()) as f32 * DEFAULT_DURAND_SIGMA_SPATIAL_RATIO).max(1.)
+}
+
+/// Tone map an HDR image with the local operator from
+/// [Fast Bilateral Filtering for the Display of High-Dynamic-Range Images](https://people.csail.mit.edu/fredo/PUBLI/Siggraph2002/DurandBilateral.pdf)
+/// by Durand and Dorsey.
+///
+/// The log10 luminance of the image is split into a base layer, obtained with an edge preserving
+/// bilateral filter, and a detail layer holding the remainder. Only the base layer is compressed
+/// to `contrast`, so local contrast and fine detail are kept while the overall dynamic range is
+/// reduced. The brightest part of the base layer maps to white, and colors are restored by
+/// multiplying every channel by `Y_out / Y_in`, like [`tone_map_reinhard_luminance_with_key`].
+///
+/// The bilateral filter is approximated with a bilateral grid, which makes its cost independent
+/// of `sigma_spatial`.
+///
+/// The result is an sRGB encoded 8-bit image, ready to be saved or displayed. RGB input produces
+/// an RGB image and grayscale input produces a grayscale image.
+///
+/// # Arguments
+///
+/// * `hdr`: The merged HDR image
+/// * `contrast`: Ratio between the brightest and darkest parts of the base layer in the result.
+///   Higher values keep more of the global contrast.
+/// * `sigma_spatial`: Spatial extent of the bilateral filter, in pixels. Larger values treat
+///   larger features as detail.
+/// * `sigma_range`: Edge threshold of the bilateral filter, in log10 luminance units. Luminance
+///   steps larger than this are considered edges and are not smoothed across.
+///
+/// returns: `Result<DynamicImage, Error>`
+///
+/// # Errors
+///
+/// - If `contrast` is not a finite number greater than `1.0`
+/// - If `sigma_spatial` or `sigma_range` is not a finite positive number
+pub fn tone_map_durand_with_parameters(
+    hdr: &HDRImage,
+    contrast: f32,
+    sigma_spatial: f32,
+    sigma_range: f32,
+) -> Result<DynamicImage, Error> {
+    validate_durand_parameters(contrast, sigma_spatial, sigma_range)?;
+
+    Ok(to_dynamic_image(&durand(
+        hdr,
+        contrast,
+        sigma_spatial,
+        sigma_range,
+    )))
+}
+
+fn validate_durand_parameters(
+    contrast: f32,
+    sigma_spatial: f32,
+    sigma_range: f32,
+) -> Result<(), Error> {
+    if !contrast.is_finite() || contrast <= 1. {
+        return Err(Error::InputError {
+            parameter_name: "contrast".to_string(),
+            message: format!("Contrast must be a finite number greater than 1, got {contrast}"),
+        });
+    }
+
+    for (parameter_name, value) in [
+        ("sigma_spatial", sigma_spatial),
+        ("sigma_range", sigma_range),
+    ] {
+        if !value.is_finite() || value <= 0. {
+            return Err(Error::InputError {
+                parameter_name: parameter_name.to_string(),
+                message: format!("Sigma must be a finite positive number, got {value}"),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Durand and Dorsey operator with already validated parameters, producing linear values in
+/// `[0, 1]`.
+#[allow(clippy::cast_possible_truncation)]
+fn durand(hdr: &HDRImage, contrast: f32, sigma_spatial: f32, sigma_range: f32) -> Array3<f32> {
+    let mut buffer = hdr.get_buffer().clone();
+    let (height, width, _) = buffer.dim();
+
+    let mut log_luminance = Array2::<f32>::zeros((height, width));
+    Zip::from(&mut log_luminance)
+        .and(buffer.lanes(Axis(2)))
+        .par_for_each(|log_luminance, pixel| {
+            *log_luminance = (LUMINANCE_DELTA + f64::from(luminance(pixel).max(0.))).log10() as f32;
+        });
+
+    let base = bilateral_filter(&log_luminance, sigma_spatial, sigma_range);
+    let (min, max) = base
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        });
+    let compression = if max > min {
+        contrast.log10() / (max - min)
+    } else {
+        1.
+    };
+
+    Zip::from(buffer.lanes_mut(Axis(2)))
+        .and(&log_luminance)
+        .and(&base)
+        .par_for_each(|mut pixel, log_luminance, base| {
+            let luminance_in = luminance(pixel.view()).max(0.);
+            if luminance_in <= 0. {
+                pixel.fill(0.);
+                return;
+            }
+
+            let detail = log_luminance - base;
+            let luminance_out = 10_f32.powf((base - max) * compression + detail);
+            let ratio = luminance_out / luminance_in;
+
+            pixel.mapv_inplace(|value| value.max(0.) * ratio);
+        });
+
+    buffer
+}
+
+/// Approximate a bilateral filter of `image` with a bilateral grid, as described in
+/// [Real-time Edge-Aware Image Processing with the Bilateral Grid](https://people.csail.mit.edu/jiawen/papers/bgrid.pdf).
+///
+/// Pixels are accumulated into a coarse 3D grid with one cell per `sigma_spatial` pixels and
+/// `sigma_range` value units, the grid is blurred, and the result is read back for each pixel
+/// with trilinear interpolation.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_sign_loss)]
+fn bilateral_filter(image: &Array2<f32>, sigma_spatial: f32, sigma_range: f32) -> Array2<f32> {
+    let (height, width) = image.dim();
+    let (min, max) = image
+        .iter()
+        .copied()
+        .filter(|value| value.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+
+    if height == 0 || width == 0 || min > max {
+        return image.clone();
+    }
+
+    let cells = |extent: f32, sigma: f32| (extent / sigma) as usize + 1 + 2 * GRID_PADDING;
+    let shape = (
+        cells((height - 1) as f32, sigma_spatial),
+        cells((width - 1) as f32, sigma_spatial),
+        cells(max - min, sigma_range),
+    );
+    let position = |y: usize, x: usize, value: f32| {
+        (
+            y as f32 / sigma_spatial + GRID_PADDING as f32,
+            x as f32 / sigma_spatial + GRID_PADDING as f32,
+            (value.clamp(min, max) - min) / sigma_range + GRID_PADDING as f32,
+        )
+    };
+
+    let (mut values, mut weights) = image
+        .axis_chunks_iter(Axis(0), GRID_CHUNK_ROWS)
+        .into_par_iter()
+        .enumerate()
+        .map(|(chunk, rows)| {
+            let mut values = Array3::<f32>::zeros(shape);
+            let mut weights = Array3::<f32>::zeros(shape);
+
+            for ((y, x), value) in rows.indexed_iter() {
+                let (grid_y, grid_x, grid_z) = position(chunk * GRID_CHUNK_ROWS + y, x, *value);
+                let cell = [
+                    grid_y.round() as usize,
+                    grid_x.round() as usize,
+                    grid_z.round() as usize,
+                ];
+
+                values[cell] += value;
+                weights[cell] += 1.;
+            }
+
+            (values, weights)
+        })
+        .collect::<Vec<(Array3<f32>, Array3<f32>)>>()
+        .into_iter()
+        .fold(
+            (Array3::<f32>::zeros(shape), Array3::<f32>::zeros(shape)),
+            |(values, weights), (other_values, other_weights)| {
+                (values + other_values, weights + other_weights)
+            },
+        );
+
+    for axis in 0..3 {
+        blur_grid(&mut values, Axis(axis));
+        blur_grid(&mut weights, Axis(axis));
+    }
+
+    let mut filtered = Array2::<f32>::zeros((height, width));
+    Zip::indexed(&mut filtered)
+        .and(image)
+        .par_for_each(|(y, x), filtered, value| {
+            let (grid_y, grid_x, grid_z) = position(y, x, *value);
+            let weight = trilinear(&weights, grid_y, grid_x, grid_z);
+
+            *filtered = if weight > 0. {
+                trilinear(&values, grid_y, grid_x, grid_z) / weight
+            } else {
+                *value
+            };
+        });
+
+    filtered
+}
+
+/// Blur the grid along `axis` with a `[1, 4, 6, 4, 1] / 16` kernel. Cells outside of the grid
+/// are treated as empty.
+#[allow(clippy::cast_possible_wrap)]
+#[allow(clippy::cast_sign_loss)]
+fn blur_grid(grid: &mut Array3<f32>, axis: Axis) {
+    const KERNEL: [f32; 5] = [1. / 16., 4. / 16., 6. / 16., 4. / 16., 1. / 16.];
+
+    Zip::from(grid.lanes_mut(axis)).par_for_each(|mut lane| {
+        let source = lane.to_owned();
+        let length = source.len() as isize;
+
+        for (index, value) in lane.iter_mut().enumerate() {
+            *value = KERNEL
+                .iter()
+                .enumerate()
+                .map(|(offset, factor)| {
+                    let source_index = index as isize + offset as isize - 2;
+
+                    if (0..length).contains(&source_index) {
+                        factor * source[source_index as usize]
+                    } else {
+                        0.
+                    }
+                })
+                .sum();
+        }
+    });
+}
+
+/// Sample the grid at a fractional position with trilinear interpolation.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_sign_loss)]
+fn trilinear(grid: &Array3<f32>, y: f32, x: f32, z: f32) -> f32 {
+    let (y0, x0, z0) = (y.floor() as usize, x.floor() as usize, z.floor() as usize);
+    let (fraction_y, fraction_x, fraction_z) = (y - y0 as f32, x - x0 as f32, z - z0 as f32);
+
+    let mut sum = 0.;
+    for (dy, weight_y) in [(0, 1. - fraction_y), (1, fraction_y)] {
+        for (dx, weight_x) in [(0, 1. - fraction_x), (1, fraction_x)] {
+            for (dz, weight_z) in [(0, 1. - fraction_z), (1, fraction_z)] {
+                sum += grid[[y0 + dy, x0 + dx, z0 + dz]] * weight_y * weight_x * weight_z;
+            }
+        }
+    }
+
+    sum
+}
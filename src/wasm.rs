@@ -0,0 +1,103 @@
+//! JavaScript bindings for running the merge in the browser with `wasm-bindgen`.
+//!
+//! Encoded images are passed as a single byte buffer holding every file one after another, along
+//! with the length of each file, since `wasm-bindgen` cannot pass a list of byte arrays directly.
+
+use crate::output::HDRImage;
+use crate::tonemap::tone_map_reinhard;
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+/// Result of [`merge_images`], holding the linear radiance of the merged image.
+#[wasm_bindgen]
+pub struct MergedImage(HDRImage);
+
+#[wasm_bindgen]
+impl MergedImage {
+    /// Width of the image in pixels
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.0.get_width()
+    }
+
+    /// Height of the image in pixels
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.0.get_height()
+    }
+
+    /// Number of channels per pixel, `3` for RGB images and `1` for grayscale images
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn channels(&self) -> u32 {
+        self.0.get_channels() as u32
+    }
+
+    /// Linear radiance as interleaved channel values, see [`HDRImage::as_slice`]
+    #[must_use]
+    pub fn radiance(&self) -> Vec<f32> {
+        self.0.as_slice().to_vec()
+    }
+
+    /// Tone map the image with [`tone_map_reinhard`] into 8-bit RGBA pixels, ready to be drawn
+    /// with `ImageData`.
+    #[wasm_bindgen(js_name = toRgba)]
+    #[must_use]
+    pub fn to_rgba(&self) -> Vec<u8> {
+        tone_map_reinhard(&self.0).to_rgba8().into_raw()
+    }
+}
+
+/// Decode and merge encoded images. See [`crate::hdr_merge_encoded_images`].
+///
+/// # Arguments
+///
+/// * `data`: Every encoded file, one after another
+/// * `lengths`: Length in bytes of each file in `data`
+/// * `exposures`: Exposure of each image, in seconds
+/// * `gains`: Gain of each image
+///
+/// # Errors
+///
+/// - If `lengths` doesn't add up to the length of `data`
+/// - If an exposure is not a valid duration
+/// - If the merge fails, see [`crate::hdr_merge_encoded_images`]
+#[wasm_bindgen(js_name = mergeImages)]
+pub fn merge_images(
+    data: &[u8],
+    lengths: &[u32],
+    exposures: &[f32],
+    gains: &[f32],
+) -> Result<MergedImage, JsError> {
+    let mut images = Vec::with_capacity(lengths.len());
+    let mut remaining = data;
+
+    for length in lengths {
+        let length = *length as usize;
+        if length > remaining.len() {
+            return Err(JsError::new("File lengths exceed the length of the data"));
+        }
+
+        let (image, rest) = remaining.split_at(length);
+        images.push(image);
+        remaining = rest;
+    }
+
+    if !remaining.is_empty() {
+        return Err(JsError::new("File lengths don't cover the whole data"));
+    }
+
+    let exposures = exposures
+        .iter()
+        .map(|exposure| Duration::try_from_secs_f32(*exposure))
+        .collect::<Result<Vec<Duration>, _>>()
+        .map_err(|error| JsError::new(&format!("Invalid exposure: {error}")))?;
+
+    let merged = crate::hdr_merge_encoded_images(&images, &exposures, gains)
+        .map_err(|error| JsError::new(&error.to_string()))?;
+
+    Ok(MergedImage(merged))
+}